@@ -1,23 +1,47 @@
 use std::sync::Arc;
-use tokio::time::{interval, Duration};
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::time::{interval, Duration, MissedTickBehavior};
 use tracing::{info, warn, error};
 
-use rd_core::domain::ports::{ScreenCapture, Encoder, Transport, ProtocolMessage};
+use rd_core::domain::ports::{ScreenCapture, Encoder, ProtocolMessage};
 
+/// Capture, encode and send frames while `streaming` is true
+///
+/// Sending waits when the outgoing queue is full, so a slow link lowers the
+/// frame rate instead of piling up stale frames. A notification on `keyframes`
+/// sends a keyframe right away instead of at the next tick.
 pub async fn run_capture_loop(
     screen_capture: Arc<tokio::sync::Mutex<dyn ScreenCapture>>,
     encoder: Arc<tokio::sync::Mutex<dyn Encoder>>,
-    transport: Arc<tokio::sync::Mutex<dyn Transport>>,
+    outgoing: mpsc::Sender<ProtocolMessage>,
+    mut streaming: watch::Receiver<bool>,
+    keyframes: Arc<Notify>,
     max_fps: u8,
 ) -> anyhow::Result<()> {
     info!("Starting capture loop at {} FPS", max_fps);
     
-    let frame_interval = Duration::from_millis(1000 / max_fps as u64);
+    let frame_interval = Duration::from_millis(1000 / max_fps.max(1) as u64);
     let mut ticker = interval(frame_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
     let mut sequence = 0u64;
     
     loop {
-        ticker.tick().await;
+        // Idle until a viewer is connected
+        if !*streaming.borrow() {
+            info!("No active session, pausing capture");
+            if streaming.wait_for(|active| *active).await.is_err() {
+                break;
+            }
+            info!("Session active, resuming capture");
+        }
+        
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = keyframes.notified() => {
+                encoder.lock().await.request_keyframe();
+                ticker.reset();
+            }
+        }
         
         // Capture frame
         let frame = match screen_capture.lock().await.capture().await {
@@ -47,8 +71,8 @@ pub async fn run_capture_loop(
             format: frame.format,
         };
         
-        if let Err(e) = transport.lock().await.send(message).await {
-            error!("Failed to send frame: {}", e);
+        if outgoing.send(message).await.is_err() {
+            error!("Failed to send frame: transport closed");
             break;
         }
         
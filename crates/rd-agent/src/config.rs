@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
-use figment::{Figment, providers::{Format, Toml, Env}};
-use anyhow::Result;
+use figment::{Figment, providers::{Format, Serialized, Toml, Env}};
+use anyhow::{Context, Result};
+use rd_platform::InputBackend;
+use rd_transport::{QuicTuning, TrustSettings};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentConfig {
@@ -8,6 +10,21 @@ pub struct AgentConfig {
     pub server_url: String,
     pub max_fps: u8,
     pub encoder_quality: u8,
+    pub input_backend: InputBackend,
+    /// Stream the screen but refuse all input from viewers
+    pub view_only: bool,
+    /// Device token from `rd-cli token issue`, if the server requires one
+    pub auth_token: Option<String>,
+    /// How the server's certificate is checked, the `[agent.tls]` table
+    pub tls: TrustSettings,
+    /// Certificate presented to servers requiring mutual TLS
+    pub client_cert: String,
+    /// Private key of `client_cert`
+    pub client_key: String,
+    /// Without `client_cert`, ask the server for one and wait for approval
+    pub enroll: bool,
+    /// QUIC transport parameters, the `[agent.quic]` table
+    pub quic: QuicTuning,
 }
 
 impl Default for AgentConfig {
@@ -20,18 +37,53 @@ impl Default for AgentConfig {
             server_url: "127.0.0.1:4433".to_string(),
             max_fps: 30,
             encoder_quality: 80,
+            input_backend: InputBackend::Auto,
+            view_only: false,
+            auth_token: None,
+            tls: TrustSettings::default(),
+            client_cert: "config/agent.crt".to_string(),
+            client_key: "config/agent.key".to_string(),
+            enroll: false,
+            quic: QuicTuning::default(),
         }
     }
 }
 
 impl AgentConfig {
-    pub fn load() -> Result<Self> {
-        let config: AgentConfig = Figment::new()
-            .merge(Toml::file("config/agent.toml"))
-            .merge(Env::prefixed("RD_AGENT_"))
-            .extract()
-            .unwrap_or_default();
-        
+    /// Load the `[agent]` table of `path`, overridden by `RD_AGENT_*` variables
+    ///
+    /// A missing file means defaults; a malformed one is an error rather than
+    /// silently falling back, since that would drop settings such as the auth
+    /// token. Invalid `[agent.quic]` settings fail here too, instead of on
+    /// every connection attempt.
+    pub fn load(path: &str) -> Result<Self> {
+        let config: AgentConfig = Figment::from(Serialized::defaults(AgentConfig::default()))
+            .merge(Toml::file(path).nested())
+            .merge(Env::prefixed("RD_AGENT_").global())
+            .select("agent")
+            .extract()?;
+        config.quic.transport_config().context("Invalid [agent.quic] settings")?;
+
         Ok(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_refuses_invalid_quic_settings() {
+        let path = std::env::temp_dir().join(format!("rd-agent-{}.toml", std::process::id()));
+        std::fs::write(&path, "[agent.quic]
+idle_timeout_ms = 1000
+keep_alive_interval_ms = 5000
+").unwrap();
+
+        let loaded = AgentConfig::load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let error = loaded.unwrap_err();
+        assert!(format!("{:#}", error).contains("keep_alive_interval_ms"), "{:#}", error);
+    }
+}
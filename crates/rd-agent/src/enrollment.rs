@@ -0,0 +1,62 @@
+//! Obtaining the client certificate servers with mutual TLS ask for
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+use rd_core::domain::models::{Capabilities, EnrollmentStatus, PeerRole};
+use rd_transport::{ClientIdentity, QuicClient, QuicTransport};
+
+use crate::config::AgentConfig;
+
+/// How often a pending request is asked about again
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Certificate to connect with: the stored one, or, with `enroll` set, one
+/// the server issues once an administrator approved this agent
+///
+/// Without either the agent connects without a certificate.
+pub async fn client_identity(config: &AgentConfig) -> Result<Option<ClientIdentity>> {
+    if Path::new(&config.client_cert).exists() {
+        return Ok(Some(ClientIdentity::load(&config.client_cert, &config.client_key)?));
+    }
+    if !config.enroll {
+        return Ok(None);
+    }
+
+    // The key stays on this machine; only the request naming it is sent
+    let csr = ClientIdentity::signing_request(&config.client_key, &config.device_id)?;
+    let fingerprint = rd_transport::quic::request_fingerprint(&csr)?;
+    let mut announced = false;
+    loop {
+        match request_certificate(config, &csr).await? {
+            EnrollmentStatus::Issued { certificate } => {
+                std::fs::write(&config.client_cert, certificate)?;
+                info!("Enrolled: certificate stored in {}", config.client_cert);
+                return Ok(Some(ClientIdentity::load(&config.client_cert, &config.client_key)?));
+            }
+            EnrollmentStatus::Pending if !announced => {
+                warn!(
+                    "Waiting for approval: run `rd-cli enroll approve {}` on the server and check it lists key {}",
+                    config.device_id, fingerprint
+                );
+                announced = true;
+            }
+            EnrollmentStatus::Pending => {}
+            EnrollmentStatus::Refused { reason } => anyhow::bail!("Enrollment refused: {}", reason),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Send `csr` to the server on a connection of its own
+async fn request_certificate(config: &AgentConfig, csr: &str) -> Result<EnrollmentStatus> {
+    let client = QuicClient::from_settings(&config.tls)?.with_tuning(&config.quic)?;
+    let connection = client.connect(config.server_url.parse()?).await?;
+    let mut transport = QuicTransport::new(connection).await?;
+
+    rd_transport::hello(&mut transport, &config.device_id, PeerRole::Agent, Capabilities::default()).await?;
+    Ok(rd_transport::enroll(&mut transport, csr.to_string()).await?)
+}
@@ -1,29 +1,66 @@
+use std::collections::HashMap;
 use std::sync::Arc;
-use tracing::{info, warn, error};
+use tokio::sync::{mpsc, watch, Notify};
+use tracing::{debug, info, warn};
 
-use rd_core::domain::ports::{InputInjector, Transport, ProtocolMessage};
+use rd_core::domain::models::{Capabilities, SessionId};
+use rd_core::domain::ports::{InputInjector, ProtocolMessage};
 
+use crate::input_tracker::TrackingInputInjector;
+
+/// Apply input and track sessions from the server's messages
+///
+/// `streaming` is kept true while at least one session is active, and
+/// `keyframes` notified when a session's viewer needs one. Input is only
+/// applied if the session it comes from agreed on its kind, so a view-only
+/// agent refuses all of it.
 pub async fn run_input_handler(
     input_injector: Arc<tokio::sync::Mutex<dyn InputInjector>>,
-    transport: Arc<tokio::sync::Mutex<dyn Transport>>,
+    mut incoming: mpsc::Receiver<ProtocolMessage>,
+    streaming: watch::Sender<bool>,
+    keyframes: Arc<Notify>,
 ) -> anyhow::Result<()> {
     info!("Starting input event handler");
     
-    loop {
-        let message = match transport.lock().await.receive().await {
-            Ok(msg) => msg,
-            Err(e) => {
-                error!("Failed to receive message: {}", e);
-                break;
-            }
-        };
-        
+    let mut injector = TrackingInputInjector::new(input_injector);
+    let mut sessions: HashMap<SessionId, Capabilities> = HashMap::new();
+    
+    while let Some(message) = incoming.recv().await {
         match message {
-            ProtocolMessage::InputEvent { event, .. } => {
-                if let Err(e) = input_injector.lock().await.inject(event).await {
+            ProtocolMessage::InputEvent { session_id, event, .. } => {
+                if !sessions.get(&session_id).is_some_and(|capabilities| capabilities.accepts(&event)) {
+                    debug!("Refusing {:?} input: session {} does not accept it", event.kind(), session_id);
+                    continue;
+                }
+                if let Err(e) = injector.inject(session_id, event).await {
                     warn!("Failed to inject input event: {}", e);
                 }
             }
+            ProtocolMessage::SessionCreated { session_id, endpoint, capabilities, .. } => {
+                info!(
+                    "Session {} started by {} ({:?}{})",
+                    session_id,
+                    endpoint,
+                    capabilities.codec(),
+                    if capabilities.is_view_only() { ", view-only" } else { "" },
+                );
+                sessions.insert(session_id, capabilities);
+                streaming.send_replace(true);
+            }
+            ProtocolMessage::KeyframeRequest { session_id } if sessions.contains_key(&session_id) => {
+                debug!("Keyframe requested for session {}", session_id);
+                keyframes.notify_one();
+            }
+            ProtocolMessage::SessionPaused { session_id } if sessions.contains_key(&session_id) => {
+                info!("Session {} paused: its viewer lost the connection", session_id);
+                injector.release(&session_id).await;
+            }
+            ProtocolMessage::SessionEnd { session_id, reason } => {
+                info!("Session {} ended: {}", session_id, reason);
+                sessions.remove(&session_id);
+                streaming.send_replace(!sessions.is_empty());
+                injector.release(&session_id).await;
+            }
             ProtocolMessage::Disconnect => {
                 info!("Received disconnect signal");
                 break;
@@ -34,5 +71,10 @@ pub async fn run_input_handler(
         }
     }
     
+    info!("Connection to server closed");
+    
+    // Never leave the host with keys or buttons held by a viewer that went away
+    injector.release_all().await;
+    
     Ok(())
 }
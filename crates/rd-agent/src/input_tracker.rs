@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use rd_core::domain::{
+    error::InjectionError,
+    models::{InputEvent, KeyCode, MouseButton, SessionId},
+    ports::InputInjector,
+};
+
+/// Input injector wrapper that remembers which keys and buttons each session holds down
+///
+/// A viewer that drops mid-drag or while holding a modifier never sends the
+/// matching release. Call [`release`](Self::release) when its session ends so
+/// the host is not left with stuck input, without touching what the other
+/// sessions hold.
+pub struct TrackingInputInjector {
+    inner: Arc<tokio::sync::Mutex<dyn InputInjector>>,
+    held: HashMap<SessionId, Held>,
+}
+
+/// Keys and buttons one session holds, in press order
+#[derive(Default)]
+struct Held {
+    keys: Vec<KeyCode>,
+    buttons: Vec<MouseButton>,
+}
+
+impl Held {
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.buttons.is_empty()
+    }
+}
+
+impl TrackingInputInjector {
+    pub fn new(inner: Arc<tokio::sync::Mutex<dyn InputInjector>>) -> Self {
+        Self {
+            inner,
+            held: HashMap::new(),
+        }
+    }
+
+    /// Keys `session_id` currently holds, in press order
+    #[cfg(test)]
+    pub fn pressed_keys(&self, session_id: &SessionId) -> &[KeyCode] {
+        self.held.get(session_id).map_or(&[], |held| &held.keys)
+    }
+
+    /// Mouse buttons `session_id` currently holds, in press order
+    #[cfg(test)]
+    pub fn pressed_buttons(&self, session_id: &SessionId) -> &[MouseButton] {
+        self.held.get(session_id).map_or(&[], |held| &held.buttons)
+    }
+
+    /// Inject an event on behalf of `session_id`
+    pub async fn inject(&mut self, session_id: SessionId, event: InputEvent) -> Result<(), InjectionError> {
+        let tracked = match &event {
+            InputEvent::KeyPress { key, pressed } => Some((Pressed::Key(*key), *pressed)),
+            InputEvent::MouseButton { button, pressed } => Some((Pressed::Button(*button), *pressed)),
+            _ => None,
+        };
+
+        self.inner.lock().await.inject(event).await?;
+
+        // Only record state the host actually saw
+        let Some((pressed, down)) = tracked else {
+            return Ok(());
+        };
+        let held = self.held.entry(session_id).or_default();
+        match (pressed, down) {
+            (Pressed::Key(key), true) if !held.keys.contains(&key) => held.keys.push(key),
+            (Pressed::Key(key), false) => held.keys.retain(|k| *k != key),
+            (Pressed::Button(button), true) if !held.buttons.contains(&button) => held.buttons.push(button),
+            (Pressed::Button(button), false) => held.buttons.retain(|b| *b != button),
+            _ => {}
+        }
+        if held.is_empty() {
+            self.held.remove(&session_id);
+        }
+        Ok(())
+    }
+
+    /// Release every button and key `session_id` holds, most recent first
+    ///
+    /// Input another session also holds stays down. Failures are logged and
+    /// do not stop the remaining releases.
+    pub async fn release(&mut self, session_id: &SessionId) {
+        let Some(held) = self.held.remove(session_id) else {
+            return;
+        };
+        let buttons: Vec<_> = held
+            .buttons
+            .into_iter()
+            .filter(|button| !self.held.values().any(|other| other.buttons.contains(button)))
+            .collect();
+        let keys: Vec<_> = held
+            .keys
+            .into_iter()
+            .filter(|key| !self.held.values().any(|other| other.keys.contains(key)))
+            .collect();
+        if keys.is_empty() && buttons.is_empty() {
+            return;
+        }
+        debug!(
+            "Releasing {} stuck key(s) and {} stuck button(s) of session {}",
+            keys.len(),
+            buttons.len(),
+            session_id
+        );
+
+        let mut inner = self.inner.lock().await;
+        for button in buttons.into_iter().rev() {
+            if let Err(e) = inner.inject(InputEvent::MouseButton { button, pressed: false }).await {
+                warn!("Failed to release {:?}: {}", button, e);
+            }
+        }
+        for key in keys.into_iter().rev() {
+            if let Err(e) = inner.inject(InputEvent::KeyPress { key, pressed: false }).await {
+                warn!("Failed to release {:?}: {}", key, e);
+            }
+        }
+    }
+
+    /// Release the input of every session
+    pub async fn release_all(&mut self) {
+        let sessions: Vec<_> = self.held.keys().copied().collect();
+        for session_id in sessions {
+            self.release(&session_id).await;
+        }
+    }
+}
+
+enum Pressed {
+    Key(KeyCode),
+    Button(MouseButton),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    /// Records every injected event, optionally failing all of them
+    #[derive(Default)]
+    struct MockInjector {
+        events: Vec<InputEvent>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl InputInjector for MockInjector {
+        async fn inject(&mut self, event: InputEvent) -> Result<(), InjectionError> {
+            if self.fail {
+                return Err(InjectionError::InjectionFailed("mock".into()));
+            }
+            self.events.push(event);
+            Ok(())
+        }
+    }
+
+    fn tracker() -> (TrackingInputInjector, Arc<tokio::sync::Mutex<MockInjector>>) {
+        let mock = Arc::new(tokio::sync::Mutex::new(MockInjector::default()));
+        (TrackingInputInjector::new(mock.clone()), mock)
+    }
+
+    fn key(key: KeyCode, pressed: bool) -> InputEvent {
+        InputEvent::KeyPress { key, pressed }
+    }
+
+    fn button(button: MouseButton, pressed: bool) -> InputEvent {
+        InputEvent::MouseButton { button, pressed }
+    }
+
+    #[tokio::test]
+    async fn test_release_releases_held_input_in_reverse_order() {
+        let (mut tracker, mock) = tracker();
+        let session = SessionId::new();
+        tracker.inject(session, key(KeyCode::LEFT_CTRL, true)).await.unwrap();
+        tracker.inject(session, key(KeyCode::LEFT_SHIFT, true)).await.unwrap();
+        tracker.inject(session, button(MouseButton::Left, true)).await.unwrap();
+        tracker.inject(session, InputEvent::MouseMove { x: 10, y: 10 }).await.unwrap();
+        assert_eq!(tracker.pressed_keys(&session), &[KeyCode::LEFT_CTRL, KeyCode::LEFT_SHIFT]);
+        assert_eq!(tracker.pressed_buttons(&session), &[MouseButton::Left]);
+
+        mock.lock().await.events.clear();
+        tracker.release(&session).await;
+
+        assert_eq!(
+            mock.lock().await.events,
+            [
+                button(MouseButton::Left, false),
+                key(KeyCode::LEFT_SHIFT, false),
+                key(KeyCode::LEFT_CTRL, false),
+            ]
+        );
+        assert!(tracker.pressed_keys(&session).is_empty());
+        assert!(tracker.pressed_buttons(&session).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_released_input_is_not_released_again() {
+        let (mut tracker, mock) = tracker();
+        let session = SessionId::new();
+        tracker.inject(session, key(KeyCode::A, true)).await.unwrap();
+        // Auto-repeat sends several presses for one release
+        tracker.inject(session, key(KeyCode::A, true)).await.unwrap();
+        tracker.inject(session, key(KeyCode::A, false)).await.unwrap();
+        tracker.inject(session, button(MouseButton::Right, true)).await.unwrap();
+        tracker.inject(session, button(MouseButton::Right, false)).await.unwrap();
+
+        let before = mock.lock().await.events.len();
+        tracker.release_all().await;
+        assert_eq!(mock.lock().await.events.len(), before);
+    }
+
+    #[tokio::test]
+    async fn test_ending_session_leaves_other_sessions_input_held() {
+        let (mut tracker, mock) = tracker();
+        let (ending, staying) = (SessionId::new(), SessionId::new());
+        tracker.inject(ending, key(KeyCode::LEFT_CTRL, true)).await.unwrap();
+        tracker.inject(ending, key(KeyCode::A, true)).await.unwrap();
+        tracker.inject(staying, key(KeyCode::LEFT_CTRL, true)).await.unwrap();
+        tracker.inject(staying, button(MouseButton::Left, true)).await.unwrap();
+
+        mock.lock().await.events.clear();
+        tracker.release(&ending).await;
+
+        // The modifier the other viewer still holds stays down
+        assert_eq!(mock.lock().await.events, [key(KeyCode::A, false)]);
+        assert!(tracker.pressed_keys(&ending).is_empty());
+        assert_eq!(tracker.pressed_keys(&staying), &[KeyCode::LEFT_CTRL]);
+        assert_eq!(tracker.pressed_buttons(&staying), &[MouseButton::Left]);
+
+        mock.lock().await.events.clear();
+        tracker.release(&staying).await;
+        assert_eq!(
+            mock.lock().await.events,
+            [button(MouseButton::Left, false), key(KeyCode::LEFT_CTRL, false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_press_is_not_tracked() {
+        let (mut tracker, mock) = tracker();
+        let session = SessionId::new();
+        mock.lock().await.fail = true;
+        assert!(tracker.inject(session, key(KeyCode::LEFT_ALT, true)).await.is_err());
+        assert!(tracker.pressed_keys(&session).is_empty());
+    }
+}
@@ -1,11 +1,14 @@
 mod config;
 mod capture_loop;
+mod enrollment;
 mod input_handler;
+mod input_tracker;
+mod supervisor;
 
+use std::sync::Arc;
 use clap::Parser;
-use rd_core::domain::ports::Transport;
-use tracing::{info, error};
-use tracing_subscriber;
+use tokio::sync::{watch, Mutex};
+use tracing::{info, warn};
 use anyhow::Result;
 
 #[derive(Parser)]
@@ -20,7 +23,7 @@ struct Cli {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let _cli = Cli::parse();
+    let cli = Cli::parse();
     // Install default crypto provider for rustls
     rustls::crypto::aws_lc_rs::default_provider()
         .install_default()
@@ -32,73 +35,32 @@ async fn main() -> Result<()> {
     info!("Starting Remote Desktop Agent");
     
     // Load configuration
-    let config = config::AgentConfig::load()?;
+    let config = config::AgentConfig::load(&cli.config)?;
     info!("Agent config: device_id={}, server={}", 
         config.device_id, config.server_url);
     
-    // Connect to server
-    info!("Connecting to server: {}", config.server_url);
-    let client = rd_transport::quic::QuicClient::new()?;
-    let connection = client.connect(config.server_url.parse()?).await?;
-    
-    let mut transport = rd_transport::QuicTransport::new(connection).await?;
-    
-    // Send Hello
-    let platform = if cfg!(target_os = "windows") {
-        rd_core::domain::models::Platform::Windows
-    } else if cfg!(target_os = "linux") {
-        rd_core::domain::models::Platform::Linux
-    } else if cfg!(target_os = "macos") {
-        rd_core::domain::models::Platform::MacOS
-    } else {
-        rd_core::domain::models::Platform::Linux
+    // Create screen capture, encoder and input injector
+    let host = supervisor::Host {
+        screen_capture: rd_platform::create_screen_capture()?,
+        encoder: Arc::new(Mutex::new(rd_codec::JpegEncoder::with_quality(config.encoder_quality))),
+        input_injector: rd_platform::create_input_injector(config.input_backend)?,
     };
     
-    transport.send(rd_core::domain::ports::ProtocolMessage::Hello {
-        version: 1,
-        device_id: config.device_id.clone(),
-        platform,
-    }).await?;
-    
-    info!("Connected to server");
-    
-    // Create screen capture and encoder
-    let screen_capture = rd_platform::create_screen_capture()?;
-    let encoder = std::sync::Arc::new(tokio::sync::Mutex::new(
-        rd_codec::JpegEncoder::with_quality(config.encoder_quality)
-    ));
-    
-    // Create input injector
-    let input_injector = rd_platform::create_input_injector()?;
-    
-    // Start capture loop
-    let transport_clone = std::sync::Arc::new(tokio::sync::Mutex::new(transport));
-    let capture_handle = tokio::spawn(capture_loop::run_capture_loop(
-        screen_capture,
-        encoder,
-        transport_clone.clone(),
-        config.max_fps,
-    ));
-    
-    // Start input handler
-    let input_handle = tokio::spawn(input_handler::run_input_handler(
-        input_injector,
-        transport_clone,
-    ));
-    
-    // Wait for tasks
-    tokio::select! {
-        result = capture_handle => {
-            if let Err(e) = result {
-                error!("Capture loop failed: {}", e);
+    // On Ctrl-C, end the connection cleanly: release input held by viewers and
+    // tell the server we are leaving
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => {
+                info!("Shutting down");
+                shutdown_tx.send_replace(true);
             }
-        }
-        result = input_handle => {
-            if let Err(e) = result {
-                error!("Input handler failed: {}", e);
+            Err(e) => {
+                warn!("Cannot listen for Ctrl-C: {}", e);
+                shutdown_tx.closed().await;
             }
         }
-    }
+    });
     
-    Ok(())
+    supervisor::run(&config, &host, shutdown).await
 }
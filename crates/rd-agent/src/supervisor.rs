@@ -0,0 +1,176 @@
+//! Keeping the agent connected to the server
+//!
+//! The agent connects, authenticates, which registers it, and serves sessions
+//! until the connection is lost; then it starts over after a backoff, so it
+//! comes back by itself after a server restart. Only shutting down, or a
+//! refusal that retrying cannot fix, ends it.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::{watch, Mutex, Notify};
+use tokio::task::JoinError;
+use tracing::{debug, error, info, warn};
+
+use rd_core::domain::models::{AuthToken, Capabilities, InputKind, PeerRole};
+use rd_core::domain::ports::{Encoder, InputInjector, ScreenCapture};
+use rd_transport::reconnect::{self, Backoff};
+use rd_transport::{QuicClient, QuicTransport};
+
+use crate::config::AgentConfig;
+use crate::{capture_loop, enrollment, input_handler};
+
+/// Messages queued towards or from the server before the producer waits
+const TRANSPORT_QUEUE_CAPACITY: usize = 8;
+
+/// What the agent drives on this machine, kept across connections
+pub struct Host {
+    pub screen_capture: Arc<Mutex<dyn ScreenCapture>>,
+    pub encoder: Arc<Mutex<dyn Encoder>>,
+    pub input_injector: Arc<Mutex<dyn InputInjector>>,
+}
+
+/// Stay connected to the server until `shutdown` turns true
+///
+/// Fails if the server refuses this agent for a reason retrying cannot fix,
+/// such as an expired token.
+pub async fn run(config: &AgentConfig, host: &Host, mut shutdown: watch::Receiver<bool>) -> Result<()> {
+    let server_addr: SocketAddr = config.server_url.parse()?;
+    let mut backoff = Backoff::default();
+
+    loop {
+        let connected = tokio::select! {
+            result = connect(config, host, server_addr) => result,
+            _ = shutdown.wait_for(|stop| *stop) => return Ok(()),
+        };
+        match connected {
+            Ok((client, transport)) => {
+                backoff.reset();
+                if serve(config, host, transport, &mut shutdown).await {
+                    client.wait_idle().await;
+                    return Ok(());
+                }
+                warn!("Lost connection to server");
+            }
+            Err(e) if reconnect::is_permanent(&e) => return Err(e),
+            Err(e) => warn!("Failed to connect to server: {:#}", e),
+        }
+
+        let delay = backoff.next_delay();
+        info!("Reconnecting in {:.1}s", delay.as_secs_f64());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.wait_for(|stop| *stop) => return Ok(()),
+        }
+    }
+}
+
+/// Connect, agree on a protocol version and authenticate
+async fn connect(config: &AgentConfig, host: &Host, server_addr: SocketAddr) -> Result<(QuicClient, QuicTransport)> {
+    let mut client = QuicClient::from_settings(&config.tls)?.with_tuning(&config.quic)?;
+    if let Some(identity) = enrollment::client_identity(config).await? {
+        client = client.with_client_identity(identity);
+    }
+    info!("Connecting to server: {}", config.server_url);
+    let connection = client.connect(server_addr).await?;
+    let mut transport = QuicTransport::new(connection).await?;
+
+    // Displays may have changed while disconnected
+    let capabilities = announced_capabilities(config, host).await;
+    info!("Capabilities: {:?}", capabilities);
+
+    // Introduce ourselves and agree on a protocol version
+    let version = rd_transport::hello(&mut transport, &config.device_id, PeerRole::Agent, capabilities).await?;
+
+    rd_transport::authenticate(
+        &mut transport,
+        AuthToken::new(config.auth_token.clone().unwrap_or_default(), &config.device_id),
+    )
+    .await?;
+
+    info!("Connected to server (protocol v{})", version);
+    Ok((client, transport))
+}
+
+/// Capture and apply input for the server's sessions until the connection is
+/// lost or `shutdown` turns true; returns whether it was shut down
+async fn serve(
+    config: &AgentConfig,
+    host: &Host,
+    transport: QuicTransport,
+    shutdown: &mut watch::Receiver<bool>,
+) -> bool {
+    // Run the transport on its own task so capture and input never wait on each other
+    let pump = rd_transport::spawn_pump(transport, TRANSPORT_QUEUE_CAPACITY);
+    let (streaming_tx, streaming_rx) = watch::channel(false);
+    let keyframes = Arc::new(Notify::new());
+
+    let mut capture = tokio::spawn(capture_loop::run_capture_loop(
+        host.screen_capture.clone(),
+        host.encoder.clone(),
+        pump.outgoing,
+        streaming_rx,
+        keyframes.clone(),
+        config.max_fps,
+    ));
+    let mut input = tokio::spawn(input_handler::run_input_handler(
+        host.input_injector.clone(),
+        pump.incoming,
+        streaming_tx,
+        keyframes,
+    ));
+
+    let mut input_done = false;
+    let stopped = tokio::select! {
+        result = &mut capture => {
+            report("Capture loop", result);
+            false
+        }
+        result = &mut input => {
+            report("Input handler", result);
+            input_done = true;
+            false
+        }
+        _ = shutdown.wait_for(|stop| *stop) => true,
+    };
+
+    // Without the capture loop's sender the pump says goodbye and closes the
+    // transport, which ends the input handler once it released held input
+    capture.abort();
+    match pump.task.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => debug!("Transport closed: {}", e),
+        Err(e) => error!("Transport pump failed: {}", e),
+    }
+    if !input_done {
+        report("Input handler", input.await);
+    }
+    stopped
+}
+
+fn report(task: &str, result: std::result::Result<Result<()>, JoinError>) {
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("{} failed: {}", task, e),
+        Err(e) => error!("{} failed: {}", task, e),
+    }
+}
+
+/// What this agent offers to viewers
+async fn announced_capabilities(config: &AgentConfig, host: &Host) -> Capabilities {
+    let displays = match host.screen_capture.lock().await.get_displays().await {
+        Ok(displays) => displays,
+        Err(e) => {
+            warn!("Failed to list displays: {}", e);
+            Vec::new()
+        }
+    };
+
+    Capabilities {
+        codecs: vec![host.encoder.lock().await.config().codec],
+        displays,
+        input: if config.view_only { Vec::new() } else { InputKind::ALL.to_vec() },
+        ..Capabilities::default()
+    }
+}
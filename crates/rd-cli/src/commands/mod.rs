@@ -1,66 +1,205 @@
-use tracing::{info, error};
+use tracing::{info, warn, error};
 use anyhow::Result;
 use std::sync::Arc;
 
-use rd_transport::{QuicClient, QuicTransport};
-use rd_client::RemoteSession;
-use rd_core::domain::ports::{Transport, ProtocolMessage};
+use rd_transport::reconnect::{self, Backoff};
+use rd_transport::{EnrollmentQueue, QuicClient, QuicTransport, TrustSettings};
+use rd_client::{RemoteSession, Resumption};
+use rd_core::application::{TokenAuthenticator, TokenClaims};
+use rd_core::domain::error::{ApplicationError, AuthError, DomainError};
+use rd_core::domain::models::{AgentInfo, AuthToken, Capabilities};
+use rd_core::domain::ports::{Authenticator, Transport, ProtocolMessage};
 
-pub async fn list_agents(server: &str) -> Result<()> {
+/// Connect to the server
+async fn open_transport(server: &str, tls: &TrustSettings) -> Result<Arc<tokio::sync::Mutex<QuicTransport>>> {
+    let client = QuicClient::from_settings(tls)?;
+    let connection = client.connect(server.parse()?).await?;
+    let transport = QuicTransport::new(connection).await?;
+    
+    Ok(Arc::new(tokio::sync::Mutex::new(transport)))
+}
+
+/// Connect to the server and introduce ourselves, with `token` if given
+async fn open_session(server: &str, tls: &TrustSettings, token: Option<String>) -> Result<RemoteSession> {
+    let transport = open_transport(server, tls).await?;
+    let session = match token {
+        Some(token) => RemoteSession::with_token(transport, token).await?,
+        None => RemoteSession::new(transport).await?,
+    };
+    
+    Ok(session)
+}
+
+pub async fn list_agents(server: &str, tls: &TrustSettings, token: Option<String>, json: bool) -> Result<()> {
     info!("Listing agents from server: {}", server);
     
-    // TODO: Implement agent list retrieval
-    println!("Agent list feature not yet implemented");
+    let mut session = open_session(server, tls, token).await?;
+    let agents = session.list_agents().await?;
+    session.disconnect().await?;
+    
+    if json {
+        println!("{}", serde_json::to_string_pretty(&agents)?);
+    } else if agents.is_empty() {
+        println!("No agents registered");
+    } else {
+        print_agent_table(&agents);
+    }
     
     Ok(())
 }
 
-pub async fn connect_to_agent(agent_id: &str, server: &str, max_frames: usize) -> Result<()> {
-    info!("Connecting to agent {} via server {}", agent_id, server);
+fn print_agent_table(agents: &[AgentInfo]) {
+    let rows: Vec<[String; 5]> = agents
+        .iter()
+        .map(|agent| {
+            [
+                agent.device_id.clone(),
+                format!("{:?}", agent.platform),
+                if agent.online { "online" } else { "offline" }.to_string(),
+                capability_names(&agent.capabilities).join(","),
+                agent.last_seen.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            ]
+        })
+        .collect();
     
-    // Connect to server
-    let client = QuicClient::new()?;
-    let connection = client.connect(server.parse()?).await?;
-    let transport = QuicTransport::new(connection).await?;
+    let header = ["DEVICE ID", "PLATFORM", "STATUS", "CAPABILITIES", "LAST SEEN"];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
     
-    // Create remote session
-    let transport = Arc::new(tokio::sync::Mutex::new(transport));
-    let mut session = RemoteSession::new(transport).await?;
+    let print_row = |cells: [&str; 5]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
     
-    // Connect to agent
-    let session_id = session.connect(agent_id.to_string()).await?;
-    info!("Connected with session ID: {}", session_id);
+    print_row(header);
+    for row in &rows {
+        print_row(row.each_ref().map(String::as_str));
+    }
+}
+
+fn capability_names(capabilities: &Capabilities) -> Vec<String> {
+    let mut names: Vec<String> = capabilities
+        .codecs
+        .iter()
+        .map(|codec| format!("{:?}", codec).to_lowercase())
+        .collect();
+    if !capabilities.displays.is_empty() {
+        names.push(format!("{}-display", capabilities.displays.len()));
+    }
+    names.push(if capabilities.is_view_only() { "view-only" } else { "input" }.to_string());
+    for (name, supported) in [
+        ("audio", capabilities.audio),
+        ("files", capabilities.file_transfer),
+        ("clipboard", capabilities.clipboard),
+    ] {
+        if supported {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// Print frames of `agent_id` until `max_frames` arrived, the session ends or
+/// Ctrl-C, resuming the session whenever the connection is lost, or starting a
+/// new one if the server ended it meanwhile
+pub async fn connect_to_agent(agent_id: &str, server: &str, tls: &TrustSettings, token: Option<String>, max_frames: usize) -> Result<()> {
+    info!("Connecting to agent {} via server {}", agent_id, server);
     
-    // Receive frames
-    println!("Receiving frames (max: {})...", max_frames);
+    let mut interrupted = std::pin::pin!(tokio::signal::ctrl_c());
+    let mut backoff = Backoff::default();
+    let mut reconnecting = false;
+    let mut resumption: Option<Resumption> = None;
     let mut count = 0;
     
-    while count < max_frames {
-        if let Some(frame) = session.receive_frame().await {
-            count += 1;
-            println!(
-                "Frame {}: {}x{}, {} bytes, seq={}",
-                count,
-                frame.width,
-                frame.height,
-                frame.data.len(),
-                frame.sequence
-            );
-        } else {
-            break;
+    println!("Receiving frames (max: {})...", max_frames);
+    loop {
+        // Connect to server and agent
+        let resuming = resumption.clone();
+        let attempt = async {
+            if let Some(resuming) = resuming {
+                let session_id = resuming.session_id();
+                match RemoteSession::resume(open_transport(server, tls).await?, resuming).await {
+                    Ok(session) => return anyhow::Ok((session, session_id)),
+                    Err(ApplicationError::Domain(DomainError::SessionNotFound(_))) => {
+                        info!("Session {} ended meanwhile, requesting a new one", session_id);
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            let mut session = open_session(server, tls, token.clone()).await?;
+            let session_id = session.connect(agent_id.to_string()).await?;
+            anyhow::Ok((session, session_id))
+        };
+        let attempt = tokio::select! {
+            result = attempt => result,
+            _ = &mut interrupted => return Ok(()),
+        };
+        
+        match attempt {
+            Ok((mut session, session_id)) => {
+                info!("Connected with session ID: {}", session_id);
+                backoff.reset();
+                reconnecting = true;
+                resumption = session.resumption();
+                
+                // Receive frames
+                let mut stopped = false;
+                while count < max_frames {
+                    tokio::select! {
+                        frame = session.receive_frame() => match frame {
+                            Some(frame) => {
+                                count += 1;
+                                println!(
+                                    "Frame {}: {}x{}, {} bytes, seq={}",
+                                    count,
+                                    frame.width,
+                                    frame.height,
+                                    frame.data.len(),
+                                    frame.sequence
+                                );
+                            }
+                            None => break,
+                        },
+                        _ = &mut interrupted => {
+                            stopped = true;
+                            break;
+                        }
+                    }
+                }
+                
+                if stopped || count >= max_frames || session.is_connected() {
+                    // Disconnect
+                    session.disconnect().await?;
+                    return Ok(());
+                }
+                warn!("Lost connection to server");
+            }
+            // Failing to connect at all is reported as is, the agent id may be wrong
+            Err(e) if !reconnecting || reconnect::is_permanent(&e) => return Err(e),
+            Err(e) => warn!("Failed to reconnect: {:#}", e),
+        }
+        
+        let delay = backoff.next_delay();
+        info!("Reconnecting in {:.1}s", delay.as_secs_f64());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = &mut interrupted => return Ok(()),
         }
     }
-    
-    // Disconnect
-    session.disconnect().await?;
-    
-    Ok(())
 }
 
-pub async fn debug_transport(server: &str) -> Result<()> {
+pub async fn debug_transport(server: &str, tls: &TrustSettings, token: Option<String>) -> Result<()> {
     info!("Testing QUIC transport to: {}", server);
     
-    let client = QuicClient::new()?;
+    let client = QuicClient::from_settings(tls)?;
     let connection = client.connect(server.parse()?).await?;
     
     println!("✓ QUIC connection established");
@@ -69,13 +208,25 @@ pub async fn debug_transport(server: &str) -> Result<()> {
     let mut transport = QuicTransport::new(connection).await?;
     
     // Send Hello
-    transport.send(ProtocolMessage::Hello {
-        version: 1,
-        device_id: "test-client".to_string(),
-        platform: rd_core::domain::models::Platform::Linux,
-    }).await?;
+    let token = match token {
+        Some(token) => {
+            let claims = TokenClaims::peek(&token).ok_or(AuthError::InvalidToken)?;
+            AuthToken::new(token, claims.device_id)
+        }
+        None => AuthToken::new("", "test-client"),
+    };
+    let version = rd_transport::hello(
+        &mut transport,
+        &token.device_id,
+        rd_core::domain::models::PeerRole::Client,
+        Capabilities::default(),
+    ).await?;
+    
+    println!("✓ Negotiated protocol v{}", version);
+    
+    rd_transport::authenticate(&mut transport, token).await?;
     
-    println!("✓ Sent Hello message");
+    println!("✓ Authenticated");
     
     // Send Heartbeat
     transport.send(ProtocolMessage::Heartbeat { timestamp: 12345 }).await?;
@@ -98,3 +249,60 @@ pub async fn debug_transport(server: &str) -> Result<()> {
     
     Ok(())
 }
+
+pub fn issue_token(device_id: &str, ttl_hours: i64, secret: &str) -> Result<()> {
+    let authenticator = TokenAuthenticator::new(secret.as_bytes());
+    let token = authenticator.issue(device_id, chrono::Duration::hours(ttl_hours))?;
+    
+    if let Some(expires_at) = TokenClaims::peek(&token.token).and_then(|claims| claims.expires_at()) {
+        info!("Issued token for {} expiring {}", device_id, expires_at);
+    }
+    println!("{}", token.token);
+    
+    Ok(())
+}
+
+pub async fn revoke_token(token: &str, secret: &str, revocation_list: &str) -> Result<()> {
+    let claims = TokenClaims::peek(token).ok_or(AuthError::InvalidToken)?;
+    
+    let authenticator = TokenAuthenticator::new(secret.as_bytes())
+        .with_revocation_list(revocation_list);
+    authenticator.revoke_token(&AuthToken::new(token, &claims.device_id)).await?;
+    
+    println!("Revoked token {} for {}", claims.token_id, claims.device_id);
+    
+    Ok(())
+}
+
+pub fn list_enrollments(dir: &str) -> Result<()> {
+    let pending = EnrollmentQueue::new(dir).pending()?;
+    if pending.is_empty() {
+        println!("No pending enrollments");
+        return Ok(());
+    }
+    
+    for request in pending {
+        println!(
+            "{}  {}  {}",
+            request.device_id,
+            request.requested_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            request.fingerprint
+        );
+    }
+    
+    Ok(())
+}
+
+pub fn approve_enrollment(device_id: &str, dir: &str) -> Result<()> {
+    EnrollmentQueue::new(dir).approve(device_id)?;
+    println!("Approved {}: it gets its certificate the next time it asks", device_id);
+    
+    Ok(())
+}
+
+pub fn reject_enrollment(device_id: &str, dir: &str) -> Result<()> {
+    EnrollmentQueue::new(dir).reject(device_id)?;
+    println!("Rejected the enrollment request of {}", device_id);
+    
+    Ok(())
+}
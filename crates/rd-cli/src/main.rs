@@ -1,17 +1,60 @@
 mod commands;
 
-use clap::{Parser, Subcommand};
-use tracing_subscriber;
+use clap::{Args, Parser, Subcommand};
 use anyhow::Result;
+use rd_transport::TrustSettings;
 
 #[derive(Parser)]
 #[command(name = "rd-cli")]
 #[command(about = "Remote Desktop CLI Tool", long_about = None)]
 struct Cli {
+    /// Device token for servers that require authentication
+    #[arg(long, global = true, env = "RD_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+/// How the server's certificate is checked; trusted on first use by default
+#[derive(Args)]
+struct TlsArgs {
+    /// Only trust server certificates issued by the CAs in this PEM file
+    #[arg(long, global = true, conflicts_with_all = ["fingerprint", "insecure"])]
+    ca: Option<String>,
+
+    /// Only trust the server certificate with this SHA-256 fingerprint
+    #[arg(long, global = true, conflicts_with = "insecure")]
+    fingerprint: Option<String>,
+
+    /// Known hosts file for trust on first use [default: ~/.rd/known_hosts]
+    #[arg(long, global = true)]
+    known_hosts: Option<String>,
+
+    /// Name the server certificate is issued for, checked with --ca
+    #[arg(long, global = true)]
+    server_name: Option<String>,
+
+    /// Accept any server certificate (development only)
+    #[arg(long, global = true)]
+    insecure: bool,
+}
+
+impl From<TlsArgs> for TrustSettings {
+    fn from(args: TlsArgs) -> Self {
+        TrustSettings {
+            ca: args.ca,
+            fingerprint: args.fingerprint,
+            known_hosts: args.known_hosts,
+            server_name: args.server_name,
+            insecure: args.insecure,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// List available agents
@@ -19,6 +62,10 @@ enum Commands {
         /// Server address
         #[arg(short, long, default_value = "127.0.0.1:4433")]
         server: String,
+
+        /// Print the list as JSON instead of a table
+        #[arg(long)]
+        json: bool,
     },
     
     /// Connect to an agent
@@ -41,6 +88,71 @@ enum Commands {
         #[arg(short, long, default_value = "127.0.0.1:4433")]
         server: String,
     },
+    
+    /// Manage device tokens
+    Token {
+        #[command(subcommand)]
+        command: TokenCommands,
+    },
+    
+    /// Approve devices asking the server for a client certificate
+    Enroll {
+        /// Enrollment requests the server keeps
+        #[arg(long, global = true, default_value = "data/enrollment")]
+        dir: String,
+        
+        #[command(subcommand)]
+        command: EnrollCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum EnrollCommands {
+    /// List requests waiting for approval, with the fingerprint each device logs
+    List,
+    
+    /// Let a device's pending request be signed
+    Approve {
+        /// Device ID of the request
+        device_id: String,
+    },
+    
+    /// Drop a device's pending request
+    Reject {
+        /// Device ID of the request
+        device_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokenCommands {
+    /// Issue a signed token for a device
+    Issue {
+        /// Device ID the token is issued to
+        device_id: String,
+        
+        /// Hours until the token expires
+        #[arg(long, default_value = "720")]
+        ttl_hours: i64,
+        
+        /// Secret the server signs tokens with
+        #[arg(long, env = "RD_SERVER_AUTH_SECRET", hide_env_values = true)]
+        secret: String,
+    },
+    
+    /// Revoke a previously issued token
+    Revoke {
+        /// Token to revoke
+        token: String,
+        
+        /// Secret the server signs tokens with
+        #[arg(long, env = "RD_SERVER_AUTH_SECRET", hide_env_values = true)]
+        secret: String,
+        
+        /// Revocation list the server reads
+        #[arg(long, default_value = "config/revoked_tokens")]
+        revocation_list: String,
+    },
 }
 
 #[tokio::main]
@@ -54,16 +166,32 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
     
     let cli = Cli::parse();
+    let tls = TrustSettings::from(cli.tls);
     
     match cli.command {
-        Commands::List { server } => {
-            commands::list_agents(&server).await?;
+        Commands::List { server, json } => {
+            commands::list_agents(&server, &tls, cli.token, json).await?;
         }
         Commands::Connect { agent_id, server, frames } => {
-            commands::connect_to_agent(&agent_id, &server, frames).await?;
+            commands::connect_to_agent(&agent_id, &server, &tls, cli.token, frames).await?;
         }
         Commands::Debug { server } => {
-            commands::debug_transport(&server).await?;
+            commands::debug_transport(&server, &tls, cli.token).await?;
+        }
+        Commands::Token { command: TokenCommands::Issue { device_id, ttl_hours, secret } } => {
+            commands::issue_token(&device_id, ttl_hours, &secret)?;
+        }
+        Commands::Token { command: TokenCommands::Revoke { token, secret, revocation_list } } => {
+            commands::revoke_token(&token, &secret, &revocation_list).await?;
+        }
+        Commands::Enroll { dir, command: EnrollCommands::List } => {
+            commands::list_enrollments(&dir)?;
+        }
+        Commands::Enroll { dir, command: EnrollCommands::Approve { device_id } } => {
+            commands::approve_enrollment(&device_id, &dir)?;
+        }
+        Commands::Enroll { dir, command: EnrollCommands::Reject { device_id } } => {
+            commands::reject_enrollment(&device_id, &dir)?;
         }
     }
     
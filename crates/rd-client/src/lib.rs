@@ -1,6 +1,6 @@
 pub mod session;
 
-pub use session::RemoteSession;
+pub use session::{RemoteSession, Resumption};
 
 // Re-export commonly used types
 pub use rd_core::domain::models::*;
@@ -1,6 +1,8 @@
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
-use tracing::{info, warn, error};
+use tokio::task::JoinHandle;
+use tracing::{info, warn, error, debug};
 
 use rd_core::domain::{
     models::*,
@@ -8,121 +10,426 @@ use rd_core::domain::{
     error::*,
 };
 
+use rd_core::application::TokenClaims;
 use rd_codec::jpeg::JpegDecoder;
 
+/// Decoded frames buffered for `receive_frame`; older frames win, newer ones are dropped
+const FRAME_BUFFER_CAPACITY: usize = 2;
+
+/// How long `connect` and `list_agents` wait for the server to answer
+const SESSION_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a client needs to get its session back after losing the connection,
+/// see [`RemoteSession::resume`]
+#[derive(Debug, Clone)]
+pub struct Resumption {
+    token: AuthToken,
+    session_id: SessionId,
+    resume_token: String,
+}
+
+impl Resumption {
+    /// Session to resume
+    pub fn session_id(&self) -> SessionId {
+        self.session_id
+    }
+}
+
 /// Remote session client
 pub struct RemoteSession {
+    device_id: String,
+    /// Token the client authenticated with, presented again to resume
+    token: AuthToken,
+    protocol_version: u32,
     session_id: Option<SessionId>,
-    transport: Arc<tokio::sync::Mutex<dyn Transport>>,
-    decoder: Arc<tokio::sync::Mutex<dyn Decoder>>,
-    frame_receiver: mpsc::UnboundedReceiver<ScreenFrame>,
+    session_capabilities: Option<Capabilities>,
+    resume_token: Option<String>,
+    outgoing: mpsc::UnboundedSender<ProtocolMessage>,
+    control_receiver: mpsc::UnboundedReceiver<ProtocolMessage>,
+    frame_receiver: mpsc::Receiver<ScreenFrame>,
+    io_task: Option<JoinHandle<()>>,
 }
 
 impl RemoteSession {
-    /// Create a new remote session
+    /// Create a new remote session on a server that does not require authentication
+    ///
+    /// Introduces the client to the server and fails with
+    /// [`TransportError::IncompatibleVersion`] if they share no protocol version.
     pub async fn new(transport: Arc<tokio::sync::Mutex<dyn Transport>>) -> std::result::Result<Self, ApplicationError> {
-        let decoder = Arc::new(tokio::sync::Mutex::new(JpegDecoder::new()));
-        let (tx, rx) = mpsc::unbounded_channel();
-        
-        let transport_clone = transport.clone();
-        let decoder_clone = decoder.clone();
-        
-        // Start frame receiver task
-        tokio::spawn(async move {
-            loop {
-                let message = match transport_clone.lock().await.receive().await {
-                    Ok(msg) => msg,
-                    Err(e) => {
-                        error!("Failed to receive message: {}", e);
-                        break;
-                    }
-                };
-                
-                if let ProtocolMessage::ScreenFrame { data, sequence, timestamp, width, height, format } = message {
-                    // Decode frame
-                    match decoder_clone.lock().await.decode(&data).await {
-                        Ok(mut frame) => {
-                            frame.sequence = sequence;
-                            frame.timestamp = timestamp;
-                            
-                            if tx.send(frame).is_err() {
-                                warn!("Frame receiver dropped");
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            error!("Failed to decode frame: {}", e);
-                        }
-                    }
-                }
+        let device_id = format!("client-{}", uuid::Uuid::new_v4().simple());
+        Self::start(transport, AuthToken::new("", device_id)).await
+    }
+
+    /// Create a new remote session, authenticating with a device token
+    ///
+    /// The client connects as the device the token was issued to. A refused
+    /// token fails with the server's [`AuthError`], e.g. [`AuthError::TokenExpired`].
+    pub async fn with_token(
+        transport: Arc<tokio::sync::Mutex<dyn Transport>>,
+        token: String,
+    ) -> std::result::Result<Self, ApplicationError> {
+        let claims = TokenClaims::peek(&token).ok_or(AuthError::InvalidToken)?;
+        Self::start(transport, AuthToken::new(token, claims.device_id)).await
+    }
+
+    /// Get a session back on a new connection after losing the previous one
+    ///
+    /// The client connects as the same device and the server hands it the same
+    /// session, with the agent sending a keyframe to start over from. A session
+    /// the server ended meanwhile fails with [`DomainError::SessionNotFound`].
+    pub async fn resume(
+        transport: Arc<tokio::sync::Mutex<dyn Transport>>,
+        resumption: Resumption,
+    ) -> std::result::Result<Self, ApplicationError> {
+        let Resumption { token, session_id, resume_token } = resumption;
+        info!("Resuming session {}", session_id);
+
+        let mut session = Self::start(transport, token).await?;
+        session.send(ProtocolMessage::SessionResume { session_id, resume_token })?;
+        let resumed = session
+            .await_session(|code| match code {
+                error_codes::SESSION_EXPIRED => Some(DomainError::SessionNotFound(session_id).into()),
+                _ => None,
+            })
+            .await;
+
+        match resumed {
+            Ok(_) => {
+                info!("Session resumed: {}", session_id);
+                Ok(session)
+            }
+            Err(e) => {
+                session.disconnect().await?;
+                Err(e)
             }
-        });
-        
+        }
+    }
+
+    async fn start(
+        transport: Arc<tokio::sync::Mutex<dyn Transport>>,
+        token: AuthToken,
+    ) -> std::result::Result<Self, ApplicationError> {
+        let device_id = token.device_id.clone();
+        let protocol_version = {
+            let mut transport = transport.lock().await;
+            let version = rd_transport::hello(&mut *transport, &device_id, PeerRole::Client, client_capabilities()).await?;
+            rd_transport::authenticate(&mut *transport, token.clone()).await?;
+            version
+        };
+        debug!("Negotiated protocol v{}", protocol_version);
+
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let (control_tx, control_rx) = mpsc::unbounded_channel();
+        let (frame_tx, frame_rx) = mpsc::channel(FRAME_BUFFER_CAPACITY);
+
+        // The I/O task owns the transport so sends never wait behind a pending receive
+        let io_task = tokio::spawn(run_io(transport, outgoing_rx, control_tx, frame_tx));
+
         Ok(Self {
+            device_id,
+            token,
+            protocol_version,
             session_id: None,
-            transport,
-            decoder,
-            frame_receiver: rx,
+            session_capabilities: None,
+            resume_token: None,
+            outgoing,
+            control_receiver: control_rx,
+            frame_receiver: frame_rx,
+            io_task: Some(io_task),
         })
     }
-    
+
+    /// Device id this client identifies itself with
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// Protocol version agreed with the server
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    /// Current session, once `connect` succeeded
+    pub fn session_id(&self) -> Option<SessionId> {
+        self.session_id
+    }
+
+    /// What the current session agreed to use, once `connect` succeeded
+    pub fn session_capabilities(&self) -> Option<&Capabilities> {
+        self.session_capabilities.as_ref()
+    }
+
+    /// What it takes to resume the current session on a new connection, see
+    /// [`RemoteSession::resume`]
+    pub fn resumption(&self) -> Option<Resumption> {
+        Some(Resumption {
+            token: self.token.clone(),
+            session_id: self.session_id?,
+            resume_token: self.resume_token.clone()?,
+        })
+    }
+
+    /// Whether the connection to the server is still up
+    ///
+    /// Tells apart the two reasons `receive_frame` returns `None`: the session
+    /// ended, or the connection was lost.
+    pub fn is_connected(&self) -> bool {
+        !self.frame_receiver.is_closed()
+    }
+
     /// Connect to a remote agent
+    ///
+    /// Waits for the server to create the session; an unknown agent fails with
+    /// [`DomainError::PeerNotFound`] and no answer with [`TransportError::Timeout`].
     pub async fn connect(&mut self, agent_device_id: String) -> std::result::Result<SessionId, ApplicationError> {
         info!("Requesting session with agent: {}", agent_device_id);
-        
+
         // Send session request
-        self.transport
-            .lock()
-            .await
-            .send(ProtocolMessage::SessionRequest {
-                target_device: agent_device_id,
+        self.send(ProtocolMessage::SessionRequest {
+            target_device: agent_device_id.clone(),
+        })?;
+
+        // Wait for session created response
+        let session_id = self
+            .await_session(|code| match code {
+                error_codes::AGENT_NOT_FOUND => Some(DomainError::PeerNotFound(PeerId::new(agent_device_id)).into()),
+                _ => None,
             })
             .await?;
-        
-        // Wait for session created response
-        // TODO: Implement proper session negotiation
-        
-        let session_id = SessionId::new();
-        self.session_id = Some(session_id);
-        
         info!("Session created: {}", session_id);
-        
         Ok(session_id)
     }
-    
+
+    /// Wait for the server to answer a session request or resumption
+    ///
+    /// A refusal fails with the error `refused` maps its code to, if any, or
+    /// else with a protocol error.
+    async fn await_session(
+        &mut self,
+        refused: impl FnOnce(u32) -> Option<ApplicationError>,
+    ) -> std::result::Result<SessionId, ApplicationError> {
+        let reply = tokio::time::timeout(SESSION_REQUEST_TIMEOUT, self.next_reply())
+            .await
+            .map_err(|_| TransportError::Timeout)?;
+
+        match reply {
+            Some(ProtocolMessage::SessionCreated { session_id, capabilities, resume_token, .. }) => {
+                self.session_id = Some(session_id);
+                self.session_capabilities = Some(capabilities);
+                self.resume_token = resume_token;
+                Ok(session_id)
+            }
+            Some(ProtocolMessage::Error { code, message }) => {
+                warn!("Session request rejected ({}): {}", code, message);
+                Err(refused(code).unwrap_or_else(|| TransportError::ProtocolError(message).into()))
+            }
+            _ => Err(TransportError::Closed.into()),
+        }
+    }
+
+    /// Ask the server which agents it knows about, online ones first
+    pub async fn list_agents(&mut self) -> std::result::Result<Vec<AgentInfo>, ApplicationError> {
+        self.send(ProtocolMessage::ListAgents)?;
+
+        let reply = tokio::time::timeout(SESSION_REQUEST_TIMEOUT, self.next_reply())
+            .await
+            .map_err(|_| TransportError::Timeout)?;
+
+        match reply {
+            Some(ProtocolMessage::AgentList { agents }) => Ok(agents),
+            Some(ProtocolMessage::Error { message, .. }) => Err(TransportError::ProtocolError(message).into()),
+            _ => Err(TransportError::Closed.into()),
+        }
+    }
+
+    /// Next reply to a request, skipping notices about earlier sessions
+    async fn next_reply(&mut self) -> Option<ProtocolMessage> {
+        loop {
+            match self.control_receiver.recv().await? {
+                ProtocolMessage::SessionEnd { session_id, reason } => {
+                    info!("Session {} ended: {}", session_id, reason);
+                    if self.session_id == Some(session_id) {
+                        self.session_id = None;
+                        self.session_capabilities = None;
+                        self.resume_token = None;
+                    }
+                }
+                reply => return Some(reply),
+            }
+        }
+    }
+
     /// Receive the next frame
+    ///
+    /// Returns `None` once the connection closes or the server ends the session.
     pub async fn receive_frame(&mut self) -> Option<ScreenFrame> {
-        self.frame_receiver.recv().await
+        loop {
+            tokio::select! {
+                frame = self.frame_receiver.recv() => return frame,
+                message = self.control_receiver.recv() => match message {
+                    Some(ProtocolMessage::SessionEnd { session_id, reason }) => {
+                        info!("Session {} ended: {}", session_id, reason);
+                        if self.session_id == Some(session_id) {
+                            self.session_id = None;
+                            self.session_capabilities = None;
+                            self.resume_token = None;
+                            return None;
+                        }
+                    }
+                    Some(_) => {}
+                    None => return self.frame_receiver.recv().await,
+                },
+            }
+        }
     }
-    
+
     /// Send an input event
+    ///
+    /// Fails with [`InjectionError::UnsupportedEvent`] if the session did not agree
+    /// on this kind of input, e.g. because the agent is view-only, and with
+    /// [`DomainError::InvalidState`] outside a session.
     pub async fn send_input(&mut self, event: InputEvent) -> std::result::Result<(), ApplicationError> {
+        let Some(session_id) = self.session_id else {
+            return Err(DomainError::InvalidState("No active session".into()).into());
+        };
+        if let Some(capabilities) = &self.session_capabilities {
+            if !capabilities.accepts(&event) {
+                return Err(InjectionError::UnsupportedEvent.into());
+            }
+        }
+
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_millis() as u64;
-        
-        self.transport
-            .lock()
-            .await
-            .send(ProtocolMessage::InputEvent { timestamp, event })
-            .await?;
-        
-        Ok(())
+
+        self.send(ProtocolMessage::InputEvent { session_id, timestamp, event })
     }
-    
+
+    /// Type Unicode text on the remote host, independent of its keyboard layout
+    pub async fn type_text(&mut self, text: &str) -> std::result::Result<(), ApplicationError> {
+        self.send_input(InputEvent::Text { text: text.to_string() }).await
+    }
+
     /// Disconnect from the session
     pub async fn disconnect(&mut self) -> std::result::Result<(), ApplicationError> {
         info!("Disconnecting session");
-        
-        self.transport
-            .lock()
-            .await
-            .send(ProtocolMessage::Disconnect)
-            .await?;
-        
-        self.transport.lock().await.close().await?;
-        
+
+        // The I/O task closes the transport after sending Disconnect
+        if self.send(ProtocolMessage::Disconnect).is_err() {
+            debug!("Transport already closed");
+        }
+        if let Some(io_task) = self.io_task.take() {
+            io_task
+                .await
+                .map_err(|e| ApplicationError::Internal(format!("I/O task failed: {}", e)))?;
+        }
+        self.session_id = None;
+        self.session_capabilities = None;
+        self.resume_token = None;
+
         Ok(())
     }
+
+    fn send(&self, message: ProtocolMessage) -> std::result::Result<(), ApplicationError> {
+        self.outgoing
+            .send(message)
+            .map_err(|_| ApplicationError::Transport(TransportError::Closed))
+    }
+}
+
+/// What this client supports: it decodes JPEG and sends every kind of input
+fn client_capabilities() -> Capabilities {
+    Capabilities {
+        codecs: vec![JpegDecoder::new().codec_type()],
+        input: InputKind::ALL.to_vec(),
+        ..Capabilities::default()
+    }
+}
+
+/// Pump messages between the transport and the session's channels until either side closes
+async fn run_io(
+    transport: Arc<tokio::sync::Mutex<dyn Transport>>,
+    mut outgoing: mpsc::UnboundedReceiver<ProtocolMessage>,
+    control: mpsc::UnboundedSender<ProtocolMessage>,
+    frames: mpsc::Sender<ScreenFrame>,
+) {
+    let mut decoder = JpegDecoder::new();
+    let mut transport = transport.lock().await;
+    // Frames travel on streams of their own and may overtake each other
+    let mut latest_frame: Option<u64> = None;
+
+    loop {
+        tokio::select! {
+            message = outgoing.recv() => {
+                let Some(message) = message else {
+                    // Session dropped without disconnecting
+                    break;
+                };
+                let disconnect = matches!(message, ProtocolMessage::Disconnect);
+                if let Err(e) = transport.send(message).await {
+                    error!("Failed to send message: {}", e);
+                    break;
+                }
+                if disconnect {
+                    if let Err(e) = transport.close().await {
+                        warn!("Failed to close transport: {}", e);
+                    }
+                    break;
+                }
+            }
+            result = transport.receive() => {
+                let message = match result {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        error!("Failed to receive message: {}", e);
+                        break;
+                    }
+                };
+
+                match message {
+                    ProtocolMessage::ScreenFrame { data, sequence, timestamp, width: _, height: _, format: _ } => {
+                        if latest_frame.is_some_and(|latest| sequence <= latest) {
+                            debug!("Dropped frame {}: a newer one arrived first", sequence);
+                            continue;
+                        }
+                        latest_frame = Some(sequence);
+
+                        // Decode frame
+                        match decoder.decode(&data).await {
+                            Ok(mut frame) => {
+                                frame.sequence = sequence;
+                                frame.timestamp = timestamp;
+
+                                match frames.try_send(frame) {
+                                    Ok(()) => {}
+                                    Err(mpsc::error::TrySendError::Full(_)) => {
+                                        debug!("Dropped frame {}: viewer is behind", sequence);
+                                    }
+                                    Err(mpsc::error::TrySendError::Closed(_)) => {
+                                        warn!("Frame receiver dropped");
+                                        break;
+                                    }
+                                }
+                            }
+                            Err(e) => {
+                                error!("Failed to decode frame: {}", e);
+                            }
+                        }
+                    }
+                    ProtocolMessage::SessionCreated { .. } => {
+                        // The agent numbers frames across all its sessions
+                        latest_frame = None;
+                        let _ = control.send(message);
+                    }
+                    ProtocolMessage::SessionEnd { .. }
+                    | ProtocolMessage::AgentList { .. }
+                    | ProtocolMessage::Error { .. } => {
+                        let _ = control.send(message);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
 }
@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use image::{ImageBuffer, Rgba, codecs::jpeg::JpegEncoder as ImageJpegEncoder, ImageEncoder};
 use std::io::Cursor;
-use tracing::{debug, warn};
+use tracing::debug;
 
 use rd_core::domain::{
     models::*,
@@ -20,9 +20,11 @@ impl JpegEncoder {
     }
     
     pub fn with_quality(quality: u8) -> Self {
-        let mut config = EncoderConfig::default();
-        config.codec = CodecType::Jpeg;
-        config.quality = quality.clamp(1, 100);
+        let config = EncoderConfig {
+            codec: CodecType::Jpeg,
+            quality: quality.clamp(1, 100),
+            ..Default::default()
+        };
         Self { config }
     }
 }
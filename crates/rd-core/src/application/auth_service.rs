@@ -0,0 +1,262 @@
+//! Token authentication
+//!
+//! Device tokens look like `<claims>.<signature>`: base64url JSON claims naming
+//! the device, a token id and the expiry, signed with HMAC-SHA256 under a
+//! secret only the server and whoever issues tokens know. Revoked token ids can
+//! be kept in a file, so a token revoked with `rd-cli` is refused by a running
+//! server on the next connection.
+
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::warn;
+
+use crate::domain::{
+    error::AuthError,
+    models::{AuthToken, PeerId},
+    ports::Authenticator,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a token says about itself; only trustworthy once the signature is checked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenClaims {
+    pub device_id: String,
+    /// Unique id used to revoke this token
+    pub token_id: String,
+    /// Unix timestamps in seconds
+    pub issued_at: i64,
+    pub expires_at: i64,
+}
+
+impl TokenClaims {
+    /// Read the claims of a token without checking its signature
+    ///
+    /// Meant for peers picking the device id to connect as, never for
+    /// authorization.
+    pub fn peek(token: &str) -> Option<TokenClaims> {
+        let (payload, _) = token.split_once('.')?;
+        let json = URL_SAFE_NO_PAD.decode(payload).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp(self.expires_at, 0)
+    }
+}
+
+/// [`Authenticator`] for HMAC-signed, expiring device tokens
+pub struct TokenAuthenticator {
+    secret: Vec<u8>,
+    ttl: Duration,
+    revocation_list: Option<PathBuf>,
+    revoked: RwLock<HashSet<String>>,
+}
+
+impl TokenAuthenticator {
+    /// Lifetime of tokens from [`Authenticator::generate_token`] unless changed
+    pub const DEFAULT_TTL: Duration = Duration::days(30);
+
+    /// Authenticator signing with `secret`, which should be at least 32 random bytes
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            ttl: Self::DEFAULT_TTL,
+            revocation_list: None,
+            revoked: RwLock::new(HashSet::new()),
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Keep revoked token ids in `path`, one per line, shared with other processes
+    pub fn with_revocation_list(mut self, path: impl Into<PathBuf>) -> Self {
+        self.revocation_list = Some(path.into());
+        self
+    }
+
+    /// Issue a token for `device_id` valid for `ttl`
+    pub fn issue(&self, device_id: &str, ttl: Duration) -> Result<AuthToken, AuthError> {
+        self.issue_at(device_id, Utc::now(), ttl)
+    }
+
+    fn issue_at(&self, device_id: &str, issued_at: DateTime<Utc>, ttl: Duration) -> Result<AuthToken, AuthError> {
+        if device_id.is_empty() {
+            return Err(AuthError::TokenGenerationFailed("empty device id".to_string()));
+        }
+
+        let claims = TokenClaims {
+            device_id: device_id.to_string(),
+            token_id: uuid::Uuid::new_v4().simple().to_string(),
+            issued_at: issued_at.timestamp(),
+            expires_at: (issued_at + ttl).timestamp(),
+        };
+        let json = serde_json::to_vec(&claims)
+            .map_err(|e| AuthError::TokenGenerationFailed(e.to_string()))?;
+        let payload = URL_SAFE_NO_PAD.encode(json);
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+
+        Ok(AuthToken::new(format!("{}.{}", payload, signature), device_id))
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    /// Check the signature and decode the claims, without looking at expiry or revocation
+    fn verify(&self, token: &str) -> Result<TokenClaims, AuthError> {
+        let (payload, signature) = token.split_once('.').ok_or(AuthError::InvalidToken)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| AuthError::InvalidToken)?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| AuthError::InvalidToken)?;
+
+        TokenClaims::peek(token).ok_or(AuthError::InvalidToken)
+    }
+
+    fn is_revoked(&self, token_id: &str) -> bool {
+        // Pick up revocations written by other processes since the last check
+        if let Some(path) = &self.revocation_list {
+            match std::fs::read_to_string(path) {
+                Ok(contents) => {
+                    let mut revoked = self.revoked.write().unwrap();
+                    revoked.extend(contents.lines().map(str::trim).filter(|id| !id.is_empty()).map(String::from));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to read revocation list {}: {}", path.display(), e),
+            }
+        }
+
+        self.revoked.read().unwrap().contains(token_id)
+    }
+}
+
+#[async_trait]
+impl Authenticator for TokenAuthenticator {
+    async fn authenticate(&self, token: &AuthToken) -> Result<PeerId, AuthError> {
+        let claims = self.verify(&token.token)?;
+        if claims.device_id != token.device_id || self.is_revoked(&claims.token_id) {
+            return Err(AuthError::InvalidToken);
+        }
+        if claims.expires_at <= Utc::now().timestamp() {
+            return Err(AuthError::TokenExpired);
+        }
+
+        Ok(PeerId::new(claims.device_id))
+    }
+
+    async fn generate_token(&self, device_id: &str) -> Result<AuthToken, AuthError> {
+        self.issue(device_id, self.ttl)
+    }
+
+    async fn revoke_token(&self, token: &AuthToken) -> Result<(), AuthError> {
+        // Only tokens we signed can be revoked, so the list cannot be stuffed
+        let claims = self.verify(&token.token)?;
+
+        if let Some(path) = &self.revocation_list {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", claims.token_id))
+                .map_err(|e| AuthError::AuthenticationFailed(format!(
+                    "Failed to update revocation list {}: {}",
+                    path.display(),
+                    e
+                )))?;
+        }
+
+        self.revoked.write().unwrap().insert(claims.token_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authenticator() -> TokenAuthenticator {
+        TokenAuthenticator::new(b"0123456789abcdef0123456789abcdef".to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_issued_token_authenticates_its_device() {
+        let auth = authenticator();
+        let token = auth.generate_token("host").await.unwrap();
+
+        assert_eq!(auth.authenticate(&token).await.unwrap(), PeerId::new("host"));
+        assert_eq!(TokenClaims::peek(&token.token).unwrap().device_id, "host");
+    }
+
+    #[tokio::test]
+    async fn test_forged_tokens_are_invalid() {
+        let auth = authenticator();
+        let token = auth.generate_token("host").await.unwrap();
+
+        // Signed with another secret
+        let other = TokenAuthenticator::new(b"another secret".to_vec());
+        assert!(matches!(other.authenticate(&token).await, Err(AuthError::InvalidToken)));
+
+        // Claims swapped for another device's
+        let (_, signature) = token.token.split_once('.').unwrap();
+        let stolen = auth.generate_token("laptop").await.unwrap();
+        let (payload, _) = stolen.token.split_once('.').unwrap();
+        let forged = AuthToken::new(format!("{}.{}", payload, signature), "laptop");
+        assert!(matches!(auth.authenticate(&forged).await, Err(AuthError::InvalidToken)));
+
+        // Presented for a device it was not issued to
+        let borrowed = AuthToken::new(token.token.clone(), "laptop");
+        assert!(matches!(auth.authenticate(&borrowed).await, Err(AuthError::InvalidToken)));
+
+        for garbage in ["", "no-dot", "a.b", "..."] {
+            let token = AuthToken::new(garbage, "host");
+            assert!(matches!(auth.authenticate(&token).await, Err(AuthError::InvalidToken)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expired_token() {
+        let auth = authenticator();
+        let token = auth.issue_at("host", Utc::now() - Duration::hours(2), Duration::hours(1)).unwrap();
+
+        assert!(matches!(auth.authenticate(&token).await, Err(AuthError::TokenExpired)));
+    }
+
+    #[tokio::test]
+    async fn test_revocation_is_shared_through_the_list() {
+        let path = std::env::temp_dir().join(format!("rd-revoked-{}", uuid::Uuid::new_v4()));
+        let server = authenticator().with_revocation_list(&path);
+        let admin = authenticator().with_revocation_list(&path);
+
+        let token = admin.generate_token("host").await.unwrap();
+        let other = admin.generate_token("host").await.unwrap();
+        assert!(server.authenticate(&token).await.is_ok());
+
+        admin.revoke_token(&token).await.unwrap();
+        assert!(matches!(server.authenticate(&token).await, Err(AuthError::InvalidToken)));
+        assert!(server.authenticate(&other).await.is_ok());
+
+        // Tokens we did not sign are not added to the list
+        let foreign = TokenAuthenticator::new(b"x".to_vec()).generate_token("host").await.unwrap();
+        assert!(admin.revoke_token(&foreign).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,5 +1,7 @@
+pub mod auth_service;
 pub mod session_manager;
 pub mod stream_controller;
 
+pub use auth_service::{TokenAuthenticator, TokenClaims};
 pub use session_manager::SessionManager;
 pub use stream_controller::StreamController;
@@ -26,10 +26,13 @@ impl SessionManager {
     }
     
     /// Create a new session between client and agent
+    ///
+    /// `capabilities` are what both ends agreed to use, see [`Capabilities::for_session`].
     pub async fn create_session(
         &self,
         client_token: &AuthToken,
         agent_id: PeerId,
+        capabilities: Capabilities,
     ) -> Result<Session> {
         // Authenticate client
         let client_peer = self.authenticator
@@ -43,6 +46,7 @@ impl SessionManager {
             agent: agent_id,
             created_at: Utc::now(),
             status: SessionStatus::Pending,
+            capabilities,
         };
         
         // Store in repository
@@ -86,11 +90,11 @@ impl SessionManager {
         let repo = self.repository.read().await;
         Ok(repo.list_active().await?)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    
-    // TODO: Add tests with mock repository and authenticator
+    /// Active sessions in which `peer` takes part, as client or agent
+    pub async fn sessions_of(&self, peer: &PeerId) -> Result<Vec<Session>> {
+        let mut sessions = self.list_active_sessions().await?;
+        sessions.retain(|session| session.client == *peer || session.agent == *peer);
+        Ok(sessions)
+    }
 }
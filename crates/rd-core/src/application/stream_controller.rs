@@ -1,9 +1,7 @@
 use std::sync::Arc;
-use tokio::sync::mpsc;
 use tracing::{info, warn, error};
 
 use crate::domain::{
-    models::*,
     ports::*,
     error::*,
 };
@@ -127,10 +125,3 @@ impl StreamController {
         Ok(())
     }
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    
-    // TODO: Add tests with mock capture, encoder, transport
-}
@@ -98,6 +98,9 @@ pub enum TransportError {
     #[error("Protocol error: {0}")]
     ProtocolError(String),
     
+    #[error("Incompatible protocol version: {0}")]
+    IncompatibleVersion(String),
+    
     #[error("Serialization error: {0}")]
     SerializationError(String),
     
@@ -120,6 +123,21 @@ pub enum AuthError {
     TokenGenerationFailed(String),
 }
 
+impl AuthError {
+    /// Rebuild the error a server reported in `AuthResponse`
+    ///
+    /// Servers send the error's display text, so the well-known variants survive
+    /// the trip.
+    pub fn from_response(error: Option<String>) -> Self {
+        match error {
+            Some(message) if message == AuthError::InvalidToken.to_string() => AuthError::InvalidToken,
+            Some(message) if message == AuthError::TokenExpired.to_string() => AuthError::TokenExpired,
+            Some(message) => AuthError::AuthenticationFailed(message),
+            None => AuthError::AuthenticationFailed("rejected by server".to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum RepositoryError {
     #[error("Database error: {0}")]
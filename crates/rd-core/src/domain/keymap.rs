@@ -0,0 +1,437 @@
+//! Conversion between [`KeyCode`] and OS-level key identifiers
+//!
+//! Every key known to the protocol has one row in [`KEY_TABLE`] giving its
+//! evdev code, USB HID usage and X11 keysym. Keysyms are the unshifted symbol
+//! on a US layout; for layout-dependent keys without a distinct keysym the
+//! entry is [`NO_SYMBOL`].
+
+use super::models::KeyCode;
+
+/// USB HID Keyboard/Keypad usage page
+pub const HID_PAGE_KEYBOARD: u16 = 0x07;
+
+/// USB HID Consumer usage page (media and browser keys)
+pub const HID_PAGE_CONSUMER: u16 = 0x0C;
+
+/// X11 `NoSymbol`
+pub const NO_SYMBOL: u32 = 0;
+
+/// Combine a HID usage page and usage id into a single 32-bit usage
+pub const fn hid_usage(page: u16, id: u16) -> u32 {
+    ((page as u32) << 16) | id as u32
+}
+
+const fn kb(id: u16) -> u32 {
+    hid_usage(HID_PAGE_KEYBOARD, id)
+}
+
+const fn consumer(id: u16) -> u32 {
+    hid_usage(HID_PAGE_CONSUMER, id)
+}
+
+struct KeyMapping {
+    key: KeyCode,
+    hid: u32,
+    keysym: u32,
+}
+
+const fn map(key: KeyCode, hid: u32, keysym: u32) -> KeyMapping {
+    KeyMapping { key, hid, keysym }
+}
+
+#[rustfmt::skip]
+const KEY_TABLE: &[KeyMapping] = &[
+    // Letters (keysyms are the lowercase symbols)
+    map(KeyCode::A, kb(0x04), 0x0061),
+    map(KeyCode::B, kb(0x05), 0x0062),
+    map(KeyCode::C, kb(0x06), 0x0063),
+    map(KeyCode::D, kb(0x07), 0x0064),
+    map(KeyCode::E, kb(0x08), 0x0065),
+    map(KeyCode::F, kb(0x09), 0x0066),
+    map(KeyCode::G, kb(0x0A), 0x0067),
+    map(KeyCode::H, kb(0x0B), 0x0068),
+    map(KeyCode::I, kb(0x0C), 0x0069),
+    map(KeyCode::J, kb(0x0D), 0x006A),
+    map(KeyCode::K, kb(0x0E), 0x006B),
+    map(KeyCode::L, kb(0x0F), 0x006C),
+    map(KeyCode::M, kb(0x10), 0x006D),
+    map(KeyCode::N, kb(0x11), 0x006E),
+    map(KeyCode::O, kb(0x12), 0x006F),
+    map(KeyCode::P, kb(0x13), 0x0070),
+    map(KeyCode::Q, kb(0x14), 0x0071),
+    map(KeyCode::R, kb(0x15), 0x0072),
+    map(KeyCode::S, kb(0x16), 0x0073),
+    map(KeyCode::T, kb(0x17), 0x0074),
+    map(KeyCode::U, kb(0x18), 0x0075),
+    map(KeyCode::V, kb(0x19), 0x0076),
+    map(KeyCode::W, kb(0x1A), 0x0077),
+    map(KeyCode::X, kb(0x1B), 0x0078),
+    map(KeyCode::Y, kb(0x1C), 0x0079),
+    map(KeyCode::Z, kb(0x1D), 0x007A),
+
+    // Digits
+    map(KeyCode::DIGIT_1, kb(0x1E), 0x0031),
+    map(KeyCode::DIGIT_2, kb(0x1F), 0x0032),
+    map(KeyCode::DIGIT_3, kb(0x20), 0x0033),
+    map(KeyCode::DIGIT_4, kb(0x21), 0x0034),
+    map(KeyCode::DIGIT_5, kb(0x22), 0x0035),
+    map(KeyCode::DIGIT_6, kb(0x23), 0x0036),
+    map(KeyCode::DIGIT_7, kb(0x24), 0x0037),
+    map(KeyCode::DIGIT_8, kb(0x25), 0x0038),
+    map(KeyCode::DIGIT_9, kb(0x26), 0x0039),
+    map(KeyCode::DIGIT_0, kb(0x27), 0x0030),
+
+    // Editing and punctuation
+    map(KeyCode::ENTER,         kb(0x28), 0xFF0D), // Return
+    map(KeyCode::ESCAPE,        kb(0x29), 0xFF1B),
+    map(KeyCode::BACKSPACE,     kb(0x2A), 0xFF08),
+    map(KeyCode::TAB,           kb(0x2B), 0xFF09),
+    map(KeyCode::SPACE,         kb(0x2C), 0x0020),
+    map(KeyCode::MINUS,         kb(0x2D), 0x002D),
+    map(KeyCode::EQUAL,         kb(0x2E), 0x003D),
+    map(KeyCode::LEFT_BRACKET,  kb(0x2F), 0x005B),
+    map(KeyCode::RIGHT_BRACKET, kb(0x30), 0x005D),
+    map(KeyCode::BACKSLASH,     kb(0x31), 0x005C),
+    map(KeyCode::SEMICOLON,     kb(0x33), 0x003B),
+    map(KeyCode::APOSTROPHE,    kb(0x34), 0x0027),
+    map(KeyCode::GRAVE,         kb(0x35), 0x0060),
+    map(KeyCode::COMMA,         kb(0x36), 0x002C),
+    map(KeyCode::PERIOD,        kb(0x37), 0x002E),
+    map(KeyCode::SLASH,         kb(0x38), 0x002F),
+    map(KeyCode::CAPS_LOCK,     kb(0x39), 0xFFE5),
+
+    // Function keys
+    map(KeyCode::F1,  kb(0x3A), 0xFFBE),
+    map(KeyCode::F2,  kb(0x3B), 0xFFBF),
+    map(KeyCode::F3,  kb(0x3C), 0xFFC0),
+    map(KeyCode::F4,  kb(0x3D), 0xFFC1),
+    map(KeyCode::F5,  kb(0x3E), 0xFFC2),
+    map(KeyCode::F6,  kb(0x3F), 0xFFC3),
+    map(KeyCode::F7,  kb(0x40), 0xFFC4),
+    map(KeyCode::F8,  kb(0x41), 0xFFC5),
+    map(KeyCode::F9,  kb(0x42), 0xFFC6),
+    map(KeyCode::F10, kb(0x43), 0xFFC7),
+    map(KeyCode::F11, kb(0x44), 0xFFC8),
+    map(KeyCode::F12, kb(0x45), 0xFFC9),
+    map(KeyCode::F13, kb(0x68), 0xFFCA),
+    map(KeyCode::F14, kb(0x69), 0xFFCB),
+    map(KeyCode::F15, kb(0x6A), 0xFFCC),
+    map(KeyCode::F16, kb(0x6B), 0xFFCD),
+    map(KeyCode::F17, kb(0x6C), 0xFFCE),
+    map(KeyCode::F18, kb(0x6D), 0xFFCF),
+    map(KeyCode::F19, kb(0x6E), 0xFFD0),
+    map(KeyCode::F20, kb(0x6F), 0xFFD1),
+    map(KeyCode::F21, kb(0x70), 0xFFD2),
+    map(KeyCode::F22, kb(0x71), 0xFFD3),
+    map(KeyCode::F23, kb(0x72), 0xFFD4),
+    map(KeyCode::F24, kb(0x73), 0xFFD5),
+
+    // Navigation and system
+    map(KeyCode::PRINT_SCREEN, kb(0x46), 0xFF61), // Print
+    map(KeyCode::SCROLL_LOCK,  kb(0x47), 0xFF14),
+    map(KeyCode::PAUSE,        kb(0x48), 0xFF13),
+    map(KeyCode::INSERT,       kb(0x49), 0xFF63),
+    map(KeyCode::HOME,         kb(0x4A), 0xFF50),
+    map(KeyCode::PAGE_UP,      kb(0x4B), 0xFF55), // Prior
+    map(KeyCode::DELETE,       kb(0x4C), 0xFFFF),
+    map(KeyCode::END,          kb(0x4D), 0xFF57),
+    map(KeyCode::PAGE_DOWN,    kb(0x4E), 0xFF56), // Next
+    map(KeyCode::RIGHT,        kb(0x4F), 0xFF53),
+    map(KeyCode::LEFT,         kb(0x50), 0xFF51),
+    map(KeyCode::DOWN,         kb(0x51), 0xFF54),
+    map(KeyCode::UP,           kb(0x52), 0xFF52),
+    map(KeyCode::MENU,         kb(0x65), 0xFF67),
+    map(KeyCode::POWER,        kb(0x66), 0x1008_FF2A), // XF86PowerOff
+
+    // Keypad
+    map(KeyCode::NUM_LOCK,    kb(0x53), 0xFF7F),
+    map(KeyCode::KP_DIVIDE,   kb(0x54), 0xFFAF),
+    map(KeyCode::KP_MULTIPLY, kb(0x55), 0xFFAA),
+    map(KeyCode::KP_SUBTRACT, kb(0x56), 0xFFAD),
+    map(KeyCode::KP_ADD,      kb(0x57), 0xFFAB),
+    map(KeyCode::KP_ENTER,    kb(0x58), 0xFF8D),
+    map(KeyCode::KP_1,        kb(0x59), 0xFFB1),
+    map(KeyCode::KP_2,        kb(0x5A), 0xFFB2),
+    map(KeyCode::KP_3,        kb(0x5B), 0xFFB3),
+    map(KeyCode::KP_4,        kb(0x5C), 0xFFB4),
+    map(KeyCode::KP_5,        kb(0x5D), 0xFFB5),
+    map(KeyCode::KP_6,        kb(0x5E), 0xFFB6),
+    map(KeyCode::KP_7,        kb(0x5F), 0xFFB7),
+    map(KeyCode::KP_8,        kb(0x60), 0xFFB8),
+    map(KeyCode::KP_9,        kb(0x61), 0xFFB9),
+    map(KeyCode::KP_0,        kb(0x62), 0xFFB0),
+    map(KeyCode::KP_DECIMAL,  kb(0x63), 0xFFAE),
+    map(KeyCode::KP_EQUAL,    kb(0x67), 0xFFBD),
+    map(KeyCode::KP_COMMA,    kb(0x85), 0xFFAC), // KP_Separator
+
+    // International
+    map(KeyCode::INTL_BACKSLASH,    kb(0x64), 0x003C), // less
+    map(KeyCode::INTL_RO,           kb(0x87), NO_SYMBOL),
+    map(KeyCode::KATAKANA_HIRAGANA, kb(0x88), 0xFF27), // Hiragana_Katakana
+    map(KeyCode::INTL_YEN,          kb(0x89), 0x00A5), // yen
+    map(KeyCode::HENKAN,            kb(0x8A), 0xFF23), // Henkan_Mode
+    map(KeyCode::MUHENKAN,          kb(0x8B), 0xFF22),
+    map(KeyCode::KP_JP_COMMA,       kb(0x8C), NO_SYMBOL),
+    map(KeyCode::HANGUL,            kb(0x90), 0xFF31),
+    map(KeyCode::HANJA,             kb(0x91), 0xFF34), // Hangul_Hanja
+    map(KeyCode::KATAKANA,          kb(0x92), 0xFF26),
+    map(KeyCode::HIRAGANA,          kb(0x93), 0xFF25),
+    map(KeyCode::ZENKAKU_HANKAKU,   kb(0x94), 0xFF2A),
+
+    // Modifiers
+    map(KeyCode::LEFT_CTRL,   kb(0xE0), 0xFFE3),
+    map(KeyCode::LEFT_SHIFT,  kb(0xE1), 0xFFE1),
+    map(KeyCode::LEFT_ALT,    kb(0xE2), 0xFFE9),
+    map(KeyCode::LEFT_META,   kb(0xE3), 0xFFEB), // Super_L
+    map(KeyCode::RIGHT_CTRL,  kb(0xE4), 0xFFE4),
+    map(KeyCode::RIGHT_SHIFT, kb(0xE5), 0xFFE2),
+    map(KeyCode::RIGHT_ALT,   kb(0xE6), 0xFFEA),
+    map(KeyCode::RIGHT_META,  kb(0xE7), 0xFFEC), // Super_R
+
+    // Media and browser
+    map(KeyCode::MEDIA_NEXT,        consumer(0x0B5), 0x1008_FF17), // XF86AudioNext
+    map(KeyCode::MEDIA_PREVIOUS,    consumer(0x0B6), 0x1008_FF16), // XF86AudioPrev
+    map(KeyCode::MEDIA_STOP,        consumer(0x0B7), 0x1008_FF15), // XF86AudioStop
+    map(KeyCode::MEDIA_PLAY_PAUSE,  consumer(0x0CD), 0x1008_FF14), // XF86AudioPlay
+    map(KeyCode::MUTE,              consumer(0x0E2), 0x1008_FF12), // XF86AudioMute
+    map(KeyCode::VOLUME_UP,         consumer(0x0E9), 0x1008_FF13), // XF86AudioRaiseVolume
+    map(KeyCode::VOLUME_DOWN,       consumer(0x0EA), 0x1008_FF11), // XF86AudioLowerVolume
+    map(KeyCode::LAUNCH_MAIL,       consumer(0x18A), 0x1008_FF19), // XF86Mail
+    map(KeyCode::LAUNCH_CALCULATOR, consumer(0x192), 0x1008_FF1D), // XF86Calculator
+    map(KeyCode::BROWSER_SEARCH,    consumer(0x221), 0x1008_FF1B), // XF86Search
+    map(KeyCode::BROWSER_HOME,      consumer(0x223), 0x1008_FF18), // XF86HomePage
+    map(KeyCode::BROWSER_BACK,      consumer(0x224), 0x1008_FF26), // XF86Back
+    map(KeyCode::BROWSER_FORWARD,   consumer(0x225), 0x1008_FF27), // XF86Forward
+    map(KeyCode::BROWSER_REFRESH,   consumer(0x227), 0x1008_FF29), // XF86Refresh
+];
+
+/// Additional HID usages reported for keys that already have a primary usage
+#[rustfmt::skip]
+const HID_ALIASES: &[(u32, KeyCode)] = &[
+    (kb(0x32), KeyCode::BACKSLASH), // Non-US # and ~
+    (kb(0x7F), KeyCode::MUTE),
+    (kb(0x80), KeyCode::VOLUME_UP),
+    (kb(0x81), KeyCode::VOLUME_DOWN),
+];
+
+/// Keysyms produced by the same physical key under another level or lock state
+#[rustfmt::skip]
+const KEYSYM_ALIASES: &[(u32, KeyCode)] = &[
+    (0xFE20, KeyCode::TAB),        // ISO_Left_Tab
+    (0xFE03, KeyCode::RIGHT_ALT),  // ISO_Level3_Shift (AltGr)
+    (0xFF7E, KeyCode::RIGHT_ALT),  // Mode_switch
+    (0xFF9E, KeyCode::KP_0),       // KP_Insert
+    (0xFF9C, KeyCode::KP_1),       // KP_End
+    (0xFF99, KeyCode::KP_2),       // KP_Down
+    (0xFF9B, KeyCode::KP_3),       // KP_Next
+    (0xFF96, KeyCode::KP_4),       // KP_Left
+    (0xFF9D, KeyCode::KP_5),       // KP_Begin
+    (0xFF98, KeyCode::KP_6),       // KP_Right
+    (0xFF95, KeyCode::KP_7),       // KP_Home
+    (0xFF97, KeyCode::KP_8),       // KP_Up
+    (0xFF9A, KeyCode::KP_9),       // KP_Prior
+    (0xFF9F, KeyCode::KP_DECIMAL), // KP_Delete
+    (0xFF15, KeyCode::PRINT_SCREEN), // Sys_Req
+    (0xFF6B, KeyCode::PAUSE),      // Break
+];
+
+/// Printable ASCII characters typed with Shift held on a US layout
+#[rustfmt::skip]
+const US_SHIFTED: &[(char, KeyCode)] = &[
+    ('!', KeyCode::DIGIT_1), ('@', KeyCode::DIGIT_2), ('#', KeyCode::DIGIT_3),
+    ('$', KeyCode::DIGIT_4), ('%', KeyCode::DIGIT_5), ('^', KeyCode::DIGIT_6),
+    ('&', KeyCode::DIGIT_7), ('*', KeyCode::DIGIT_8), ('(', KeyCode::DIGIT_9),
+    (')', KeyCode::DIGIT_0), ('_', KeyCode::MINUS), ('+', KeyCode::EQUAL),
+    ('{', KeyCode::LEFT_BRACKET), ('}', KeyCode::RIGHT_BRACKET), ('|', KeyCode::BACKSLASH),
+    (':', KeyCode::SEMICOLON), ('"', KeyCode::APOSTROPHE), ('~', KeyCode::GRAVE),
+    ('<', KeyCode::COMMA), ('>', KeyCode::PERIOD), ('?', KeyCode::SLASH),
+];
+
+/// X11 keysym that produces `c`
+///
+/// Latin-1 characters use their code point, control characters with a key of
+/// their own map to that key's keysym, and everything else uses the Unicode
+/// keysym range (`0x0100_0000 | code point`).
+pub fn char_to_keysym(c: char) -> Option<u32> {
+    match c {
+        '\n' | '\r' => Some(0xFF0D),
+        '\t' => Some(0xFF09),
+        '\u{8}' => Some(0xFF08),
+        '\u{1b}' => Some(0xFF1B),
+        '\u{7f}' => Some(0xFFFF),
+        c if c.is_control() => None,
+        ' '..='~' | '\u{a0}'..='\u{ff}' => Some(c as u32),
+        c => Some(0x0100_0000 | c as u32),
+    }
+}
+
+/// Key and Shift state that type `c` on a US layout, for backends that cannot remap keys
+pub fn us_layout_key(c: char) -> Option<(KeyCode, bool)> {
+    if let Some(&(_, key)) = US_SHIFTED.iter().find(|(ch, _)| *ch == c) {
+        return Some((key, true));
+    }
+    if !c.is_ascii() {
+        return None;
+    }
+    let key = KeyCode::from_x11_keysym(char_to_keysym(c)?)?;
+    Some((key, c.is_ascii_uppercase()))
+}
+
+fn lookup(key: KeyCode) -> Option<&'static KeyMapping> {
+    KEY_TABLE.iter().find(|m| m.key == key)
+}
+
+impl KeyCode {
+    /// All key codes with a known mapping
+    pub fn all() -> impl Iterator<Item = KeyCode> {
+        KEY_TABLE.iter().map(|m| m.key)
+    }
+
+    /// Whether this key code has a known mapping
+    pub fn is_known(self) -> bool {
+        lookup(self).is_some()
+    }
+
+    /// Key code for a Linux evdev `KEY_*` code
+    pub fn from_evdev(code: u16) -> Option<Self> {
+        Some(Self(code as u32)).filter(|key| key.is_known())
+    }
+
+    /// Linux evdev `KEY_*` code
+    pub fn to_evdev(self) -> Option<u16> {
+        lookup(self).map(|m| m.key.0 as u16)
+    }
+
+    /// Key code for a USB HID usage built with [`hid_usage`]
+    pub fn from_hid_usage(usage: u32) -> Option<Self> {
+        KEY_TABLE
+            .iter()
+            .find(|m| m.hid == usage)
+            .map(|m| m.key)
+            .or_else(|| HID_ALIASES.iter().find(|(u, _)| *u == usage).map(|(_, key)| *key))
+    }
+
+    /// USB HID usage (page in the high 16 bits, usage id in the low 16 bits)
+    pub fn to_hid_usage(self) -> Option<u32> {
+        lookup(self).map(|m| m.hid)
+    }
+
+    /// Key code for an X11 keysym
+    ///
+    /// Shifted Latin letters map to the same key as their lowercase keysym.
+    pub fn from_x11_keysym(keysym: u32) -> Option<Self> {
+        if keysym == NO_SYMBOL {
+            return None;
+        }
+        let keysym = match keysym {
+            0x0041..=0x005A => keysym + 0x20,
+            _ => keysym,
+        };
+        KEY_TABLE
+            .iter()
+            .find(|m| m.keysym == keysym)
+            .map(|m| m.key)
+            .or_else(|| KEYSYM_ALIASES.iter().find(|(k, _)| *k == keysym).map(|(_, key)| *key))
+    }
+
+    /// Unshifted X11 keysym on a US layout
+    pub fn to_x11_keysym(self) -> Option<u32> {
+        lookup(self).map(|m| m.keysym).filter(|&keysym| keysym != NO_SYMBOL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_table_columns_are_unique() {
+        let mut keys = HashSet::new();
+        let mut usages = HashSet::new();
+        let mut keysyms = HashSet::new();
+        for m in KEY_TABLE {
+            assert!(keys.insert(m.key), "duplicate key {:?}", m.key);
+            assert!(usages.insert(m.hid), "duplicate HID usage {:#x}", m.hid);
+            if m.keysym != NO_SYMBOL {
+                assert!(keysyms.insert(m.keysym), "duplicate keysym {:#x}", m.keysym);
+            }
+        }
+        for (usage, _) in HID_ALIASES {
+            assert!(!usages.contains(usage), "alias shadows HID usage {:#x}", usage);
+        }
+        for (keysym, _) in KEYSYM_ALIASES {
+            assert!(!keysyms.contains(keysym), "alias shadows keysym {:#x}", keysym);
+        }
+    }
+
+    #[test]
+    fn test_round_trips() {
+        for key in KeyCode::all() {
+            let evdev = key.to_evdev().unwrap();
+            assert_eq!(KeyCode::from_evdev(evdev), Some(key));
+
+            let usage = key.to_hid_usage().unwrap();
+            assert_eq!(KeyCode::from_hid_usage(usage), Some(key));
+
+            if let Some(keysym) = key.to_x11_keysym() {
+                assert_eq!(KeyCode::from_x11_keysym(keysym), Some(key), "keysym {:#x}", keysym);
+            }
+        }
+    }
+
+    #[test]
+    fn test_known_values() {
+        // evdev values are the constants themselves
+        assert_eq!(KeyCode::ESCAPE.to_evdev(), Some(1));
+        assert_eq!(KeyCode::A.to_evdev(), Some(30));
+        assert_eq!(KeyCode::F24.to_evdev(), Some(194));
+
+        assert_eq!(KeyCode::A.to_hid_usage(), Some(hid_usage(HID_PAGE_KEYBOARD, 0x04)));
+        assert_eq!(KeyCode::RIGHT_META.to_hid_usage(), Some(hid_usage(HID_PAGE_KEYBOARD, 0xE7)));
+        assert_eq!(KeyCode::VOLUME_UP.to_hid_usage(), Some(hid_usage(HID_PAGE_CONSUMER, 0xE9)));
+
+        assert_eq!(KeyCode::ENTER.to_x11_keysym(), Some(0xFF0D));
+        assert_eq!(KeyCode::DIGIT_0.to_x11_keysym(), Some(0x30));
+        assert_eq!(KeyCode::LEFT_SHIFT.to_x11_keysym(), Some(0xFFE1));
+    }
+
+    #[test]
+    fn test_keysym_aliases() {
+        // XK_Q and XK_q are the same key
+        assert_eq!(KeyCode::from_x11_keysym(0x51), Some(KeyCode::Q));
+        assert_eq!(KeyCode::from_x11_keysym(0x71), Some(KeyCode::Q));
+        assert_eq!(KeyCode::from_x11_keysym(0xFF95), Some(KeyCode::KP_7));
+        assert_eq!(KeyCode::from_x11_keysym(0xFE03), Some(KeyCode::RIGHT_ALT));
+        assert_eq!(KeyCode::from_hid_usage(hid_usage(HID_PAGE_KEYBOARD, 0x7F)), Some(KeyCode::MUTE));
+    }
+
+    #[test]
+    fn test_char_to_keysym() {
+        assert_eq!(char_to_keysym('a'), Some(0x61));
+        assert_eq!(char_to_keysym('\u{e9}'), Some(0xE9)); // é is Latin-1
+        assert_eq!(char_to_keysym('\u{20ac}'), Some(0x0100_20AC)); // €
+        assert_eq!(char_to_keysym('\n'), Some(0xFF0D));
+        assert_eq!(char_to_keysym('\u{7}'), None);
+    }
+
+    #[test]
+    fn test_us_layout_key() {
+        assert_eq!(us_layout_key('a'), Some((KeyCode::A, false)));
+        assert_eq!(us_layout_key('A'), Some((KeyCode::A, true)));
+        assert_eq!(us_layout_key('<'), Some((KeyCode::COMMA, true)));
+        assert_eq!(us_layout_key(' '), Some((KeyCode::SPACE, false)));
+        assert_eq!(us_layout_key('\n'), Some((KeyCode::ENTER, false)));
+        assert_eq!(us_layout_key('\u{e9}'), None);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        assert_eq!(KeyCode(0).to_evdev(), None);
+        assert_eq!(KeyCode(0x1000).to_hid_usage(), None);
+        assert_eq!(KeyCode::from_evdev(0x2ff), None);
+        assert_eq!(KeyCode::from_x11_keysym(NO_SYMBOL), None);
+        assert_eq!(KeyCode::INTL_RO.to_x11_keysym(), None);
+        assert_eq!(KeyCode::from_hid_usage(hid_usage(HID_PAGE_KEYBOARD, 0x00)), None);
+    }
+}
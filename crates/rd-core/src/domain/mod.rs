@@ -1,3 +1,5 @@
+pub mod keymap;
 pub mod models;
 pub mod ports;
 pub mod error;
+pub mod version;
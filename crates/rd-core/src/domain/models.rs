@@ -53,6 +53,8 @@ pub struct Session {
     pub agent: PeerId,
     pub created_at: DateTime<Utc>,
     pub status: SessionStatus,
+    /// What both ends agreed to use
+    pub capabilities: Capabilities,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -77,6 +79,30 @@ pub struct Peer {
     pub last_seen: DateTime<Utc>,
 }
 
+/// An agent known to the server, as listed to clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentInfo {
+    pub device_id: String,
+    pub platform: Platform,
+    pub capabilities: Capabilities,
+    /// Whether the agent is connected right now
+    pub online: bool,
+    /// When the server last heard from the agent
+    pub last_seen: DateTime<Utc>,
+}
+
+impl AgentInfo {
+    pub fn from_peer(peer: &Peer, online: bool) -> Self {
+        Self {
+            device_id: peer.device_id.clone(),
+            platform: peer.platform,
+            capabilities: peer.capabilities.clone(),
+            online,
+            last_seen: peer.last_seen,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Platform {
     Windows,
@@ -86,26 +112,126 @@ pub enum Platform {
     IOS,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Platform {
+    /// Platform this binary was built for
+    pub fn current() -> Self {
+        if cfg!(target_os = "windows") {
+            Platform::Windows
+        } else if cfg!(target_os = "macos") {
+            Platform::MacOS
+        } else if cfg!(target_os = "android") {
+            Platform::Android
+        } else if cfg!(target_os = "ios") {
+            Platform::IOS
+        } else {
+            Platform::Linux
+        }
+    }
+}
+
+/// Side a peer plays when it connects to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PeerRole {
+    /// Host sharing its screen and accepting input
+    Agent,
+    /// Viewer controlling an agent
+    Client,
+}
+
+/// Encoding of protocol messages on a connection, agreed during the handshake
+///
+/// Everything up to and including `WireFormatSelected` is bincode, so peers
+/// can always get that far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WireFormat {
+    Bincode,
+    Postcard,
+    /// Postcard, with messages other than screen frames compressed by zstd
+    PostcardZstd,
+}
+
+impl WireFormat {
+    /// Every format, in the server's order of preference
+    pub const ALL: [WireFormat; 3] = [WireFormat::PostcardZstd, WireFormat::Postcard, WireFormat::Bincode];
+
+    /// The server's preferred format among those a peer `offered`; bincode if
+    /// it offered none the server knows
+    pub fn negotiate(offered: &[WireFormat]) -> WireFormat {
+        WireFormat::ALL
+            .into_iter()
+            .find(|format| offered.contains(format))
+            .unwrap_or(WireFormat::Bincode)
+    }
+}
+
+/// What a peer supports, announced right after the version handshake
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Capabilities {
-    pub screen_capture: bool,
-    pub input_injection: bool,
-    pub audio_capture: bool,
+    /// Codecs the peer can encode (agent) or decode (client), preferred first
+    pub codecs: Vec<CodecType>,
+    /// Displays an agent can stream; empty for clients
+    pub displays: Vec<DisplayInfo>,
+    pub audio: bool,
     pub file_transfer: bool,
+    pub clipboard: bool,
+    /// Input an agent accepts or a client sends; empty for a view-only agent
+    pub input: Vec<InputKind>,
+}
+
+impl Capabilities {
+    /// What a session between `agent` and `client` may use
+    ///
+    /// Codecs keep the agent's preference order, displays are the agent's, and
+    /// everything else must be supported by both ends.
+    pub fn for_session(agent: &Capabilities, client: &Capabilities) -> Capabilities {
+        Capabilities {
+            codecs: agent
+                .codecs
+                .iter()
+                .copied()
+                .filter(|codec| client.codecs.contains(codec))
+                .collect(),
+            displays: agent.displays.clone(),
+            audio: agent.audio && client.audio,
+            file_transfer: agent.file_transfer && client.file_transfer,
+            clipboard: agent.clipboard && client.clipboard,
+            input: agent
+                .input
+                .iter()
+                .copied()
+                .filter(|kind| client.input.contains(kind))
+                .collect(),
+        }
+    }
+
+    /// Codec a session with these capabilities streams in
+    pub fn codec(&self) -> Option<CodecType> {
+        self.codecs.first().copied()
+    }
+
+    pub fn accepts(&self, event: &InputEvent) -> bool {
+        self.input.contains(&event.kind())
+    }
+
+    pub fn is_view_only(&self) -> bool {
+        self.input.is_empty()
+    }
 }
 
 impl Default for Capabilities {
     fn default() -> Self {
         Self {
-            screen_capture: true,
-            input_injection: true,
-            audio_capture: false,
+            codecs: vec![CodecType::Jpeg],
+            displays: Vec::new(),
+            audio: false,
             file_transfer: false,
+            clipboard: false,
+            input: InputKind::ALL.to_vec(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DisplayInfo {
     pub id: u32,
     pub name: String,
@@ -150,12 +276,41 @@ pub struct InputEventData {
     pub event: InputEvent,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum InputEvent {
     MouseMove { x: i32, y: i32 },
     MouseButton { button: MouseButton, pressed: bool },
     MouseScroll { delta_x: i32, delta_y: i32 },
     KeyPress { key: KeyCode, pressed: bool },
+    /// Unicode text typed independently of the host keyboard layout
+    Text { text: String },
+}
+
+impl InputEvent {
+    pub fn kind(&self) -> InputKind {
+        match self {
+            InputEvent::MouseMove { .. }
+            | InputEvent::MouseButton { .. }
+            | InputEvent::MouseScroll { .. } => InputKind::Pointer,
+            InputEvent::KeyPress { .. } => InputKind::Keyboard,
+            InputEvent::Text { .. } => InputKind::Text,
+        }
+    }
+}
+
+/// Classes of input a peer can send or accept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputKind {
+    /// Mouse movement, buttons and scrolling
+    Pointer,
+    /// Physical key presses
+    Keyboard,
+    /// Layout-independent Unicode text
+    Text,
+}
+
+impl InputKind {
+    pub const ALL: [InputKind; 3] = [InputKind::Pointer, InputKind::Keyboard, InputKind::Text];
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -167,22 +322,179 @@ pub enum MouseButton {
     X2,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// Platform-agnostic key code
+///
+/// Values are Linux evdev codes for the physical key position on a US layout.
+/// See [`crate::domain::keymap`] for conversion to X11 keysyms, evdev codes and
+/// USB HID usages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct KeyCode(pub u32);
 
 impl KeyCode {
-    // Common key codes (platform-agnostic, will be mapped to OS-specific)
-    pub const ESCAPE: Self = Self(0x01);
-    pub const ENTER: Self = Self(0x1C);
-    pub const SPACE: Self = Self(0x39);
-    pub const BACKSPACE: Self = Self(0x0E);
-    pub const TAB: Self = Self(0x0F);
-    
+    // Editing and whitespace
+    pub const ESCAPE: Self = Self(1);
+    pub const ENTER: Self = Self(28);
+    pub const SPACE: Self = Self(57);
+    pub const BACKSPACE: Self = Self(14);
+    pub const TAB: Self = Self(15);
+
     // Letters
-    pub const A: Self = Self(0x1E);
-    pub const B: Self = Self(0x30);
-    pub const C: Self = Self(0x2E);
-    // ... (add more as needed)
+    pub const A: Self = Self(30);
+    pub const B: Self = Self(48);
+    pub const C: Self = Self(46);
+    pub const D: Self = Self(32);
+    pub const E: Self = Self(18);
+    pub const F: Self = Self(33);
+    pub const G: Self = Self(34);
+    pub const H: Self = Self(35);
+    pub const I: Self = Self(23);
+    pub const J: Self = Self(36);
+    pub const K: Self = Self(37);
+    pub const L: Self = Self(38);
+    pub const M: Self = Self(50);
+    pub const N: Self = Self(49);
+    pub const O: Self = Self(24);
+    pub const P: Self = Self(25);
+    pub const Q: Self = Self(16);
+    pub const R: Self = Self(19);
+    pub const S: Self = Self(31);
+    pub const T: Self = Self(20);
+    pub const U: Self = Self(22);
+    pub const V: Self = Self(47);
+    pub const W: Self = Self(17);
+    pub const X: Self = Self(45);
+    pub const Y: Self = Self(21);
+    pub const Z: Self = Self(44);
+
+    // Digits (top row)
+    pub const DIGIT_1: Self = Self(2);
+    pub const DIGIT_2: Self = Self(3);
+    pub const DIGIT_3: Self = Self(4);
+    pub const DIGIT_4: Self = Self(5);
+    pub const DIGIT_5: Self = Self(6);
+    pub const DIGIT_6: Self = Self(7);
+    pub const DIGIT_7: Self = Self(8);
+    pub const DIGIT_8: Self = Self(9);
+    pub const DIGIT_9: Self = Self(10);
+    pub const DIGIT_0: Self = Self(11);
+
+    // Punctuation
+    pub const MINUS: Self = Self(12);
+    pub const EQUAL: Self = Self(13);
+    pub const LEFT_BRACKET: Self = Self(26);
+    pub const RIGHT_BRACKET: Self = Self(27);
+    pub const BACKSLASH: Self = Self(43);
+    pub const SEMICOLON: Self = Self(39);
+    pub const APOSTROPHE: Self = Self(40);
+    pub const GRAVE: Self = Self(41);
+    pub const COMMA: Self = Self(51);
+    pub const PERIOD: Self = Self(52);
+    pub const SLASH: Self = Self(53);
+
+    // Function keys
+    pub const F1: Self = Self(59);
+    pub const F2: Self = Self(60);
+    pub const F3: Self = Self(61);
+    pub const F4: Self = Self(62);
+    pub const F5: Self = Self(63);
+    pub const F6: Self = Self(64);
+    pub const F7: Self = Self(65);
+    pub const F8: Self = Self(66);
+    pub const F9: Self = Self(67);
+    pub const F10: Self = Self(68);
+    pub const F11: Self = Self(87);
+    pub const F12: Self = Self(88);
+    pub const F13: Self = Self(183);
+    pub const F14: Self = Self(184);
+    pub const F15: Self = Self(185);
+    pub const F16: Self = Self(186);
+    pub const F17: Self = Self(187);
+    pub const F18: Self = Self(188);
+    pub const F19: Self = Self(189);
+    pub const F20: Self = Self(190);
+    pub const F21: Self = Self(191);
+    pub const F22: Self = Self(192);
+    pub const F23: Self = Self(193);
+    pub const F24: Self = Self(194);
+
+    // Modifiers and locks
+    pub const LEFT_CTRL: Self = Self(29);
+    pub const RIGHT_CTRL: Self = Self(97);
+    pub const LEFT_SHIFT: Self = Self(42);
+    pub const RIGHT_SHIFT: Self = Self(54);
+    pub const LEFT_ALT: Self = Self(56);
+    pub const RIGHT_ALT: Self = Self(100);
+    pub const LEFT_META: Self = Self(125);
+    pub const RIGHT_META: Self = Self(126);
+    pub const CAPS_LOCK: Self = Self(58);
+    pub const NUM_LOCK: Self = Self(69);
+    pub const SCROLL_LOCK: Self = Self(70);
+
+    // Navigation and system
+    pub const INSERT: Self = Self(110);
+    pub const DELETE: Self = Self(111);
+    pub const HOME: Self = Self(102);
+    pub const END: Self = Self(107);
+    pub const PAGE_UP: Self = Self(104);
+    pub const PAGE_DOWN: Self = Self(109);
+    pub const LEFT: Self = Self(105);
+    pub const RIGHT: Self = Self(106);
+    pub const UP: Self = Self(103);
+    pub const DOWN: Self = Self(108);
+    pub const PRINT_SCREEN: Self = Self(99);
+    pub const PAUSE: Self = Self(119);
+    pub const MENU: Self = Self(127);
+    pub const POWER: Self = Self(116);
+
+    // Keypad
+    pub const KP_0: Self = Self(82);
+    pub const KP_1: Self = Self(79);
+    pub const KP_2: Self = Self(80);
+    pub const KP_3: Self = Self(81);
+    pub const KP_4: Self = Self(75);
+    pub const KP_5: Self = Self(76);
+    pub const KP_6: Self = Self(77);
+    pub const KP_7: Self = Self(71);
+    pub const KP_8: Self = Self(72);
+    pub const KP_9: Self = Self(73);
+    pub const KP_DIVIDE: Self = Self(98);
+    pub const KP_MULTIPLY: Self = Self(55);
+    pub const KP_SUBTRACT: Self = Self(74);
+    pub const KP_ADD: Self = Self(78);
+    pub const KP_ENTER: Self = Self(96);
+    pub const KP_DECIMAL: Self = Self(83);
+    pub const KP_EQUAL: Self = Self(117);
+    pub const KP_COMMA: Self = Self(121);
+
+    // Media and browser
+    pub const MUTE: Self = Self(113);
+    pub const VOLUME_DOWN: Self = Self(114);
+    pub const VOLUME_UP: Self = Self(115);
+    pub const MEDIA_PLAY_PAUSE: Self = Self(164);
+    pub const MEDIA_STOP: Self = Self(166);
+    pub const MEDIA_PREVIOUS: Self = Self(165);
+    pub const MEDIA_NEXT: Self = Self(163);
+    pub const BROWSER_HOME: Self = Self(172);
+    pub const BROWSER_BACK: Self = Self(158);
+    pub const BROWSER_FORWARD: Self = Self(159);
+    pub const BROWSER_REFRESH: Self = Self(173);
+    pub const BROWSER_SEARCH: Self = Self(217);
+    pub const LAUNCH_MAIL: Self = Self(155);
+    pub const LAUNCH_CALCULATOR: Self = Self(140);
+
+    // International
+    pub const INTL_BACKSLASH: Self = Self(86); // ISO key between left shift and Z
+    pub const INTL_RO: Self = Self(89);
+    pub const INTL_YEN: Self = Self(124);
+    pub const KATAKANA_HIRAGANA: Self = Self(93);
+    pub const HENKAN: Self = Self(92);
+    pub const MUHENKAN: Self = Self(94);
+    pub const KP_JP_COMMA: Self = Self(95);
+    pub const HANGUL: Self = Self(122);
+    pub const HANJA: Self = Self(123);
+    pub const KATAKANA: Self = Self(90);
+    pub const HIRAGANA: Self = Self(91);
+    pub const ZENKAKU_HANKAKU: Self = Self(85);
 }
 
 // ============================================================================
@@ -235,6 +547,18 @@ impl AuthToken {
     }
 }
 
+/// Where a device's certificate signing request stands, see
+/// [`ProtocolMessage::EnrollRequest`](crate::domain::ports::ProtocolMessage::EnrollRequest)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EnrollmentStatus {
+    /// Waiting for an administrator to approve the request
+    Pending,
+    /// Approved: the PEM certificate issued by the server's client CA
+    Issued { certificate: String },
+    /// The server does not enroll devices, or refused this one
+    Refused { reason: String },
+}
+
 // Helper module for serde_bytes compatibility
 mod serde_bytes {
     use serde::{Deserialize, Deserializer, Serializer};
@@ -253,3 +577,54 @@ mod serde_bytes {
         Vec::<u8>::deserialize(deserializer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_capabilities_are_the_intersection() {
+        let agent = Capabilities {
+            codecs: vec![CodecType::H264, CodecType::Jpeg],
+            clipboard: true,
+            file_transfer: true,
+            ..Capabilities::default()
+        };
+        let client = Capabilities {
+            codecs: vec![CodecType::Jpeg],
+            clipboard: true,
+            input: vec![InputKind::Pointer],
+            ..Capabilities::default()
+        };
+
+        let session = Capabilities::for_session(&agent, &client);
+        // A JPEG-only viewer never gets H.264
+        assert_eq!(session.codec(), Some(CodecType::Jpeg));
+        assert!(session.clipboard);
+        assert!(!session.file_transfer);
+        assert_eq!(session.input, [InputKind::Pointer]);
+        assert!(!session.accepts(&InputEvent::Text { text: "a".into() }));
+    }
+
+    #[test]
+    fn test_view_only_agent_accepts_no_input() {
+        let agent = Capabilities { input: Vec::new(), ..Capabilities::default() };
+        let session = Capabilities::for_session(&agent, &Capabilities::default());
+
+        assert!(session.is_view_only());
+        assert!(!session.accepts(&InputEvent::MouseMove { x: 0, y: 0 }));
+    }
+
+    #[test]
+    fn test_wire_format_negotiation() {
+        assert_eq!(WireFormat::negotiate(&[WireFormat::Bincode, WireFormat::Postcard]), WireFormat::Postcard);
+        assert_eq!(WireFormat::negotiate(&WireFormat::ALL), WireFormat::PostcardZstd);
+        assert_eq!(WireFormat::negotiate(&[]), WireFormat::Bincode);
+    }
+
+    #[test]
+    fn test_no_common_codec() {
+        let agent = Capabilities { codecs: vec![CodecType::H264], ..Capabilities::default() };
+        assert_eq!(Capabilities::for_session(&agent, &Capabilities::default()).codec(), None);
+    }
+}
@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 use super::models::*;
 use super::error::*;
+use super::version::VersionRange;
 
 // ============================================================================
 // Screen Capture Port
@@ -53,6 +54,11 @@ pub trait Encoder: Send + Sync {
     
     /// Update encoder configuration
     fn set_config(&mut self, config: EncoderConfig) -> std::result::Result<(), CodecError>;
+
+    /// Make the next frame decodable without the ones before it
+    ///
+    /// Encoders whose every frame stands alone, such as JPEG, ignore it.
+    fn request_keyframe(&mut self) {}
 }
 
 /// Trait for decoding screen frames
@@ -72,12 +78,31 @@ pub trait Decoder: Send + Sync {
 /// Protocol message wrapper
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum ProtocolMessage {
-    // Handshake & Auth
+    // Handshake: these three keep their position and encoding in every
+    // protocol version, so peers of different releases can always negotiate
+    /// First message on a connection, advertising the versions the peer speaks
     Hello {
-        version: u32,
+        versions: VersionRange,
         device_id: String,
         platform: Platform,
+        role: PeerRole,
+    },
+    /// Server's answer to `Hello` with the version used from now on
+    HelloAck {
+        version: u32,
+    },
+    Error {
+        code: u32,
+        message: String,
     },
+    /// Sent by the peer right after `HelloAck`; encoded with the negotiated version
+    Capabilities {
+        capabilities: Capabilities,
+        /// Wire formats the peer can use after the handshake
+        wire_formats: Vec<WireFormat>,
+    },
+
+    // Auth
     Auth {
         token: AuthToken,
     },
@@ -91,14 +116,26 @@ pub enum ProtocolMessage {
     SessionRequest {
         target_device: String,
     },
+    /// Sent to both ends of a new session; `endpoint` is the device id of the other end
+    /// and `capabilities` what both ends support
     SessionCreated {
         session_id: SessionId,
         endpoint: String,
+        capabilities: Capabilities,
+        /// Only in the client's copy: presented in `SessionResume` to get the
+        /// session back after losing the connection
+        resume_token: Option<String>,
     },
     SessionEnd {
         session_id: SessionId,
         reason: String,
     },
+    /// Ask the server for the agents it knows about
+    ListAgents,
+    /// Reply to `ListAgents`, online agents first
+    AgentList {
+        agents: Vec<AgentInfo>,
+    },
     
     // Streaming
     ScreenFrame {
@@ -111,7 +148,9 @@ pub enum ProtocolMessage {
     },
     
     // Input
+    /// Input for the agent, from the client of `session_id`
     InputEvent {
+        session_id: SessionId,
         timestamp: u64,
         event: InputEvent,
     },
@@ -120,11 +159,90 @@ pub enum ProtocolMessage {
     Heartbeat {
         timestamp: u64,
     },
-    Error {
-        code: u32,
-        message: String,
-    },
     Disconnect,
+
+    // Enrollment
+    /// Sent instead of `Auth` by a device asking for a client certificate; `csr`
+    /// is a PEM certificate signing request
+    EnrollRequest {
+        csr: String,
+    },
+    /// Reply to `EnrollRequest`; the server closes the connection after it
+    EnrollResponse {
+        status: EnrollmentStatus,
+    },
+
+    // Wire format
+    /// Server's answer to `Capabilities`: both ends encode every later message
+    /// as `format`
+    WireFormatSelected {
+        format: WireFormat,
+    },
+
+    // Session resumption
+    /// Sent by a client that lost its connection, instead of `SessionRequest`,
+    /// to get back a session the server paused; answered the same way
+    SessionResume {
+        session_id: SessionId,
+        resume_token: String,
+    },
+    /// Ask the agent for a frame that decodes on its own, for a viewer joining
+    /// the stream anew
+    KeyframeRequest {
+        session_id: SessionId,
+    },
+    /// Tell the agent the client of a session lost its connection, so it lets
+    /// go of the session's input until the client resumes it
+    SessionPaused {
+        session_id: SessionId,
+    },
+}
+
+/// How a message needs to be delivered, for transports that can treat
+/// traffic differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageClass {
+    /// Reliable and in order with the other control messages
+    Control,
+    /// Reliable and in order with the other input, but not behind frames
+    Input,
+    /// Reliable, and independent of every other message
+    Frame,
+    /// Superseded by the next message of its kind, so it may be lost rather
+    /// than resent
+    Coalescable,
+}
+
+impl ProtocolMessage {
+    pub fn class(&self) -> MessageClass {
+        match self {
+            ProtocolMessage::ScreenFrame { .. } => MessageClass::Frame,
+            ProtocolMessage::InputEvent { event: InputEvent::MouseMove { .. }, .. }
+            | ProtocolMessage::Heartbeat { .. } => MessageClass::Coalescable,
+            ProtocolMessage::InputEvent { .. } => MessageClass::Input,
+            _ => MessageClass::Control,
+        }
+    }
+}
+
+/// Codes carried by [`ProtocolMessage::Error`]
+pub mod error_codes {
+    /// Malformed or unexpected request
+    pub const BAD_REQUEST: u32 = 400;
+    /// The peer's token does not allow the request
+    pub const UNAUTHORIZED: u32 = 401;
+    /// The requested agent is not registered with the server
+    pub const AGENT_NOT_FOUND: u32 = 404;
+    /// The session to resume has ended, or the resume token does not match
+    pub const SESSION_EXPIRED: u32 = 410;
+    /// The agent encodes no codec the client can decode
+    pub const NO_COMMON_CODEC: u32 = 415;
+    /// The peer's `Hello` shares no protocol version with the server
+    pub const INCOMPATIBLE_VERSION: u32 = 426;
+    /// The server failed to carry out the request
+    pub const INTERNAL_ERROR: u32 = 500;
+    /// The requested agent is registered but cannot take the session
+    pub const AGENT_UNAVAILABLE: u32 = 503;
 }
 
 /// Trait for network transport
@@ -134,8 +252,17 @@ pub trait Transport: Send + Sync {
     async fn send(&mut self, message: ProtocolMessage) -> std::result::Result<(), TransportError>;
     
     /// Receive a protocol message
+    ///
+    /// Must be cancel-safe: dropping the future before it completes may not
+    /// lose data, so callers can `select!` between receiving and sending.
     async fn receive(&mut self) -> std::result::Result<ProtocolMessage, TransportError>;
     
+    /// Encode and decode messages as `format` from the next one on, as agreed
+    /// in the handshake
+    ///
+    /// Transports that do not serialize messages ignore it.
+    fn set_wire_format(&mut self, _format: WireFormat) {}
+
     /// Close the transport connection
     async fn close(&mut self) -> std::result::Result<(), TransportError>;
     
@@ -0,0 +1,126 @@
+//! Protocol version negotiation
+//!
+//! Every peer advertises the range of protocol versions it speaks in its
+//! `Hello`; the server picks the highest version both ranges contain. Keeping
+//! support for the previous version for a release lets agents and servers be
+//! upgraded in any order.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Newest protocol version this build speaks
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest protocol version this build still speaks
+///
+/// Version 1 (0.1.0) sent a single version and no role in `Hello`, so its
+/// handshake cannot be decoded any more.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Inclusive range of protocol versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct VersionRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl VersionRange {
+    /// Versions this build speaks
+    pub const SUPPORTED: VersionRange = VersionRange::new(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION);
+
+    pub const fn new(min: u32, max: u32) -> Self {
+        Self { min, max }
+    }
+
+    /// Range holding a single version
+    pub const fn exactly(version: u32) -> Self {
+        Self::new(version, version)
+    }
+
+    pub fn contains(&self, version: u32) -> bool {
+        self.min <= version && version <= self.max
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min > self.max
+    }
+
+    /// Highest version both ranges contain, if they overlap
+    pub fn negotiate(&self, other: &VersionRange) -> Option<u32> {
+        let version = self.max.min(other.max);
+        (version >= self.min.max(other.min)).then_some(version)
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "v{}", self.min)
+        } else {
+            write!(f, "v{}-v{}", self.min, self.max)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ranges of past, current and plausible future releases
+    const V1: VersionRange = VersionRange::exactly(1);
+    const V2: VersionRange = VersionRange::exactly(2);
+    const V2_V3: VersionRange = VersionRange::new(2, 3);
+    const V3: VersionRange = VersionRange::exactly(3);
+    const V3_V4: VersionRange = VersionRange::new(3, 4);
+
+    #[test]
+    fn test_supported_range_is_valid() {
+        assert!(!VersionRange::SUPPORTED.is_empty());
+        assert!(VersionRange::SUPPORTED.contains(PROTOCOL_VERSION));
+        assert_eq!(VersionRange::SUPPORTED.negotiate(&VersionRange::SUPPORTED), Some(PROTOCOL_VERSION));
+    }
+
+    #[test]
+    fn test_compatibility_matrix() {
+        // (server, peer, negotiated version)
+        let matrix = [
+            // Same release on both ends
+            (V2, V2, Some(2)),
+            // Server upgraded first, agents still on the previous release
+            (V2_V3, V2, Some(2)),
+            // Agent upgraded first, server still on the previous release
+            (V2, V2_V3, Some(2)),
+            // Both upgraded
+            (V2_V3, V2_V3, Some(3)),
+            (V2_V3, V3_V4, Some(3)),
+            // Server dropped the version an old agent speaks
+            (V3, V2, None),
+            (V3_V4, V2_V3, Some(3)),
+            // 0.1.0 peers cannot talk to this release
+            (V2, V1, None),
+            (V1, V2_V3, None),
+        ];
+
+        for (server, peer, expected) in matrix {
+            assert_eq!(server.negotiate(&peer), expected, "server {} / peer {}", server, peer);
+            // Who advertises first must not matter
+            assert_eq!(peer.negotiate(&server), expected, "peer {} / server {}", peer, server);
+            if let Some(version) = expected {
+                assert!(server.contains(version) && peer.contains(version));
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_range_matches_nothing() {
+        let empty = VersionRange::new(3, 2);
+        assert!(empty.is_empty());
+        assert_eq!(empty.negotiate(&VersionRange::new(1, 5)), None);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(V2.to_string(), "v2");
+        assert_eq!(V2_V3.to_string(), "v2-v3");
+    }
+}
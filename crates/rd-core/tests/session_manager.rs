@@ -0,0 +1,98 @@
+//! `SessionManager` over an in-memory repository and a fake authenticator
+
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+
+use rd_core::application::SessionManager;
+use rd_core::domain::error::{ApplicationError, AuthError, DomainError};
+use rd_core::domain::models::{AuthToken, Capabilities, PeerId, SessionId, SessionStatus};
+use rd_core::domain::ports::SessionRepository;
+use rd_testkit::{FakeAuthenticator, InMemorySessionRepository};
+
+fn manager() -> (SessionManager, FakeAuthenticator) {
+    let repository: Arc<RwLock<dyn SessionRepository>> =
+        Arc::new(RwLock::new(InMemorySessionRepository::new()));
+    let authenticator = FakeAuthenticator::new();
+    (SessionManager::new(repository, Arc::new(authenticator.clone())), authenticator)
+}
+
+#[tokio::test]
+async fn test_create_session_for_authenticated_client() {
+    let (manager, auth) = manager();
+    let token = auth.allow("viewer-token", "viewer");
+
+    let session = manager
+        .create_session(&token, PeerId::new("host"), Capabilities::default())
+        .await
+        .unwrap();
+
+    // The client is who the token says, not who asks
+    assert_eq!(session.client, PeerId::new("viewer"));
+    assert_eq!(session.agent, PeerId::new("host"));
+    assert_eq!(session.status, SessionStatus::Pending);
+    assert_eq!(manager.get_session(session.id).await.unwrap().id, session.id);
+    assert_eq!(manager.list_active_sessions().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_refused_tokens_create_nothing() {
+    let (manager, auth) = manager();
+    let expired = auth.allow("old", "viewer");
+    auth.expire(&expired);
+
+    let unknown = AuthToken::new("made-up", "viewer");
+    assert!(matches!(
+        manager.create_session(&unknown, PeerId::new("host"), Capabilities::default()).await,
+        Err(ApplicationError::Auth(AuthError::InvalidToken))
+    ));
+    assert!(matches!(
+        manager.create_session(&expired, PeerId::new("host"), Capabilities::default()).await,
+        Err(ApplicationError::Auth(AuthError::TokenExpired))
+    ));
+    assert!(manager.list_active_sessions().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_status_changes_and_end() {
+    let (manager, auth) = manager();
+    let token = auth.allow("viewer-token", "viewer");
+    let session = manager
+        .create_session(&token, PeerId::new("host"), Capabilities::default())
+        .await
+        .unwrap();
+
+    manager.update_status(session.id, SessionStatus::Active).await.unwrap();
+    assert_eq!(manager.get_session(session.id).await.unwrap().status, SessionStatus::Active);
+
+    manager.end_session(session.id).await.unwrap();
+    assert!(matches!(
+        manager.get_session(session.id).await,
+        Err(ApplicationError::Domain(DomainError::SessionNotFound(id))) if id == session.id
+    ));
+    assert!(manager.list_active_sessions().await.unwrap().is_empty());
+
+    // Nothing left to end or update
+    assert!(manager.end_session(session.id).await.is_err());
+    assert!(manager.update_status(SessionId::new(), SessionStatus::Active).await.is_err());
+}
+
+#[tokio::test]
+async fn test_sessions_of_either_end() {
+    let (manager, auth) = manager();
+    let viewer = auth.allow("viewer-token", "viewer");
+    let laptop = auth.allow("laptop-token", "laptop");
+
+    for (token, agent) in [(&viewer, "host"), (&viewer, "nas"), (&laptop, "host")] {
+        manager.create_session(token, PeerId::new(agent), Capabilities::default()).await.unwrap();
+    }
+
+    let count = |peer: &'static str| {
+        let manager = &manager;
+        async move { manager.sessions_of(&PeerId::new(peer)).await.unwrap().len() }
+    };
+    assert_eq!(count("viewer").await, 2);
+    assert_eq!(count("host").await, 2);
+    assert_eq!(count("nas").await, 1);
+    assert_eq!(count("stranger").await, 0);
+}
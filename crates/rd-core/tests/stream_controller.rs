@@ -0,0 +1,113 @@
+//! `StreamController` driven by scripted capture, a fake encoder and in-memory transports
+
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use rd_core::application::stream_controller::{StreamConfig, StreamController};
+use rd_core::domain::error::CaptureError;
+use rd_core::domain::models::{InputEvent, InputKind, KeyCode, MouseButton, SessionId};
+use rd_core::domain::ports::{Decoder, ProtocolMessage, Transport};
+use rd_testkit::{test_frame, FakeDecoder, FakeEncoder, LoopbackTransport, RecordingInjector, ScriptedCapture};
+
+fn controller(capture: ScriptedCapture, encoder: FakeEncoder, transport: LoopbackTransport) -> StreamController {
+    StreamController::new(
+        Arc::new(Mutex::new(capture)),
+        Arc::new(Mutex::new(encoder)),
+        Arc::new(Mutex::new(transport)),
+        StreamConfig::default(),
+    )
+}
+
+/// Receive the next `count` screen frames as (sequence, width, pixels)
+async fn frames(viewer: &mut LoopbackTransport, count: usize) -> Vec<(u64, u32, Vec<u8>)> {
+    let mut frames = Vec::new();
+    while frames.len() < count {
+        if let ProtocolMessage::ScreenFrame { sequence, width, data, .. } = viewer.receive().await.unwrap() {
+            let decoded = FakeDecoder::new().decode(&data).await.unwrap();
+            assert_eq!(decoded.width, width);
+            frames.push((sequence, width, decoded.data));
+        }
+    }
+    frames
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_streams_encoded_frames_until_the_viewer_leaves() {
+    let capture = ScriptedCapture::new([test_frame(10, 4, 2), test_frame(11, 8, 2)]);
+    let encoder = FakeEncoder::new();
+    let (agent, mut viewer) = LoopbackTransport::pair();
+
+    let controller = controller(capture.clone(), encoder.clone(), agent);
+    let streaming = tokio::spawn(async move { controller.start_streaming().await });
+
+    let received = frames(&mut viewer, 3).await;
+    assert_eq!(
+        received,
+        [
+            (0, 4, test_frame(10, 4, 2).data),
+            (1, 8, test_frame(11, 8, 2).data),
+            // The screen did not change any more
+            (2, 8, test_frame(11, 8, 2).data),
+        ]
+    );
+
+    viewer.close().await.unwrap();
+    streaming.await.unwrap().unwrap();
+    assert!(encoder.encoded() >= 3);
+    assert_eq!(capture.captures(), encoder.encoded());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_failed_frames_are_skipped() {
+    let capture = ScriptedCapture::scripted([
+        Ok(test_frame(1, 2, 2)),
+        Err(CaptureError::CaptureFailed("display asleep".into())),
+        Ok(test_frame(2, 2, 2)),
+        Ok(test_frame(3, 2, 2)),
+    ]);
+    let encoder = FakeEncoder::new();
+    let (agent, mut viewer) = LoopbackTransport::pair();
+
+    // The third capture (frame 2) fails to encode
+    let controller = controller(capture.clone(), encoder.clone(), agent);
+    let streaming = tokio::spawn(async move { controller.start_streaming().await });
+    let first = frames(&mut viewer, 1).await;
+    encoder.fail_next(1);
+    let rest = frames(&mut viewer, 1).await;
+
+    // Sequence numbers count sent frames, so the viewer sees no gap
+    assert_eq!(first[0].0, 0);
+    assert_eq!(rest[0].0, 1);
+    assert_eq!(rest[0].2, test_frame(3, 2, 2).data);
+
+    viewer.close().await.unwrap();
+    streaming.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_input_stream_injects_input_until_closed() {
+    let injector = RecordingInjector::new().refusing(InputKind::Keyboard);
+    let (agent, mut viewer) = LoopbackTransport::pair();
+
+    let events = [
+        InputEvent::MouseMove { x: 10, y: 20 },
+        InputEvent::KeyPress { key: KeyCode::A, pressed: true },
+        InputEvent::MouseButton { button: MouseButton::Left, pressed: true },
+    ];
+    viewer.send(ProtocolMessage::Heartbeat { timestamp: 0 }).await.unwrap();
+    for event in events.clone() {
+        viewer.send(ProtocolMessage::InputEvent { session_id: SessionId::new(), timestamp: 0, event }).await.unwrap();
+    }
+    viewer.close().await.unwrap();
+
+    StreamController::handle_input_stream(Arc::new(Mutex::new(injector.clone())), Arc::new(Mutex::new(agent)))
+        .await
+        .unwrap();
+
+    // The refused key press does not stop the events after it
+    let injected = injector.events();
+    assert_eq!(injected.len(), 2);
+    assert!(matches!(injected[0], InputEvent::MouseMove { x: 10, y: 20 }));
+    assert!(matches!(injected[1], InputEvent::MouseButton { button: MouseButton::Left, pressed: true }));
+}
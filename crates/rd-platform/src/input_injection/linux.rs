@@ -1,33 +1,366 @@
 use async_trait::async_trait;
 use rd_core::domain::{
+    keymap::{char_to_keysym, NO_SYMBOL},
     models::*,
     ports::InputInjector,
     error::InjectionError,
 };
-use tracing::{debug, warn};
+use std::time::Duration;
+use tracing::debug;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::errors::{ConnectionError, ReplyError};
+use x11rb::protocol::xproto::{self, ConnectionExt as _, Window};
+use x11rb::protocol::xtest::{self, ConnectionExt as _};
+use x11rb::rust_connection::RustConnection;
+
+/// X keycodes are evdev codes shifted by 8 on evdev/XKB servers
+const EVDEV_OFFSET: u32 = 8;
+
+/// Spare keycodes bound at once when typing text, so that consecutive
+/// characters never reuse a keycode the previous one was just typed with
+const TEXT_KEYCODES: usize = 8;
+
+/// Time X clients get to re-read the keyboard mapping after it changed, before
+/// keys are typed with it; clients that read it lazily on `MappingNotify`
+/// would otherwise look up the wrong keysym
+const REMAP_SETTLE: Duration = Duration::from_millis(20);
 
 /// Linux input injection using XTest extension
-pub struct LinuxInputInjector;
+pub struct LinuxInputInjector {
+    conn: RustConnection,
+    root: Window,
+    min_keycode: u8,
+    max_keycode: u8,
+}
 
 impl LinuxInputInjector {
     pub fn new() -> Result<Self, InjectionError> {
         debug!("Initializing Linux input injector (XTest)");
-        Ok(Self)
+
+        let (conn, screen_num) = x11rb::connect(None)
+            .map_err(|e| InjectionError::InitializationFailed(format!("Failed to connect to X server: {}", e)))?;
+
+        let present = conn
+            .extension_information(xtest::X11_EXTENSION_NAME)
+            .map_err(|e| InjectionError::InitializationFailed(e.to_string()))?
+            .is_some();
+        if !present {
+            return Err(InjectionError::InitializationFailed("XTest extension not present".into()));
+        }
+
+        let version = conn
+            .xtest_get_version(2, 2)
+            .map_err(|e| InjectionError::InitializationFailed(e.to_string()))?
+            .reply()
+            .map_err(|e| InjectionError::InitializationFailed(e.to_string()))?;
+        debug!("X11: XTest {}.{}", version.major_version, version.minor_version);
+
+        let setup = conn.setup();
+        let root = setup.roots[screen_num].root;
+        let min_keycode = setup.min_keycode;
+        let max_keycode = setup.max_keycode;
+
+        Ok(Self {
+            conn,
+            root,
+            min_keycode,
+            max_keycode,
+        })
+    }
+
+    /// Map a platform-agnostic key code to an X keycode
+    fn x_keycode(&self, key: KeyCode) -> Result<u8, InjectionError> {
+        key.to_evdev()
+            .and_then(|code| u8::try_from(code as u32 + EVDEV_OFFSET).ok())
+            .filter(|code| (self.min_keycode..=self.max_keycode).contains(code))
+            .ok_or_else(|| InjectionError::InvalidEvent(format!("No X keycode for {:?}", key)))
+    }
+
+    /// Send a single XTest event and wait for the server to process it
+    fn fake_input(&self, type_: u8, detail: u8, x: i16, y: i16) -> Result<(), InjectionError> {
+        self.conn
+            .xtest_fake_input(type_, detail, x11rb::CURRENT_TIME, self.root, x, y, x11rb::NONE as u8)
+            .map_err(connection_error)?
+            .check()
+            .map_err(reply_error)
+    }
+
+    /// Up to [`TEXT_KEYCODES`] keycodes with no keysyms bound, highest first, plus
+    /// the server's keysyms per keycode
+    fn spare_keycodes(&self) -> Result<(Vec<u8>, u8), InjectionError> {
+        let count = self.max_keycode - self.min_keycode + 1;
+        let mapping = self
+            .conn
+            .get_keyboard_mapping(self.min_keycode, count)
+            .map_err(connection_error)?
+            .reply()
+            .map_err(reply_error)?;
+        let per_keycode = mapping.keysyms_per_keycode;
+
+        let spare: Vec<u8> = mapping
+            .keysyms
+            .chunks(per_keycode.max(1) as usize)
+            .enumerate()
+            .rev()
+            .filter(|(_, keysyms)| keysyms.iter().all(|&k| k == NO_SYMBOL))
+            .map(|(i, _)| self.min_keycode + i as u8)
+            .take(TEXT_KEYCODES)
+            .collect();
+        if spare.is_empty() {
+            return Err(InjectionError::InjectionFailed("No spare X keycode to type text".into()));
+        }
+        Ok((spare, per_keycode))
+    }
+
+    /// Bind every level of `keycode` to `keysym`, so Shift and Lock state do not matter
+    ///
+    /// Waits for the server to apply the change.
+    fn remap(&self, keycode: u8, per_keycode: u8, keysym: u32) -> Result<(), InjectionError> {
+        let keysyms = vec![keysym; per_keycode as usize];
+        self.conn
+            .change_keyboard_mapping(1, keycode, per_keycode, &keysyms)
+            .map_err(connection_error)?
+            .check()
+            .map_err(reply_error)
+    }
+
+    /// Type `text` by temporarily remapping spare keycodes to its characters' keysyms
+    ///
+    /// Characters are typed in batches, one spare keycode each, with a pause
+    /// after each remapping so no client sees a keycode bound to another
+    /// character than the one it was pressed for.
+    pub(crate) async fn type_text(&self, text: &str) -> Result<(), InjectionError> {
+        let keysyms = text
+            .chars()
+            .map(|c| {
+                char_to_keysym(c)
+                    .ok_or_else(|| InjectionError::InvalidEvent(format!("Cannot type {:?}", c)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if keysyms.is_empty() {
+            return Ok(());
+        }
+
+        let (keycodes, per_keycode) = self.spare_keycodes()?;
+        let typed = async {
+            for batch in keysyms.chunks(keycodes.len()) {
+                for (&keycode, &keysym) in keycodes.iter().zip(batch) {
+                    self.remap(keycode, per_keycode, keysym)?;
+                }
+                tokio::time::sleep(REMAP_SETTLE).await;
+                for &keycode in &keycodes[..batch.len()] {
+                    self.fake_input(xproto::KEY_PRESS_EVENT, keycode, 0, 0)?;
+                    self.fake_input(xproto::KEY_RELEASE_EVENT, keycode, 0, 0)?;
+                }
+                // Let clients handle these keys before their keycodes change again
+                tokio::time::sleep(REMAP_SETTLE).await;
+            }
+            Ok(())
+        }
+        .await;
+
+        // Always hand the keycodes back, even if typing failed halfway
+        let restored = keycodes
+            .iter()
+            .try_for_each(|&keycode| self.remap(keycode, per_keycode, NO_SYMBOL));
+        typed.and(restored)
+    }
+
+    fn click(&self, button: u8, count: u32) -> Result<(), InjectionError> {
+        for _ in 0..count {
+            self.fake_input(xproto::BUTTON_PRESS_EVENT, button, 0, 0)?;
+            self.fake_input(xproto::BUTTON_RELEASE_EVENT, button, 0, 0)?;
+        }
+        Ok(())
     }
 }
 
 #[async_trait]
 impl InputInjector for LinuxInputInjector {
     async fn inject(&mut self, event: InputEvent) -> Result<(), InjectionError> {
-        // TODO: Implement XTest input injection
-        // 1. Use XTestFakeMotionEvent for mouse move
-        // 2. Use XTestFakeButtonEvent for mouse buttons
-        // 3. Use XTestFakeKeyEvent for keyboard
-        
-        warn!("Linux input injection not yet implemented: {:?}", event);
-        
-        Ok(())
+        match event {
+            InputEvent::MouseMove { x, y } => {
+                let x = i16::try_from(x)
+                    .map_err(|_| InjectionError::InvalidEvent(format!("x out of range: {}", x)))?;
+                let y = i16::try_from(y)
+                    .map_err(|_| InjectionError::InvalidEvent(format!("y out of range: {}", y)))?;
+                self.fake_input(xproto::MOTION_NOTIFY_EVENT, 0, x, y)
+            }
+            InputEvent::MouseButton { button, pressed } => {
+                let type_ = if pressed {
+                    xproto::BUTTON_PRESS_EVENT
+                } else {
+                    xproto::BUTTON_RELEASE_EVENT
+                };
+                self.fake_input(type_, x_button(button), 0, 0)
+            }
+            InputEvent::MouseScroll { delta_x, delta_y } => {
+                // X11 reports wheel clicks as buttons 4/5 (vertical) and 6/7 (horizontal)
+                if delta_y > 0 {
+                    self.click(4, delta_y.unsigned_abs())?;
+                } else if delta_y < 0 {
+                    self.click(5, delta_y.unsigned_abs())?;
+                }
+                if delta_x > 0 {
+                    self.click(7, delta_x.unsigned_abs())?;
+                } else if delta_x < 0 {
+                    self.click(6, delta_x.unsigned_abs())?;
+                }
+                Ok(())
+            }
+            InputEvent::KeyPress { key, pressed } => {
+                let type_ = if pressed {
+                    xproto::KEY_PRESS_EVENT
+                } else {
+                    xproto::KEY_RELEASE_EVENT
+                };
+                self.fake_input(type_, self.x_keycode(key)?, 0, 0)
+            }
+            InputEvent::Text { text } => self.type_text(&text).await,
+        }
+    }
+}
+
+/// X11 core pointer button numbers
+fn x_button(button: MouseButton) -> u8 {
+    match button {
+        MouseButton::Left => 1,
+        MouseButton::Middle => 2,
+        MouseButton::Right => 3,
+        MouseButton::X1 => 8,
+        MouseButton::X2 => 9,
+    }
+}
+
+fn connection_error(e: ConnectionError) -> InjectionError {
+    InjectionError::InjectionFailed(e.to_string())
+}
+
+fn reply_error(e: ReplyError) -> InjectionError {
+    match e {
+        ReplyError::X11Error(err) if err.error_kind == x11rb::protocol::ErrorKind::Value => {
+            InjectionError::InvalidEvent(format!("{:?}", err))
+        }
+        ReplyError::X11Error(err) if err.error_kind == x11rb::protocol::ErrorKind::Access => {
+            InjectionError::PermissionDenied
+        }
+        e => InjectionError::InjectionFailed(e.to_string()),
     }
 }
 
-// TODO: Implement using x11rb or xcb
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x11rb::protocol::xproto::{CreateWindowAux, EventMask, InputFocus, WindowClass};
+    use x11rb::protocol::Event;
+
+    #[test]
+    fn test_x_button_mapping() {
+        assert_eq!(x_button(MouseButton::Left), 1);
+        assert_eq!(x_button(MouseButton::Right), 3);
+        assert_eq!(x_button(MouseButton::X1), 8);
+        assert_eq!(x_button(MouseButton::X2), 9);
+    }
+
+    /// Next event matching `f`, skipping anything else the server sends
+    fn wait_for<T>(conn: &RustConnection, f: impl Fn(&Event) -> Option<T>) -> T {
+        loop {
+            let event = conn.wait_for_event().unwrap();
+            if let Some(value) = f(&event) {
+                return value;
+            }
+        }
+    }
+
+    /// Injects events into a window owned by a second client and reads them back.
+    /// Runs against whatever X server `DISPLAY` points at (e.g. `xvfb-run cargo test`).
+    #[tokio::test]
+    async fn test_events_reach_client() {
+        if std::env::var_os("DISPLAY").is_none() {
+            eprintln!("DISPLAY not set, skipping XTest injection test");
+            return;
+        }
+
+        let (conn, screen_num) = x11rb::connect(None).unwrap();
+        let screen = &conn.setup().roots[screen_num];
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            screen.root,
+            0,
+            0,
+            200,
+            200,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::new()
+                .override_redirect(1)
+                .event_mask(
+                    EventMask::EXPOSURE
+                        | EventMask::KEY_PRESS
+                        | EventMask::KEY_RELEASE
+                        | EventMask::BUTTON_PRESS
+                        | EventMask::BUTTON_RELEASE
+                        | EventMask::POINTER_MOTION,
+                ),
+        )
+        .unwrap();
+        conn.map_window(window).unwrap();
+        conn.flush().unwrap();
+        wait_for(&conn, |e| matches!(e, Event::Expose(_)).then_some(()));
+        conn.set_input_focus(InputFocus::POINTER_ROOT, window, x11rb::CURRENT_TIME).unwrap();
+        conn.flush().unwrap();
+
+        let mut injector = LinuxInputInjector::new().unwrap();
+
+        injector.inject(InputEvent::MouseMove { x: 50, y: 60 }).await.unwrap();
+        let (x, y) = wait_for(&conn, |e| match e {
+            Event::MotionNotify(m) => Some((m.event_x, m.event_y)),
+            _ => None,
+        });
+        assert_eq!((x, y), (50, 60));
+
+        injector.inject(InputEvent::MouseButton { button: MouseButton::Right, pressed: true }).await.unwrap();
+        injector.inject(InputEvent::MouseButton { button: MouseButton::Right, pressed: false }).await.unwrap();
+        let button = wait_for(&conn, |e| match e {
+            Event::ButtonRelease(b) => Some(b.detail),
+            _ => None,
+        });
+        assert_eq!(button, 3);
+
+        injector.inject(InputEvent::MouseScroll { delta_x: 0, delta_y: -1 }).await.unwrap();
+        let button = wait_for(&conn, |e| match e {
+            Event::ButtonPress(b) => Some(b.detail),
+            _ => None,
+        });
+        assert_eq!(button, 5);
+
+        injector.inject(InputEvent::KeyPress { key: KeyCode::A, pressed: true }).await.unwrap();
+        injector.inject(InputEvent::KeyPress { key: KeyCode::A, pressed: false }).await.unwrap();
+        let keycode = wait_for(&conn, |e| match e {
+            Event::KeyPress(k) => Some(k.detail),
+            _ => None,
+        });
+        assert_eq!(keycode as u32, KeyCode::A.0 + EVDEV_OFFSET);
+
+        let (spare, _) = injector.spare_keycodes().unwrap();
+        injector.inject(InputEvent::Text { text: "\u{e9}\u{e8}".into() }).await.unwrap();
+        for expected in &spare[..2] {
+            let keycode = wait_for(&conn, |e| match e {
+                Event::KeyPress(k) => Some(k.detail),
+                _ => None,
+            });
+            // Consecutive characters go through different keycodes
+            assert_eq!(keycode, *expected);
+        }
+        // The spare keycodes are unbound again once typing is done
+        assert_eq!(injector.spare_keycodes().unwrap().0, spare);
+
+        assert!(matches!(
+            injector.inject(InputEvent::KeyPress { key: KeyCode(0x1000), pressed: true }).await,
+            Err(InjectionError::InvalidEvent(_))
+        ));
+    }
+}
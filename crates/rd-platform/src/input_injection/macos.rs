@@ -6,6 +6,10 @@ use rd_core::domain::{
 };
 use tracing::{debug, warn};
 
+/// Most UTF-16 code units a keyboard event carries; `CGEventKeyboardSetUnicodeString`
+/// silently drops the rest
+const MAX_EVENT_UTF16: usize = 20;
+
 /// macOS input injection using CGEvent
 /// Note: CGEventSource is not Send, so we use spawn_blocking
 pub struct MacOSInputInjector;
@@ -102,5 +106,55 @@ fn inject_event_sync(event: InputEvent) -> Result<(), InjectionError> {
             cg_event.post(CGEventTapLocation::HID);
             Ok(())
         }
+        InputEvent::Text { text } => {
+            debug!("Inject text: {} chars", text.chars().count());
+            // A keyboard event carrying a Unicode string types it regardless of layout
+            for chunk in utf16_chunks(&text, MAX_EVENT_UTF16) {
+                for pressed in [true, false] {
+                    let cg_event = CGEvent::new_keyboard_event(event_source.clone(), 0, pressed)
+                        .map_err(|_| InjectionError::InjectionFailed("Failed to create keyboard event".into()))?;
+                    cg_event.set_string(chunk);
+                    cg_event.post(CGEventTapLocation::HID);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Split `text` into pieces of at most `max` UTF-16 code units, never between
+/// the two halves of a surrogate pair
+fn utf16_chunks(text: &str, max: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let (mut start, mut units) = (0, 0);
+    for (i, c) in text.char_indices() {
+        if units + c.len_utf16() > max {
+            chunks.push(&text[start..i]);
+            (start, units) = (i, 0);
+        }
+        units += c.len_utf16();
+    }
+    if start < text.len() {
+        chunks.push(&text[start..]);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf16_chunks() {
+        assert!(utf16_chunks("", MAX_EVENT_UTF16).is_empty());
+        assert_eq!(utf16_chunks("abcde", 2), ["ab", "cd", "e"]);
+
+        // The emoji takes two code units and moves whole to the next chunk
+        assert_eq!(utf16_chunks("a\u{1f600}b", 2), ["a", "\u{1f600}", "b"]);
+
+        let pasted = "x".repeat(45);
+        let chunks = utf16_chunks(&pasted, MAX_EVENT_UTF16);
+        assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), [20, 20, 5]);
+        assert_eq!(chunks.concat(), pasted);
     }
 }
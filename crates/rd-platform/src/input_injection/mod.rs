@@ -4,6 +4,9 @@ mod windows;
 #[cfg(target_os = "linux")]
 mod linux;
 
+#[cfg(target_os = "linux")]
+mod uinput;
+
 #[cfg(target_os = "macos")]
 mod macos;
 
@@ -13,31 +16,87 @@ pub use self::windows::WindowsInputInjector;
 #[cfg(target_os = "linux")]
 pub use self::linux::LinuxInputInjector;
 
+#[cfg(target_os = "linux")]
+pub use self::uinput::UinputInputInjector;
+
 #[cfg(target_os = "macos")]
 pub use self::macos::MacOSInputInjector;
 
 use rd_core::domain::{ports::InputInjector, error::InjectionError};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
+/// Input injection backend selection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputBackend {
+    /// Pick the best backend for the current session
+    #[default]
+    Auto,
+    /// Native windowing system API (SendInput, XTest, CGEvent)
+    Native,
+    /// Linux `/dev/uinput` virtual devices (Wayland, headless hosts)
+    Uinput,
+}
+
 /// Create a platform-specific input injector implementation
-pub fn create_input_injector() -> Result<Arc<tokio::sync::Mutex<dyn InputInjector>>, InjectionError> {
+pub fn create_input_injector(
+    backend: InputBackend,
+) -> Result<Arc<tokio::sync::Mutex<dyn InputInjector>>, InjectionError> {
     #[cfg(target_os = "windows")]
     {
-        Ok(Arc::new(tokio::sync::Mutex::new(WindowsInputInjector::new()?)))
+        match backend {
+            InputBackend::Auto | InputBackend::Native => {
+                Ok(Arc::new(tokio::sync::Mutex::new(WindowsInputInjector::new()?)))
+            }
+            InputBackend::Uinput => Err(InjectionError::UnsupportedPlatform),
+        }
     }
-    
+
     #[cfg(target_os = "linux")]
     {
-        Ok(Arc::new(tokio::sync::Mutex::new(LinuxInputInjector::new()?)))
+        match backend {
+            InputBackend::Native => Ok(Arc::new(tokio::sync::Mutex::new(LinuxInputInjector::new()?))),
+            InputBackend::Uinput => Ok(Arc::new(tokio::sync::Mutex::new(UinputInputInjector::new()?))),
+            InputBackend::Auto => create_linux_auto(),
+        }
     }
-    
+
     #[cfg(target_os = "macos")]
     {
-        Ok(Arc::new(tokio::sync::Mutex::new(MacOSInputInjector::new()?)))
+        match backend {
+            InputBackend::Auto | InputBackend::Native => {
+                Ok(Arc::new(tokio::sync::Mutex::new(MacOSInputInjector::new()?)))
+            }
+            InputBackend::Uinput => Err(InjectionError::UnsupportedPlatform),
+        }
     }
-    
+
     #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
     {
+        let _ = backend;
         Err(InjectionError::UnsupportedPlatform)
     }
 }
+
+/// XTest on X11 sessions, uinput under Wayland or without a display server
+#[cfg(target_os = "linux")]
+fn create_linux_auto() -> Result<Arc<tokio::sync::Mutex<dyn InputInjector>>, InjectionError> {
+    use tracing::{info, warn};
+
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    let x11 = std::env::var_os("DISPLAY").is_some();
+
+    if x11 && !wayland {
+        match LinuxInputInjector::new() {
+            Ok(injector) => return Ok(Arc::new(tokio::sync::Mutex::new(injector))),
+            Err(e) if uinput::uinput_available() => {
+                warn!("XTest input injection unavailable ({}), falling back to uinput", e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    info!("Using uinput input injection");
+    Ok(Arc::new(tokio::sync::Mutex::new(UinputInputInjector::new()?)))
+}
@@ -0,0 +1,384 @@
+use async_trait::async_trait;
+use evdev::{
+    uinput::{VirtualDevice, VirtualDeviceBuilder},
+    AbsInfo, AbsoluteAxisType, AttributeSet, EventType, InputEvent as EvdevEvent, Key,
+    RelativeAxisType, UinputAbsSetup,
+};
+use rd_core::domain::{
+    keymap::us_layout_key,
+    models::*,
+    ports::InputInjector,
+    error::InjectionError,
+};
+use std::io;
+use tracing::{debug, warn};
+
+use super::linux::LinuxInputInjector;
+
+/// Pointer range used when the screen size cannot be detected
+const FALLBACK_SCREEN_SIZE: (u32, u32) = (1920, 1080);
+
+/// Highest key code registered on the virtual keyboard (below the BTN_* range)
+const MAX_KEY_CODE: u16 = 0xff;
+
+/// Linux input injection through `/dev/uinput` virtual devices
+///
+/// The kernel delivers these events like a physical keyboard and tablet, so
+/// this works under Wayland compositors and on hosts without an X server.
+/// Requires write access to `/dev/uinput`.
+///
+/// Text is typed through XTest on X11 sessions, whatever the keyboard layout.
+/// Elsewhere it is typed as US-layout key strokes, and refused on hosts
+/// configured with another layout.
+pub struct UinputInputInjector {
+    keyboard: VirtualDevice,
+    pointer: VirtualDevice,
+    screen_size: (u32, u32),
+    /// Types text by remapping keysyms, on X11 sessions
+    xtest: Option<LinuxInputInjector>,
+    /// Whether the host is configured with a US keyboard layout
+    us_layout: bool,
+}
+
+impl UinputInputInjector {
+    /// Create the virtual devices, sizing the pointer to the detected screen
+    pub fn new() -> Result<Self, InjectionError> {
+        let screen_size = detect_screen_size().unwrap_or_else(|| {
+            warn!(
+                "uinput: could not detect screen size, assuming {}x{}",
+                FALLBACK_SCREEN_SIZE.0, FALLBACK_SCREEN_SIZE.1
+            );
+            FALLBACK_SCREEN_SIZE
+        });
+        let mut injector = Self::with_screen_size(screen_size.0, screen_size.1)?;
+        injector.xtest = xtest_for_text();
+        Ok(injector)
+    }
+
+    /// Create the virtual devices with an absolute pointer covering `width`x`height` pixels
+    pub fn with_screen_size(width: u32, height: u32) -> Result<Self, InjectionError> {
+        debug!("Initializing uinput input injector ({}x{})", width, height);
+
+        if width == 0 || height == 0 {
+            return Err(InjectionError::InitializationFailed(format!(
+                "Invalid screen size {}x{}",
+                width, height
+            )));
+        }
+
+        let mut keys = AttributeSet::<Key>::new();
+        for code in 1..=MAX_KEY_CODE {
+            keys.insert(Key::new(code));
+        }
+
+        let keyboard = VirtualDeviceBuilder::new()
+            .map_err(init_error)?
+            .name("Remote Desktop Virtual Keyboard")
+            .with_keys(&keys)
+            .map_err(init_error)?
+            .build()
+            .map_err(init_error)?;
+
+        let mut buttons = AttributeSet::<Key>::new();
+        for button in [Key::BTN_LEFT, Key::BTN_RIGHT, Key::BTN_MIDDLE, Key::BTN_SIDE, Key::BTN_EXTRA] {
+            buttons.insert(button);
+        }
+
+        let mut wheels = AttributeSet::<RelativeAxisType>::new();
+        wheels.insert(RelativeAxisType::REL_WHEEL);
+        wheels.insert(RelativeAxisType::REL_HWHEEL);
+
+        let abs_x = UinputAbsSetup::new(
+            AbsoluteAxisType::ABS_X,
+            AbsInfo::new(0, 0, width as i32 - 1, 0, 0, 0),
+        );
+        let abs_y = UinputAbsSetup::new(
+            AbsoluteAxisType::ABS_Y,
+            AbsInfo::new(0, 0, height as i32 - 1, 0, 0, 0),
+        );
+
+        let pointer = VirtualDeviceBuilder::new()
+            .map_err(init_error)?
+            .name("Remote Desktop Virtual Pointer")
+            .with_keys(&buttons)
+            .map_err(init_error)?
+            .with_relative_axes(&wheels)
+            .map_err(init_error)?
+            .with_absolute_axis(&abs_x)
+            .map_err(init_error)?
+            .with_absolute_axis(&abs_y)
+            .map_err(init_error)?
+            .build()
+            .map_err(init_error)?;
+
+        let layout = configured_layout();
+        debug!("uinput: keyboard layout {}", layout.as_deref().unwrap_or("not configured, assuming us"));
+
+        Ok(Self {
+            keyboard,
+            pointer,
+            screen_size: (width, height),
+            xtest: None,
+            us_layout: layout.map_or(true, |layout| layout == "us"),
+        })
+    }
+}
+
+impl UinputInputInjector {
+    /// Type `text` through XTest on X11 sessions, otherwise as US-layout key strokes
+    ///
+    /// uinput only produces evdev codes and the compositor owns the keymap, so
+    /// there is no keysym to remap here, and key strokes only type the right
+    /// characters on a US layout. Text on hosts configured with another
+    /// layout, or with characters the US layout lacks, fails with
+    /// [`InjectionError::UnsupportedEvent`] before anything is typed.
+    async fn type_text(&mut self, text: &str) -> Result<(), InjectionError> {
+        if let Some(xtest) = &self.xtest {
+            return xtest.type_text(text).await;
+        }
+        if !self.us_layout {
+            warn!("uinput: cannot type text on a keyboard layout other than us");
+            return Err(InjectionError::UnsupportedEvent);
+        }
+
+        let strokes = text
+            .chars()
+            .map(|c| {
+                us_layout_key(c).ok_or_else(|| {
+                    warn!("uinput: cannot type {:?} without XTest", c);
+                    InjectionError::UnsupportedEvent
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let shift = KeyCode::LEFT_SHIFT.0 as u16;
+        for (key, shifted) in strokes {
+            let code = key.0 as u16;
+            let mut events = Vec::with_capacity(4);
+            if shifted {
+                events.push(EvdevEvent::new(EventType::KEY, shift, 1));
+            }
+            events.push(EvdevEvent::new(EventType::KEY, code, 1));
+            events.push(EvdevEvent::new(EventType::KEY, code, 0));
+            if shifted {
+                events.push(EvdevEvent::new(EventType::KEY, shift, 0));
+            }
+            self.keyboard.emit(&events).map_err(inject_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl InputInjector for UinputInputInjector {
+    async fn inject(&mut self, event: InputEvent) -> Result<(), InjectionError> {
+        match event {
+            InputEvent::MouseMove { x, y } => {
+                let x = x.clamp(0, self.screen_size.0 as i32 - 1);
+                let y = y.clamp(0, self.screen_size.1 as i32 - 1);
+                self.pointer
+                    .emit(&[
+                        EvdevEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_X.0, x),
+                        EvdevEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_Y.0, y),
+                    ])
+                    .map_err(inject_error)
+            }
+            InputEvent::MouseButton { button, pressed } => {
+                self.pointer
+                    .emit(&[EvdevEvent::new(EventType::KEY, evdev_button(button).code(), pressed as i32)])
+                    .map_err(inject_error)
+            }
+            InputEvent::MouseScroll { delta_x, delta_y } => {
+                let mut events = Vec::with_capacity(2);
+                if delta_y != 0 {
+                    events.push(EvdevEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, delta_y));
+                }
+                if delta_x != 0 {
+                    events.push(EvdevEvent::new(EventType::RELATIVE, RelativeAxisType::REL_HWHEEL.0, delta_x));
+                }
+                if events.is_empty() {
+                    return Ok(());
+                }
+                self.pointer.emit(&events).map_err(inject_error)
+            }
+            InputEvent::KeyPress { key, pressed } => {
+                let code = key
+                    .to_evdev()
+                    .filter(|code| (1..=MAX_KEY_CODE).contains(code))
+                    .ok_or_else(|| InjectionError::InvalidEvent(format!("No evdev key for {:?}", key)))?;
+                self.keyboard
+                    .emit(&[EvdevEvent::new(EventType::KEY, code, pressed as i32)])
+                    .map_err(inject_error)
+            }
+            InputEvent::Text { text } => self.type_text(&text).await,
+        }
+    }
+}
+
+/// Whether `/dev/uinput` exists and is writable by this process
+pub fn uinput_available() -> bool {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/uinput")
+        .is_ok()
+}
+
+/// XTest injector to type text with, on X11 sessions only: under Wayland, XTest
+/// only reaches X clients
+fn xtest_for_text() -> Option<LinuxInputInjector> {
+    if std::env::var_os("DISPLAY").is_none() || std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        return None;
+    }
+    LinuxInputInjector::new()
+        .map_err(|e| debug!("uinput: typing text without XTest: {}", e))
+        .ok()
+}
+
+/// Keyboard layout the host is configured with, from `XKB_DEFAULT_LAYOUT` or
+/// `/etc/default/keyboard`; `None` if neither sets one, leaving xkb's default, us
+fn configured_layout() -> Option<String> {
+    let layouts = match std::env::var("XKB_DEFAULT_LAYOUT") {
+        Ok(layouts) => layouts,
+        Err(_) => keyboard_defaults_layout(&std::fs::read_to_string("/etc/default/keyboard").ok()?)?,
+    };
+    first_layout(&layouts)
+}
+
+/// `XKBLAYOUT` of a `/etc/default/keyboard` file
+fn keyboard_defaults_layout(defaults: &str) -> Option<String> {
+    defaults.lines().find_map(|line| {
+        let value = line.trim().strip_prefix("XKBLAYOUT=")?;
+        Some(value.trim_matches(|c| c == '"' || c == '\'').to_string())
+    })
+}
+
+/// The first of comma-separated xkb layouts, the one active by default
+fn first_layout(layouts: &str) -> Option<String> {
+    layouts
+        .split(',')
+        .next()
+        .map(str::trim)
+        .filter(|layout| !layout.is_empty())
+        .map(str::to_string)
+}
+
+fn evdev_button(button: MouseButton) -> Key {
+    match button {
+        MouseButton::Left => Key::BTN_LEFT,
+        MouseButton::Right => Key::BTN_RIGHT,
+        MouseButton::Middle => Key::BTN_MIDDLE,
+        MouseButton::X1 => Key::BTN_SIDE,
+        MouseButton::X2 => Key::BTN_EXTRA,
+    }
+}
+
+/// Size of the desktop spanning every connected DRM connector, each at its
+/// preferred mode
+fn detect_screen_size() -> Option<(u32, u32)> {
+    let entries = std::fs::read_dir("/sys/class/drm").ok()?;
+
+    let sizes = entries.flatten().filter_map(|entry| {
+        let path = entry.path();
+        match std::fs::read_to_string(path.join("status")) {
+            Ok(status) if status.trim() == "connected" => {}
+            _ => return None,
+        }
+        std::fs::read_to_string(path.join("modes")).ok()?.lines().next().and_then(parse_mode)
+    });
+    combined_bounds(sizes)
+}
+
+/// Bounds of outputs of the given sizes laid out side by side, left to right
+///
+/// DRM does not know where the compositor places each output; side by side is
+/// the default layout of the common compositors.
+fn combined_bounds(sizes: impl IntoIterator<Item = (u32, u32)>) -> Option<(u32, u32)> {
+    sizes
+        .into_iter()
+        .reduce(|(width, height), (next_width, next_height)| (width + next_width, height.max(next_height)))
+}
+
+/// Parse a DRM mode name such as `1920x1080` or `1920x1080i`
+fn parse_mode(mode: &str) -> Option<(u32, u32)> {
+    let (width, height) = mode.trim().split_once('x')?;
+    let height = height.trim_end_matches(|c: char| !c.is_ascii_digit());
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+fn init_error(e: io::Error) -> InjectionError {
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => InjectionError::PermissionDenied,
+        _ => InjectionError::InitializationFailed(format!("uinput: {}", e)),
+    }
+}
+
+fn inject_error(e: io::Error) -> InjectionError {
+    InjectionError::InjectionFailed(format!("uinput: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev::{Device, InputEventKind};
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("1920x1080"), Some((1920, 1080)));
+        assert_eq!(parse_mode("1280x720i\n"), Some((1280, 720)));
+        assert_eq!(parse_mode("garbage"), None);
+    }
+
+    #[test]
+    fn test_configured_layout_parsing() {
+        let defaults = "XKBMODEL=\"pc105\"\nXKBLAYOUT=\"de,us\"\nXKBVARIANT=\"\"\n";
+        let layouts = keyboard_defaults_layout(defaults).unwrap();
+        assert_eq!(first_layout(&layouts).as_deref(), Some("de"));
+        assert_eq!(first_layout("us").as_deref(), Some("us"));
+        assert_eq!(first_layout(""), None);
+        assert_eq!(keyboard_defaults_layout("XKBMODEL=pc105\n"), None);
+    }
+
+    #[test]
+    fn test_combined_bounds() {
+        assert_eq!(combined_bounds([]), None);
+        assert_eq!(combined_bounds([(1920, 1080)]), Some((1920, 1080)));
+        assert_eq!(combined_bounds([(1920, 1080), (2560, 1440)]), Some((4480, 1440)));
+    }
+
+    fn open_node(device: &mut VirtualDevice) -> Device {
+        let path = device
+            .enumerate_dev_nodes_blocking()
+            .unwrap()
+            .next()
+            .expect("virtual device has no event node")
+            .unwrap();
+        Device::open(path).unwrap()
+    }
+
+    /// Reads the injected events back from the created evdev nodes (needs write access to /dev/uinput)
+    #[tokio::test]
+    async fn test_events_reach_evdev_node() {
+        if !uinput_available() {
+            eprintln!("/dev/uinput not writable, skipping uinput test");
+            return;
+        }
+
+        let mut injector = UinputInputInjector::with_screen_size(800, 600).unwrap();
+        let mut keyboard = open_node(&mut injector.keyboard);
+        let mut pointer = open_node(&mut injector.pointer);
+
+        injector.inject(InputEvent::KeyPress { key: KeyCode::A, pressed: true }).await.unwrap();
+        let event = keyboard.fetch_events().unwrap().next().unwrap();
+        assert_eq!(event.kind(), InputEventKind::Key(Key::new(KeyCode::A.0 as u16)));
+        assert_eq!(event.value(), 1);
+
+        injector.inject(InputEvent::MouseMove { x: 10_000, y: 20 }).await.unwrap();
+        let events: Vec<_> = pointer.fetch_events().unwrap().collect();
+        assert!(events.iter().any(|e| e.kind() == InputEventKind::AbsAxis(AbsoluteAxisType::ABS_X) && e.value() == 799));
+        assert!(events.iter().any(|e| e.kind() == InputEventKind::AbsAxis(AbsoluteAxisType::ABS_Y) && e.value() == 20));
+
+        injector.inject(InputEvent::MouseButton { button: MouseButton::X1, pressed: true }).await.unwrap();
+        let event = pointer.fetch_events().unwrap().next().unwrap();
+        assert_eq!(event.kind(), InputEventKind::Key(Key::BTN_SIDE));
+    }
+}
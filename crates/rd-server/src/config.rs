@@ -1,12 +1,38 @@
 use serde::{Deserialize, Serialize};
-use figment::{Figment, providers::{Format, Toml, Env}};
+use figment::{Figment, providers::{Format, Serialized, Toml, Env}};
 use anyhow::Result;
+use rd_transport::QuicTuning;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub bind_address: String,
     pub max_sessions: usize,
     pub relay_enabled: bool,
+    /// Secret device tokens are signed with; without one every device is accepted
+    pub auth_secret: Option<String>,
+    /// File listing revoked token ids, shared with `rd-cli token revoke`
+    pub revocation_list: String,
+    /// SQLite database keeping sessions and their history; in memory if unset
+    pub session_store: Option<String>,
+    /// PEM certificate the server presents; generated with `tls_key` if neither exists
+    pub tls_cert: String,
+    /// PEM private key of `tls_cert`
+    pub tls_key: String,
+    /// Require agents to present a certificate issued by the device CA; devices
+    /// without one can only ask to enroll
+    pub mutual_tls: bool,
+    /// Certificate of the CA issuing device certificates; generated with
+    /// `device_ca_key` if neither exists
+    pub device_ca_cert: String,
+    /// PEM private key of `device_ca_cert`
+    pub device_ca_key: String,
+    /// Enrollment requests, shared with `rd-cli enroll`
+    pub enrollment_dir: String,
+    /// Seconds a viewer that lost its connection has to resume its session
+    /// before it ends; 0 ends it right away
+    pub resume_grace_secs: u64,
+    /// QUIC transport parameters, the `[server.quic]` table
+    pub quic: QuicTuning,
 }
 
 impl Default for ServerConfig {
@@ -15,18 +41,73 @@ impl Default for ServerConfig {
             bind_address: "0.0.0.0:4433".to_string(),
             max_sessions: 100,
             relay_enabled: true,
+            auth_secret: None,
+            revocation_list: "config/revoked_tokens".to_string(),
+            session_store: None,
+            tls_cert: "config/server.crt".to_string(),
+            tls_key: "config/server.key".to_string(),
+            mutual_tls: false,
+            device_ca_cert: "config/device_ca.crt".to_string(),
+            device_ca_key: "config/device_ca.key".to_string(),
+            enrollment_dir: "data/enrollment".to_string(),
+            resume_grace_secs: 60,
+            quic: QuicTuning::default(),
         }
     }
 }
 
 impl ServerConfig {
-    pub fn load() -> Result<Self> {
-        let config: ServerConfig = Figment::new()
-            .merge(Toml::file("config/server.toml"))
-            .merge(Env::prefixed("RD_SERVER_"))
-            .extract()
-            .unwrap_or_default();
+    /// Load the `[server]` table of `path`, overridden by `RD_SERVER_*` variables
+    ///
+    /// A missing file means defaults; a malformed one is an error rather than
+    /// silently falling back, since that would drop settings such as the auth
+    /// secret.
+    pub fn load(path: &str) -> Result<Self> {
+        let config = Figment::from(Serialized::defaults(ServerConfig::default()))
+            .merge(Toml::file(path).nested())
+            .merge(Env::prefixed("RD_SERVER_").global())
+            .select("server")
+            .extract()?;
         
         Ok(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_reads_server_table() {
+        let path = std::env::temp_dir().join(format!("rd-server-{}.toml", std::process::id()));
+        std::fs::write(&path, "[server]\nbind_address = \"127.0.0.1:5000\"\nauth_secret = \"s3cret\"\n").unwrap();
+
+        let config = ServerConfig::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.bind_address, "127.0.0.1:5000");
+        assert_eq!(config.auth_secret.as_deref(), Some("s3cret"));
+        // Unset keys keep their defaults
+        assert!(config.relay_enabled);
+    }
+
+    #[test]
+    fn test_load_reads_quic_table() {
+        let path = std::env::temp_dir().join(format!("rd-server-quic-{}.toml", std::process::id()));
+        std::fs::write(&path, "[server.quic]\nidle_timeout_ms = 60000\ncongestion_controller = \"bbr\"\n").unwrap();
+
+        let config = ServerConfig::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.quic.idle_timeout_ms, 60_000);
+        assert_eq!(config.quic.congestion_controller, rd_transport::quic::CongestionController::Bbr);
+        assert_eq!(config.quic.initial_rtt_ms, QuicTuning::default().initial_rtt_ms);
+    }
+
+    #[test]
+    fn test_missing_file_means_defaults() {
+        let config = ServerConfig::load("does/not/exist.toml").unwrap();
+        assert_eq!(config.bind_address, ServerConfig::default().bind_address);
+        assert!(config.auth_secret.is_none());
+    }
+}
@@ -1,83 +1,850 @@
+use std::time::Duration;
 use quinn::Connection;
-use tracing::{info, warn, error};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn, error};
 use anyhow::Result;
+use chrono::Utc;
 
 use rd_transport::{QuicTransport, ProtocolMessage};
-use rd_core::domain::ports::Transport;
+use rd_core::domain::models::{
+    self, AuthToken, Capabilities, EnrollmentStatus, PeerId, PeerRole, SessionId, WireFormat,
+};
+use rd_core::domain::error::{ApplicationError, AuthError, DomainError};
+use rd_core::domain::ports::{error_codes, Transport};
+use rd_core::domain::version::VersionRange;
 
-use crate::state::ServerState;
+use crate::state::{PeerHandle, ServerState};
+
+/// Messages queued for one peer before the server stops accepting more
+const OUTBOX_CAPACITY: usize = 64;
+
+/// Relayed frames queued for one client; newer frames are dropped beyond this
+const FRAME_QUEUE_CAPACITY: usize = 2;
+
+/// How long a rejected peer gets to read the error before the connection is dropped
+const REJECT_GRACE: Duration = Duration::from_secs(1);
+
+/// The peer on the other side of a connection, known after its Hello
+struct Peer {
+    device_id: String,
+    role: PeerRole,
+    capabilities: Capabilities,
+    /// Token the peer authenticated with, presented again for every session it opens
+    token: AuthToken,
+    handle: PeerHandle,
+}
+
+impl Peer {
+    fn peer_id(&self) -> PeerId {
+        PeerId::new(self.device_id.clone())
+    }
+}
 
 /// Handle incoming connection
 pub async fn handle_connection(connection: Connection, state: ServerState) -> Result<()> {
     let remote_addr = connection.remote_address();
     info!("Handling connection from {}", remote_addr);
-    
-    let mut transport = QuicTransport::new(connection).await?;
-    
+
+    let certified = rd_transport::quic::peer_device_id(&connection);
+    if let Some(device_id) = &certified {
+        debug!("{} presented a certificate for {}", remote_addr, device_id);
+    }
+
+    let transport = QuicTransport::accept(connection).await?;
+    serve(transport, state, certified).await
+}
+
+/// Run the server side of a connection over any transport, until the peer leaves
+///
+/// `certified` is the device id from the peer's verified client certificate;
+/// the peer's `Hello` must then name the same device.
+pub async fn serve<T: Transport>(mut transport: T, state: ServerState, certified: Option<String>) -> Result<()> {
+
     // Wait for Hello message
-    match transport.receive().await {
-        Ok(ProtocolMessage::Hello { version: _, device_id, platform }) => {
-            info!("Received Hello from device: {} (platform: {:?})", device_id, platform);
-            
-            // TODO: Validate version
-            // TODO: Authenticate device
-            
-            // Register agent
-            let peer_id = rd_core::domain::models::PeerId::new(device_id.clone());
-            state.register_agent(device_id, peer_id);
-            
-            // Handle subsequent messages
-            loop {
-                match transport.receive().await {
-                    Ok(msg) => {
-                        if let Err(e) = handle_message(msg, &state, &mut transport).await {
-                            error!("Error handling message: {}", e);
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        warn!("Transport error: {}", e);
-                        break;
-                    }
-                }
-            }
+    let (versions, device_id, platform, role) = match transport.receive().await {
+        Ok(ProtocolMessage::Hello { versions, device_id, platform, role }) => {
+            info!("Received Hello from device: {} ({:?}, platform: {:?})", device_id, role, platform);
+            (versions, device_id, platform, role)
         }
         Ok(msg) => {
             warn!("Expected Hello, got: {:?}", msg);
+            return Ok(());
         }
         Err(e) => {
             error!("Failed to receive Hello: {}", e);
+            return Ok(());
+        }
+    };
+
+    let Some(version) = VersionRange::SUPPORTED.negotiate(&versions) else {
+        warn!("Rejecting {}: speaks {}, server speaks {}", device_id, versions, VersionRange::SUPPORTED);
+        reject(&mut transport, ProtocolMessage::Error {
+            code: error_codes::INCOMPATIBLE_VERSION,
+            message: format!(
+                "Protocol {} not supported, server speaks {}",
+                versions,
+                VersionRange::SUPPORTED
+            ),
+        }).await;
+        return Ok(());
+    };
+    if let Some(certified) = certified.as_ref().filter(|certified| **certified != device_id) {
+        warn!("Rejecting {}: its certificate was issued to {}", device_id, certified);
+        reject(&mut transport, ProtocolMessage::Error {
+            code: error_codes::UNAUTHORIZED,
+            message: format!("Certificate was issued to {}, not {}", certified, device_id),
+        }).await;
+        return Ok(());
+    }
+    transport.send(ProtocolMessage::HelloAck { version }).await?;
+    debug!("{} speaks protocol v{}", device_id, version);
+
+    let (capabilities, wire_formats) = match transport.receive().await {
+        Ok(ProtocolMessage::Capabilities { capabilities, wire_formats }) => (capabilities, wire_formats),
+        Ok(msg) => {
+            warn!("Expected Capabilities from {}, got: {:?}", device_id, msg);
+            reject(&mut transport, ProtocolMessage::Error {
+                code: error_codes::BAD_REQUEST,
+                message: "Expected Capabilities after HelloAck".to_string(),
+            }).await;
+            return Ok(());
         }
+        Err(e) => {
+            error!("Failed to receive capabilities from {}: {}", device_id, e);
+            return Ok(());
+        }
+    };
+    debug!("{} capabilities: {:?}", device_id, capabilities);
+
+    // The last message in bincode; both ends switch right after it
+    let format = WireFormat::negotiate(&wire_formats);
+    transport.send(ProtocolMessage::WireFormatSelected { format }).await?;
+    transport.set_wire_format(format);
+    debug!("{} uses wire format {:?}", device_id, format);
+
+    // Nothing is registered or accepted from the peer before it authenticates
+    let result = match transport.receive().await {
+        Ok(ProtocolMessage::Auth { token }) => state
+            .authenticate_peer(&device_id, role, certified.as_deref(), &token)
+            .await
+            .map(|()| token),
+        Ok(ProtocolMessage::EnrollRequest { csr }) => {
+            // Answer and hang up: the device reconnects, with its certificate once issued
+            let status = enroll(&state, &device_id, &csr);
+            reject(&mut transport, ProtocolMessage::EnrollResponse { status }).await;
+            return Ok(());
+        }
+        Ok(msg) => {
+            warn!("Expected Auth from {}, got: {:?}", device_id, msg);
+            Err(AuthError::AuthenticationFailed("Authentication required".to_string()))
+        }
+        Err(e) => {
+            error!("Failed to receive Auth from {}: {}", device_id, e);
+            return Ok(());
+        }
+    };
+    let token = match result {
+        Ok(token) => token,
+        Err(e) => {
+            warn!("Rejecting {}: {}", device_id, e);
+            reject(&mut transport, ProtocolMessage::AuthResponse {
+                success: false,
+                session_id: None,
+                error: Some(e.to_string()),
+            }).await;
+            return Ok(());
+        }
+    };
+    let (messages, mut outbox) = mpsc::channel(OUTBOX_CAPACITY);
+    let (frames, mut frame_queue) = mpsc::channel(FRAME_QUEUE_CAPACITY);
+    let peer = Peer { device_id, role, capabilities, token, handle: PeerHandle::new(messages, frames) };
+
+    if state.connect(peer.role, peer.device_id.clone(), peer.handle.clone()) {
+        warn!("{} connected again as {:?}; closing its previous connection", peer.device_id, peer.role);
     }
-    
+    if peer.role == PeerRole::Agent {
+        state.register_agent(models::Peer {
+            id: peer.peer_id(),
+            device_id: peer.device_id.clone(),
+            display_name: peer.device_id.clone(),
+            platform,
+            capabilities: peer.capabilities.clone(),
+            last_seen: Utc::now(),
+        });
+    }
+
+    // Only now, so a request the peer sends right after being accepted finds it registered
+    let accepted = transport.send(ProtocolMessage::AuthResponse {
+        success: true,
+        session_id: None,
+        error: None,
+    }).await;
+    if let Err(e) = accepted {
+        warn!("Failed to accept {}: {}", peer.device_id, e);
+        state.disconnect(peer.role, &peer.device_id, &peer.handle);
+        return Ok(());
+    }
+
+    // Handle subsequent messages, interleaved with messages other peers queued for us
+    let mut left = false;
+    loop {
+        tokio::select! {
+            // Relayed frames come last, so a busy agent cannot starve control
+            // messages or the peer's own input
+            biased;
+
+            _ = peer.handle.replaced.notified() => {
+                info!("Closing the previous connection of {}", peer.device_id);
+                reject(&mut transport, ProtocolMessage::Disconnect).await;
+                break;
+            }
+            Some(msg) = outbox.recv() => {
+                if let Err(e) = transport.send(msg).await {
+                    warn!("Failed to deliver message to {}: {}", peer.device_id, e);
+                    break;
+                }
+            }
+            result = transport.receive() => match result {
+                Ok(ProtocolMessage::Disconnect) => {
+                    info!("{} disconnecting", peer.device_id);
+                    left = true;
+                    break;
+                }
+                Ok(msg) => {
+                    if peer.role == PeerRole::Agent {
+                        state.touch_agent(&peer.device_id);
+                    }
+                    if let Err(e) = handle_message(msg, &state, &peer, &mut transport).await {
+                        error!("Error handling message: {}", e);
+                        break;
+                    }
+                }
+                Err(e) => {
+                    warn!("Transport error: {}", e);
+                    break;
+                }
+            },
+            Some(frame) = frame_queue.recv() => {
+                if let Err(e) = transport.send(frame).await {
+                    warn!("Failed to deliver frame to {}: {}", peer.device_id, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    if state.disconnect(peer.role, &peer.device_id, &peer.handle) {
+        // A client that did not say goodbye may be back shortly
+        if left || peer.role == PeerRole::Agent || state.resume_grace.is_zero() {
+            end_sessions_of(&state, &peer).await;
+        } else {
+            pause_sessions_of(&state, &peer).await;
+        }
+    }
+
     Ok(())
 }
 
-async fn handle_message(
+/// Send a final error or answer and give the peer a moment to read it before
+/// hanging up
+///
+/// Closing right away would discard the message along with the unsent stream data.
+async fn reject<T: Transport>(transport: &mut T, error: ProtocolMessage) {
+    if let Err(e) = transport.send(error).await {
+        debug!("Failed to send rejection: {}", e);
+        return;
+    }
+    // The peer closes the connection once it has read the error
+    let _ = tokio::time::timeout(REJECT_GRACE, async {
+        while transport.receive().await.is_ok() {}
+    }).await;
+}
+
+/// Queue or grant the certificate signing request of `device_id`
+fn enroll(state: &ServerState, device_id: &str, csr: &str) -> EnrollmentStatus {
+    let Some(enrollment) = &state.enrollment else {
+        return EnrollmentStatus::Refused { reason: "This server does not enroll devices".to_string() };
+    };
+    enrollment.queue.submit(&enrollment.ca, device_id, csr).unwrap_or_else(|e| {
+        error!("Failed to handle enrollment of {}: {}", device_id, e);
+        EnrollmentStatus::Refused { reason: "Failed to handle the request".to_string() }
+    })
+}
+
+async fn handle_message<T: Transport>(
     msg: ProtocolMessage,
-    _state: &ServerState,
-    transport: &mut QuicTransport,
+    state: &ServerState,
+    peer: &Peer,
+    transport: &mut T,
 ) -> Result<()> {
     match msg {
         ProtocolMessage::Heartbeat { timestamp } => {
             // Respond with heartbeat
             transport.send(ProtocolMessage::Heartbeat { timestamp }).await?;
         }
+        ProtocolMessage::SessionRequest { target_device } if peer.role == PeerRole::Client => {
+            info!("Session request from {} for device: {}", peer.device_id, target_device);
+
+            let reply = create_session(state, peer, &target_device).await;
+            transport.send(reply).await?;
+        }
         ProtocolMessage::SessionRequest { target_device } => {
-            info!("Session request for device: {}", target_device);
-            
-            // TODO: Create session
-            // TODO: Notify target agent
-            // TODO: Return session info
+            warn!("Refusing session request from agent {} for device: {}", peer.device_id, target_device);
+            transport.send(ProtocolMessage::Error {
+                code: error_codes::BAD_REQUEST,
+                message: "Only clients may request sessions".to_string(),
+            }).await?;
+        }
+        ProtocolMessage::SessionResume { session_id, resume_token } if peer.role == PeerRole::Client => {
+            info!("{} resumes session {}", peer.device_id, session_id);
+
+            let reply = resume_session(state, peer, session_id, resume_token).await;
+            transport.send(reply).await?;
+        }
+        ProtocolMessage::ListAgents => {
+            let agents = state.list_agents();
+            debug!("Listing {} agents for {}", agents.len(), peer.device_id);
+            transport.send(ProtocolMessage::AgentList { agents }).await?;
+        }
+        ProtocolMessage::SessionEnd { session_id, reason } => {
+            info!("{} ended session {}: {}", peer.device_id, session_id, reason);
+            end_session(state, session_id, &peer.peer_id(), &reason).await;
         }
-        ProtocolMessage::Disconnect => {
-            info!("Client disconnecting");
-            return Err(anyhow::anyhow!("Client disconnected"));
+        msg @ ProtocolMessage::ScreenFrame { .. } if peer.role == PeerRole::Agent => {
+            relay_frame(state, peer, msg).await;
+        }
+        msg @ ProtocolMessage::InputEvent { .. } if peer.role == PeerRole::Client => {
+            relay_input(state, peer, msg).await;
         }
         _ => {
             warn!("Unexpected message: {:?}", msg);
         }
     }
-    
+
     Ok(())
 }
+
+/// Create a session with `target_device`, notify the agent and build the reply for the client
+async fn create_session(state: &ServerState, peer: &Peer, target_device: &str) -> ProtocolMessage {
+    let session = match state.create_session(&peer.token, &peer.capabilities, target_device).await {
+        Ok(session) => session,
+        Err(ApplicationError::Domain(DomainError::PeerNotFound(_))) => {
+            warn!("Session request for unknown agent: {}", target_device);
+            return ProtocolMessage::Error {
+                code: error_codes::AGENT_NOT_FOUND,
+                message: format!("Agent not found: {}", target_device),
+            };
+        }
+        Err(ApplicationError::Auth(e)) => {
+            warn!("Refusing session for {}: {}", peer.device_id, e);
+            return ProtocolMessage::Error {
+                code: error_codes::UNAUTHORIZED,
+                message: e.to_string(),
+            };
+        }
+        Err(e) => {
+            error!("Failed to create session for {}: {}", peer.device_id, e);
+            return ProtocolMessage::Error {
+                code: error_codes::INTERNAL_ERROR,
+                message: "Failed to create session".to_string(),
+            };
+        }
+    };
+
+    let Some(codec) = session.capabilities.codec() else {
+        warn!("{} decodes none of the codecs {} encodes", peer.device_id, target_device);
+        discard_session(state, session.id).await;
+        return ProtocolMessage::Error {
+            code: error_codes::NO_COMMON_CODEC,
+            message: format!("Agent {} encodes no codec this client decodes", target_device),
+        };
+    };
+
+    let notified = state.send_to(PeerRole::Agent, target_device, ProtocolMessage::SessionCreated {
+        session_id: session.id,
+        endpoint: peer.device_id.clone(),
+        capabilities: session.capabilities.clone(),
+        resume_token: None,
+    });
+    if !notified {
+        warn!("Agent {} did not accept session {}", target_device, session.id);
+        discard_session(state, session.id).await;
+        return ProtocolMessage::Error {
+            code: error_codes::AGENT_UNAVAILABLE,
+            message: format!("Agent unavailable: {}", target_device),
+        };
+    }
+
+    if let Err(e) = state.activate_session(&session).await {
+        error!("Failed to activate session {}: {}", session.id, e);
+        discard_session(state, session.id).await;
+        return ProtocolMessage::Error {
+            code: error_codes::INTERNAL_ERROR,
+            message: "Failed to create session".to_string(),
+        };
+    }
+    info!("Session {} created: {} -> {} ({:?})", session.id, peer.device_id, target_device, codec);
+
+    ProtocolMessage::SessionCreated {
+        session_id: session.id,
+        endpoint: target_device.to_string(),
+        capabilities: session.capabilities,
+        resume_token: Some(state.issue_resume_token(session.id)),
+    }
+}
+
+/// Give a paused session back to its client and build the reply for it
+///
+/// The agent is asked for a keyframe, as the client starts over without any
+/// frame to decode the next ones against.
+async fn resume_session(state: &ServerState, peer: &Peer, session_id: SessionId, resume_token: String) -> ProtocolMessage {
+    let session = match state.resume_session(session_id, &peer.peer_id(), &resume_token).await {
+        Ok(session) => session,
+        Err(ApplicationError::Domain(DomainError::SessionNotFound(_))) => {
+            warn!("{} cannot resume session {}: ended or not its own", peer.device_id, session_id);
+            return ProtocolMessage::Error {
+                code: error_codes::SESSION_EXPIRED,
+                message: format!("Session {} cannot be resumed", session_id),
+            };
+        }
+        Err(e) => {
+            error!("Failed to resume session {} for {}: {}", session_id, peer.device_id, e);
+            return ProtocolMessage::Error {
+                code: error_codes::INTERNAL_ERROR,
+                message: "Failed to resume session".to_string(),
+            };
+        }
+    };
+
+    if !state.send_to(PeerRole::Agent, &session.agent.0, ProtocolMessage::KeyframeRequest { session_id }) {
+        warn!("Agent {} did not take the keyframe request for session {}", session.agent, session_id);
+    }
+    info!("Session {} resumed: {} -> {}", session_id, peer.device_id, session.agent);
+
+    ProtocolMessage::SessionCreated {
+        session_id,
+        endpoint: session.agent.0,
+        capabilities: session.capabilities,
+        resume_token: Some(resume_token),
+    }
+}
+
+/// End a session that never got going
+async fn discard_session(state: &ServerState, session_id: SessionId) {
+    if let Err(e) = state.sessions.end_session(session_id).await {
+        warn!("Failed to discard session {}: {}", session_id, e);
+    }
+}
+
+/// Active sessions `peer` takes part in in its role, or none if they cannot
+/// be looked up
+async fn sessions_of(state: &ServerState, peer: &Peer) -> Vec<models::Session> {
+    let peer_id = peer.peer_id();
+    let sessions = state.sessions.sessions_of(&peer_id).await.unwrap_or_else(|e| {
+        error!("Failed to look up sessions of {}: {}", peer_id, e);
+        Vec::new()
+    });
+    sessions
+        .into_iter()
+        .filter(|session| match peer.role {
+            PeerRole::Agent => session.agent == peer_id,
+            PeerRole::Client => session.client == peer_id,
+        })
+        .collect()
+}
+
+/// Forward an agent's frame to the client of every session it serves
+///
+/// Sessions are looked up in the routes kept by `state`, not the repository,
+/// as this runs for every frame.
+///
+/// Frames for a client that has not drained its queue are dropped, so one slow
+/// viewer neither delays the agent nor other sessions.
+async fn relay_frame(state: &ServerState, peer: &Peer, frame: ProtocolMessage) {
+    if !state.relay_enabled {
+        return;
+    }
+
+    for (session_id, client) in state.clients_of(&peer.peer_id()) {
+        if !state.relay_frame(&client.0, frame.clone()) {
+            debug!("Dropped frame for session {}: client is behind", session_id);
+        }
+    }
+}
+
+/// Forward a client's input to the agent of the session it names
+///
+/// Input is never dropped for lack of room: waiting for the agent's queue pushes
+/// back on the client's connection instead. Input a session did not agree on,
+/// such as any input to a view-only agent, is discarded.
+async fn relay_input(state: &ServerState, peer: &Peer, input: ProtocolMessage) {
+    let ProtocolMessage::InputEvent { session_id, event, .. } = &input else {
+        return;
+    };
+    if !state.relay_enabled {
+        return;
+    }
+
+    let client = peer.peer_id();
+    let Some(route) = state.route(*session_id).filter(|route| route.client == client) else {
+        warn!("Input from {} for session {} it has no active part in", peer.device_id, session_id);
+        return;
+    };
+    if !route.capabilities.accepts(event) {
+        debug!("Discarding {:?} input from {}: not accepted by session {}", event.kind(), peer.device_id, session_id);
+        return;
+    }
+
+    let Some(agent) = state.sender(PeerRole::Agent, &route.agent.0) else {
+        return;
+    };
+    if agent.send(input).await.is_err() {
+        debug!("Agent went away while relaying input from {}", peer.device_id);
+    }
+}
+
+/// End a session `requester` takes part in and tell the other end
+async fn end_session(state: &ServerState, session_id: SessionId, requester: &PeerId, reason: &str) {
+    let Ok(session) = state.sessions.get_session(session_id).await else {
+        return;
+    };
+    if session.client != *requester && session.agent != *requester {
+        warn!("{} tried to end session {} it is not part of", requester, session_id);
+        return;
+    }
+
+    // Both ends may hang up at once; only the first one notifies the other
+    if let Err(e) = state.sessions.end_session(session_id).await {
+        debug!("Session {} already ended: {}", session_id, e);
+        return;
+    }
+    state.forget_session(session_id);
+    let (other, role) = if session.client == *requester {
+        (&session.agent, PeerRole::Agent)
+    } else {
+        (&session.client, PeerRole::Client)
+    };
+    state.send_to(role, &other.0, ProtocolMessage::SessionEnd {
+        session_id,
+        reason: reason.to_string(),
+    });
+}
+
+/// End every session of a peer that went away
+async fn end_sessions_of(state: &ServerState, peer: &Peer) {
+    let peer_id = peer.peer_id();
+    for session in sessions_of(state, peer).await {
+        info!("Ending session {}: {} disconnected", session.id, peer.device_id);
+        end_session(state, session.id, &peer_id, &format!("{} disconnected", peer.device_id)).await;
+    }
+}
+
+/// Pause every session of a client whose connection was lost, and end the ones
+/// it has not resumed once the grace period is over
+///
+/// The agent is told, so it releases the input the client held.
+async fn pause_sessions_of(state: &ServerState, peer: &Peer) {
+    let client = peer.peer_id();
+    for session in sessions_of(state, peer).await {
+        let deadline = match state.pause_session(session.id).await {
+            Ok(deadline) => deadline,
+            Err(e) => {
+                error!("Failed to pause session {}: {}", session.id, e);
+                end_session(state, session.id, &client, &format!("{} disconnected", peer.device_id)).await;
+                continue;
+            }
+        };
+        info!("Session {} paused: {} lost its connection", session.id, peer.device_id);
+        // Keys the viewer held must not stay down for the whole grace period
+        if !state.send_to(PeerRole::Agent, &session.agent.0, ProtocolMessage::SessionPaused { session_id: session.id }) {
+            warn!("Agent {} did not take the pause of session {}", session.agent, session.id);
+        }
+
+        let state = state.clone();
+        let client = client.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(deadline).await;
+            if state.pause_expired(session.id) {
+                info!("Ending session {}: {} did not come back", session.id, client);
+                end_session(&state, session.id, &client, &format!("{} disconnected", client)).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_core::domain::models::{AuthToken, FrameFormat, InputEvent, KeyCode, SessionStatus};
+    use rd_transport::{LinkProfile, LoopbackTransport};
+
+    /// Connect a peer to a server task over a loopback link and get it accepted
+    async fn connect(state: &ServerState, device_id: &str, role: PeerRole, link: LinkProfile) -> LoopbackTransport {
+        let (mut peer, server_end) = LoopbackTransport::pair_with(link);
+        tokio::spawn(serve(server_end, state.clone(), None));
+
+        rd_transport::hello(&mut peer, device_id, role, Capabilities::default()).await.unwrap();
+        rd_transport::authenticate(&mut peer, AuthToken::new("", device_id)).await.unwrap();
+        peer
+    }
+
+    /// Next message, skipping heartbeats
+    async fn next(peer: &mut LoopbackTransport) -> ProtocolMessage {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), peer.receive()).await {
+                Ok(Ok(ProtocolMessage::Heartbeat { .. })) => continue,
+                Ok(Ok(message)) => return message,
+                Ok(Err(e)) => panic!("peer connection failed: {}", e),
+                Err(_) => panic!("nothing received"),
+            }
+        }
+    }
+
+    fn frame(sequence: u64) -> ProtocolMessage {
+        ProtocolMessage::ScreenFrame {
+            sequence,
+            timestamp: 0,
+            data: vec![7; 64],
+            width: 4,
+            height: 4,
+            format: FrameFormat::Jpeg,
+        }
+    }
+
+    /// Ask for a session with `agent` and return its id and resume token once
+    /// both ends heard of it
+    async fn open_session(client: &mut LoopbackTransport, agent: &mut LoopbackTransport) -> (SessionId, String) {
+        client.send(ProtocolMessage::SessionRequest { target_device: "host".into() }).await.unwrap();
+        let ProtocolMessage::SessionCreated { session_id, endpoint, resume_token: Some(resume_token), .. } = next(client).await else {
+            panic!("session refused");
+        };
+        assert_eq!(endpoint, "host");
+        assert!(matches!(
+            next(agent).await,
+            ProtocolMessage::SessionCreated { session_id: id, endpoint, resume_token: None, .. }
+                if id == session_id && endpoint == "viewer"
+        ));
+        (session_id, resume_token)
+    }
+
+    #[tokio::test]
+    async fn test_session_between_agent_and_client() {
+        let state = ServerState::new();
+        let mut agent = connect(&state, "host", PeerRole::Agent, LinkProfile::default()).await;
+        let mut client = connect(&state, "viewer", PeerRole::Client, LinkProfile::default()).await;
+
+        let (session_id, _) = open_session(&mut client, &mut agent).await;
+
+        agent.send(frame(1)).await.unwrap();
+        assert!(matches!(next(&mut client).await, ProtocolMessage::ScreenFrame { sequence: 1, .. }));
+
+        let event = InputEvent::MouseMove { x: 3, y: 4 };
+        client.send(ProtocolMessage::InputEvent { session_id, timestamp: 0, event }).await.unwrap();
+        assert!(matches!(
+            next(&mut agent).await,
+            ProtocolMessage::InputEvent { session_id: id, event: InputEvent::MouseMove { x: 3, y: 4 }, .. }
+                if id == session_id
+        ));
+
+        client.send(ProtocolMessage::SessionEnd { session_id, reason: "done".into() }).await.unwrap();
+        assert!(matches!(next(&mut agent).await, ProtocolMessage::SessionEnd { reason, .. } if reason == "done"));
+        assert!(state.sessions.list_active_sessions().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_agents_cannot_request_sessions() {
+        let state = ServerState::new();
+        let _host = connect(&state, "host", PeerRole::Agent, LinkProfile::default()).await;
+        let mut agent = connect(&state, "desk", PeerRole::Agent, LinkProfile::default()).await;
+
+        agent.send(ProtocolMessage::SessionRequest { target_device: "host".into() }).await.unwrap();
+        assert!(matches!(
+            next(&mut agent).await,
+            ProtocolMessage::Error { code: error_codes::BAD_REQUEST, .. }
+        ));
+        assert!(state.sessions.list_active_sessions().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconnecting_device_closes_its_previous_connection() {
+        let state = ServerState::new();
+        let mut agent = connect(&state, "host", PeerRole::Agent, LinkProfile::default()).await;
+        let mut stale = connect(&state, "viewer", PeerRole::Client, LinkProfile::default()).await;
+        let mut client = connect(&state, "viewer", PeerRole::Client, LinkProfile::default()).await;
+
+        assert!(matches!(next(&mut stale).await, ProtocolMessage::Disconnect));
+        // The new connection takes over
+        open_session(&mut client, &mut agent).await;
+    }
+
+    #[tokio::test]
+    async fn test_device_viewing_itself_keeps_both_connections() {
+        let state = ServerState::new();
+        let mut agent = connect(&state, "desk", PeerRole::Agent, LinkProfile::default()).await;
+        let mut client = connect(&state, "desk", PeerRole::Client, LinkProfile::default()).await;
+
+        client.send(ProtocolMessage::SessionRequest { target_device: "desk".into() }).await.unwrap();
+        let ProtocolMessage::SessionCreated { session_id, .. } = next(&mut client).await else {
+            panic!("session refused");
+        };
+        assert!(matches!(next(&mut agent).await, ProtocolMessage::SessionCreated { session_id: id, .. } if id == session_id));
+
+        agent.send(frame(1)).await.unwrap();
+        assert!(matches!(next(&mut client).await, ProtocolMessage::ScreenFrame { sequence: 1, .. }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_viewer_leaving_ends_the_session() {
+        let state = ServerState::new();
+        let slow = LinkProfile::default()
+            .with_bandwidth(50_000)
+            .with_delay(Duration::from_millis(80));
+        let mut agent = connect(&state, "host", PeerRole::Agent, LinkProfile::default()).await;
+        let mut client = connect(&state, "viewer", PeerRole::Client, slow).await;
+        open_session(&mut client, &mut agent).await;
+
+        // Frames arrive late over the slow link, but in order
+        for sequence in 0..3 {
+            agent.send(frame(sequence)).await.unwrap();
+        }
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            if let ProtocolMessage::ScreenFrame { sequence, .. } = next(&mut client).await {
+                received.push(sequence);
+            }
+        }
+        assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+
+        client.close().await.unwrap();
+        assert!(matches!(
+            next(&mut agent).await,
+            ProtocolMessage::SessionEnd { reason, .. } if reason == "viewer disconnected"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_viewer_resumes_its_session_after_losing_the_connection() {
+        let state = ServerState::new().with_resume_grace(Duration::from_secs(30));
+        let mut agent = connect(&state, "host", PeerRole::Agent, LinkProfile::default()).await;
+        let mut client = connect(&state, "viewer", PeerRole::Client, LinkProfile::default()).await;
+        let (session_id, resume_token) = open_session(&mut client, &mut agent).await;
+
+        // Gone without a goodbye: the session waits for the viewer
+        client.close().await.unwrap();
+        assert!(matches!(next(&mut agent).await, ProtocolMessage::SessionPaused { session_id: id } if id == session_id));
+
+        let mut client = connect(&state, "viewer", PeerRole::Client, LinkProfile::default()).await;
+        let wrong = ProtocolMessage::SessionResume { session_id, resume_token: "guess".into() };
+        client.send(wrong).await.unwrap();
+        assert!(matches!(
+            next(&mut client).await,
+            ProtocolMessage::Error { code: error_codes::SESSION_EXPIRED, .. }
+        ));
+
+        client.send(ProtocolMessage::SessionResume { session_id, resume_token }).await.unwrap();
+        assert!(matches!(
+            next(&mut client).await,
+            ProtocolMessage::SessionCreated { session_id: id, endpoint, .. } if id == session_id && endpoint == "host"
+        ));
+        assert!(matches!(next(&mut agent).await, ProtocolMessage::KeyframeRequest { session_id: id } if id == session_id));
+
+        agent.send(frame(1)).await.unwrap();
+        assert!(matches!(next(&mut client).await, ProtocolMessage::ScreenFrame { sequence: 1, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_agent_is_told_when_a_viewer_holding_keys_drops() {
+        let state = ServerState::new().with_resume_grace(Duration::from_secs(30));
+        let mut agent = connect(&state, "host", PeerRole::Agent, LinkProfile::default()).await;
+        let mut client = connect(&state, "viewer", PeerRole::Client, LinkProfile::default()).await;
+        let (session_id, _) = open_session(&mut client, &mut agent).await;
+
+        let event = InputEvent::KeyPress { key: KeyCode::LEFT_SHIFT, pressed: true };
+        client.send(ProtocolMessage::InputEvent { session_id, timestamp: 0, event }).await.unwrap();
+        assert!(matches!(next(&mut agent).await, ProtocolMessage::InputEvent { .. }));
+
+        client.close().await.unwrap();
+        assert!(matches!(next(&mut agent).await, ProtocolMessage::SessionPaused { session_id: id } if id == session_id));
+        assert_eq!(state.sessions.get_session(session_id).await.unwrap().status, SessionStatus::Paused);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paused_session_ends_after_the_grace_period() {
+        let state = ServerState::new().with_resume_grace(Duration::from_secs(2));
+        let mut agent = connect(&state, "host", PeerRole::Agent, LinkProfile::default()).await;
+        let mut client = connect(&state, "viewer", PeerRole::Client, LinkProfile::default()).await;
+        let (session_id, resume_token) = open_session(&mut client, &mut agent).await;
+
+        client.close().await.unwrap();
+        assert!(matches!(next(&mut agent).await, ProtocolMessage::SessionPaused { .. }));
+        assert!(matches!(
+            next(&mut agent).await,
+            ProtocolMessage::SessionEnd { reason, .. } if reason == "viewer disconnected"
+        ));
+
+        let mut client = connect(&state, "viewer", PeerRole::Client, LinkProfile::default()).await;
+        client.send(ProtocolMessage::SessionResume { session_id, resume_token }).await.unwrap();
+        assert!(matches!(
+            next(&mut client).await,
+            ProtocolMessage::Error { code: error_codes::SESSION_EXPIRED, .. }
+        ));
+    }
+
+    /// State enrolling devices with a fresh CA in a scratch directory
+    fn enrolling_state(name: &str) -> (ServerState, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("rd-server-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        let ca = rd_transport::CertificateAuthority::load_or_generate(dir.join("ca.crt"), dir.join("ca.key")).unwrap();
+        let queue = rd_transport::EnrollmentQueue::new(dir.join("requests"));
+        (ServerState::new().with_enrollment(ca, queue), dir)
+    }
+
+    #[tokio::test]
+    async fn test_agents_are_identified_by_their_certificate() {
+        let (state, dir) = enrolling_state("mtls");
+        let attempt = |certified: Option<&str>, device_id: &'static str| {
+            let (mut peer, server_end) = LoopbackTransport::pair();
+            tokio::spawn(serve(server_end, state.clone(), certified.map(String::from)));
+            async move {
+                rd_transport::hello(&mut peer, device_id, PeerRole::Agent, Capabilities::default()).await?;
+                rd_transport::authenticate(&mut peer, AuthToken::new("", device_id)).await?;
+                anyhow::Ok(())
+            }
+        };
+
+        attempt(Some("host"), "host").await.unwrap();
+        assert!(state.agents.contains_key("host"));
+
+        // Without a certificate, or claiming another device's name
+        assert!(attempt(None, "laptop").await.is_err());
+        assert!(attempt(Some("host"), "laptop").await.is_err());
+        assert!(!state.agents.contains_key("laptop"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_enrollment_issues_a_certificate_once_approved() {
+        let (state, dir) = enrolling_state("enroll");
+        let csr = rd_transport::ClientIdentity::signing_request(dir.join("host.key"), "host").unwrap();
+        let enroll = || {
+            let (mut peer, server_end) = LoopbackTransport::pair();
+            tokio::spawn(serve(server_end, state.clone(), None));
+            let csr = csr.clone();
+            async move {
+                rd_transport::hello(&mut peer, "host", PeerRole::Agent, Capabilities::default()).await.unwrap();
+                rd_transport::enroll(&mut peer, csr).await.unwrap()
+            }
+        };
+
+        assert_eq!(enroll().await, EnrollmentStatus::Pending);
+        state.enrollment.as_ref().unwrap().queue.approve("host").unwrap();
+        let EnrollmentStatus::Issued { certificate } = enroll().await else {
+            panic!("no certificate issued");
+        };
+
+        use rustls::pki_types::{pem::PemObject, CertificateDer};
+        let certificate = CertificateDer::from_pem_slice(certificate.as_bytes()).unwrap();
+        assert_eq!(rd_transport::quic::device_id_of(&certificate).as_deref(), Some("host"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
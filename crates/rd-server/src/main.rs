@@ -2,11 +2,17 @@ mod config;
 mod state;
 mod handlers;
 
+use std::sync::Arc;
+use std::time::Duration;
 use clap::Parser;
-use tracing::{info, error};
-use tracing_subscriber;
+use tokio::sync::RwLock;
+use tracing::{info, warn, error};
 use anyhow::Result;
 
+use rd_core::application::TokenAuthenticator;
+use rd_storage::SqliteSessionRepository;
+use rd_transport::{CertificateAuthority, EnrollmentQueue, QuicServer, ServerIdentity};
+
 #[derive(Parser)]
 #[command(name = "rd-server")]
 #[command(version = "0.1.0")]
@@ -19,7 +25,7 @@ struct Cli {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let _cli = Cli::parse();
+    let cli = Cli::parse();
     // Install default crypto provider for rustls
     rustls::crypto::aws_lc_rs::default_provider()
         .install_default()
@@ -31,14 +37,48 @@ async fn main() -> Result<()> {
     info!("Starting Remote Desktop Server");
     
     // Load configuration
-    let config = config::ServerConfig::load()?;
+    let config = config::ServerConfig::load(&cli.config)?;
     info!("Server will bind to: {}", config.bind_address);
     
     // Create server state
-    let state = state::ServerState::new();
+    let mut state = state::ServerState::new()
+        .with_relay(config.relay_enabled)
+        .with_resume_grace(Duration::from_secs(config.resume_grace_secs));
+    match &config.auth_secret {
+        Some(secret) => {
+            let authenticator = TokenAuthenticator::new(secret.as_bytes())
+                .with_revocation_list(&config.revocation_list);
+            state = state.with_authenticator(Arc::new(authenticator));
+        }
+        None => warn!("No auth_secret configured: accepting every device without authentication"),
+    }
+    if let Some(path) = &config.session_store {
+        info!("Storing sessions in {}", path);
+        let repository = SqliteSessionRepository::open(path)?;
+        state = state.with_session_store(Arc::new(RwLock::new(repository)));
+    }
+
+    // No peer is connected yet, so sessions from the previous run cannot go on
+    let stale = state.end_stale_sessions().await?;
+    if stale > 0 {
+        info!("Ended {} sessions left open by the previous run", stale);
+    }
     
-    // Start QUIC server
-    let server = rd_transport::quic::QuicServer::new(config.bind_address.parse()?)?;
+    // Start QUIC server with the same certificate every run, so clients that
+    // pinned or remembered it keep trusting it
+    let identity = ServerIdentity::load_or_generate(&config.tls_cert, &config.tls_key)?;
+    info!("Certificate fingerprint (SHA-256): {}", identity.fingerprint());
+    let server = if config.mutual_tls {
+        let ca = CertificateAuthority::load_or_generate(&config.device_ca_cert, &config.device_ca_key)?;
+        info!("Agents need a certificate from {}; enrollment requests go to {}",
+            config.device_ca_cert, config.enrollment_dir);
+        let server = QuicServer::with_client_ca(config.bind_address.parse()?, identity, &ca)?;
+        state = state.with_enrollment(ca, EnrollmentQueue::new(&config.enrollment_dir));
+        server
+    } else {
+        QuicServer::with_identity(config.bind_address.parse()?, identity)?
+    };
+    let server = server.with_tuning(&config.quic)?;
     
     info!("Server listening on {}", config.bind_address);
     
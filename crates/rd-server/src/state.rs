@@ -1,39 +1,394 @@
 use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::Utc;
 use dashmap::DashMap;
-use rd_core::domain::models::{SessionId, PeerId, Session};
+use tokio::sync::{mpsc, Notify, RwLock};
+use tokio::time::Instant;
+use rd_core::application::SessionManager;
+use rd_core::domain::models::{AgentInfo, Capabilities, Peer, PeerId, PeerRole, Session, SessionId, SessionStatus};
+use rd_core::domain::error::{AuthError, DomainError, Result};
+use rd_core::domain::models::AuthToken;
+use rd_core::domain::ports::{Authenticator, ProtocolMessage, SessionRepository};
+use rd_storage::InMemorySessionRepository;
+use rd_transport::{CertificateAuthority, EnrollmentQueue};
+
+/// Outbound queues of a connected peer
+#[derive(Clone)]
+pub struct PeerHandle {
+    /// Control messages and relayed input, delivered in order
+    pub messages: mpsc::Sender<ProtocolMessage>,
+    /// Relayed screen frames; dropped rather than queued when the peer falls behind
+    pub frames: mpsc::Sender<ProtocolMessage>,
+    /// Notified when the device connected again in the same role, so this
+    /// connection closes
+    pub replaced: Arc<Notify>,
+}
+
+impl PeerHandle {
+    pub fn new(messages: mpsc::Sender<ProtocolMessage>, frames: mpsc::Sender<ProtocolMessage>) -> Self {
+        Self { messages, frames, replaced: Arc::new(Notify::new()) }
+    }
+
+    fn same_connection(&self, other: &PeerHandle) -> bool {
+        self.messages.same_channel(&other.messages)
+    }
+}
+
+/// An agent that registered with this server
+#[derive(Debug, Clone)]
+pub struct RegisteredAgent {
+    pub peer: Peer,
+    /// Whether the agent's connection is still open
+    pub online: bool,
+}
+
+/// Ends of an active session, and what they agreed on, for relaying between them
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub agent: PeerId,
+    pub client: PeerId,
+    pub capabilities: Capabilities,
+}
+
+/// CA issuing device certificates and the requests waiting for it
+pub struct Enrollment {
+    pub ca: CertificateAuthority,
+    pub queue: EnrollmentQueue,
+}
 
 /// Server state managing agents and sessions
 #[derive(Clone)]
 pub struct ServerState {
-    /// Registered agents: device_id -> peer_id
-    pub agents: Arc<DashMap<String, PeerId>>,
-    
-    /// Active sessions: session_id -> session
-    pub sessions: Arc<DashMap<SessionId, Session>>,
+    /// Agents that registered since startup, online or not: device_id -> agent
+    pub agents: Arc<DashMap<String, RegisteredAgent>>,
+
+    /// Connected peers: (role, device_id) -> outbound queues; a device may be
+    /// connected as an agent and as a client at the same time
+    connections: Arc<DashMap<(PeerRole, String), PeerHandle>>,
+
+    /// Sessions brokered by this server
+    pub sessions: Arc<SessionManager>,
+
+    /// Where `sessions` are stored
+    repository: Arc<RwLock<dyn SessionRepository>>,
+
+    /// Whether frames and input are forwarded between the peers of a session
+    pub relay_enabled: bool,
+
+    /// Checks device tokens; `None` accepts every device
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+
+    /// Enrolls devices; when set, agents must present a certificate its CA issued
+    pub enrollment: Option<Arc<Enrollment>>,
+
+    /// How long the session of a client that lost its connection waits for it
+    /// to come back; zero ends the session right away
+    pub resume_grace: Duration,
+
+    /// Tokens the clients of open sessions present to resume them: session -> token
+    resume_tokens: Arc<DashMap<SessionId, String>>,
+
+    /// Paused sessions and when they end unless resumed
+    paused: Arc<DashMap<SessionId, Instant>>,
+
+    /// Routes of the active sessions, so relaying frames and input does not
+    /// query `sessions`: session -> route
+    routes: Arc<DashMap<SessionId, Route>>,
 }
 
 impl ServerState {
+    /// State keeping sessions in memory and accepting every device
     pub fn new() -> Self {
+        let repository: Arc<RwLock<dyn SessionRepository>> =
+            Arc::new(RwLock::new(InMemorySessionRepository::new()));
         Self {
             agents: Arc::new(DashMap::new()),
-            sessions: Arc::new(DashMap::new()),
+            connections: Arc::new(DashMap::new()),
+            sessions: Arc::new(SessionManager::new(repository.clone(), Arc::new(Unauthenticated))),
+            repository,
+            relay_enabled: true,
+            authenticator: None,
+            enrollment: None,
+            resume_grace: Duration::ZERO,
+            resume_tokens: Arc::new(DashMap::new()),
+            paused: Arc::new(DashMap::new()),
+            routes: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Keep sessions in `repository` instead of memory
+    pub fn with_session_store(mut self, repository: Arc<RwLock<dyn SessionRepository>>) -> Self {
+        self.repository = repository;
+        self.rebuild_session_manager();
+        self
+    }
+
+    pub fn with_relay(mut self, enabled: bool) -> Self {
+        self.relay_enabled = enabled;
+        self
+    }
+
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self.rebuild_session_manager();
+        self
+    }
+
+    /// Enroll devices with `ca` and require agents to present a certificate it issued
+    pub fn with_enrollment(mut self, ca: CertificateAuthority, queue: EnrollmentQueue) -> Self {
+        self.enrollment = Some(Arc::new(Enrollment { ca, queue }));
+        self
+    }
+
+    /// Keep the sessions of a client that lost its connection for `grace`, so it can resume them
+    pub fn with_resume_grace(mut self, grace: Duration) -> Self {
+        self.resume_grace = grace;
+        self
+    }
+
+    fn rebuild_session_manager(&mut self) {
+        let authenticator = self.authenticator.clone().unwrap_or_else(|| Arc::new(Unauthenticated));
+        self.sessions = Arc::new(SessionManager::new(self.repository.clone(), authenticator));
+    }
+
+    /// Check that `token` lets `device_id` connect
+    pub async fn authenticate(&self, device_id: &str, token: &AuthToken) -> std::result::Result<(), AuthError> {
+        // A token only vouches for the device it was issued to, and sessions
+        // are opened for the device the client's token names
+        if token.device_id != device_id {
+            return Err(AuthError::InvalidToken);
         }
+        let Some(authenticator) = &self.authenticator else {
+            return Ok(());
+        };
+
+        let peer = authenticator.authenticate(token).await?;
+        if peer.0 != device_id {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(())
+    }
+
+    /// Check the credentials of a peer connecting as `device_id`
+    ///
+    /// `certified` is the device a verified client certificate was issued to,
+    /// already known to match `device_id`. It is all an agent needs; clients
+    /// still need a token, which every session they open checks again.
+    pub async fn authenticate_peer(
+        &self,
+        device_id: &str,
+        role: PeerRole,
+        certified: Option<&str>,
+        token: &AuthToken,
+    ) -> std::result::Result<(), AuthError> {
+        if role == PeerRole::Agent {
+            match certified {
+                Some(_) => return Ok(()),
+                None if self.enrollment.is_some() => {
+                    return Err(AuthError::AuthenticationFailed(
+                        "Agents need a client certificate from this server; enroll first".to_string(),
+                    ));
+                }
+                None => {}
+            }
+        }
+        self.authenticate(device_id, token).await
+    }
+
+    pub fn register_agent(&self, peer: Peer) {
+        self.agents.insert(peer.device_id.clone(), RegisteredAgent { peer, online: true });
+    }
+
+    /// Record that an agent was just heard from
+    pub fn touch_agent(&self, device_id: &str) {
+        if let Some(mut agent) = self.agents.get_mut(device_id) {
+            agent.peer.last_seen = Utc::now();
+        }
+    }
+
+    /// Known agents, online ones first, each group ordered by device id
+    pub fn list_agents(&self) -> Vec<AgentInfo> {
+        let mut agents: Vec<_> = self
+            .agents
+            .iter()
+            .map(|agent| AgentInfo::from_peer(&agent.peer, agent.online))
+            .collect();
+        agents.sort_by(|a, b| b.online.cmp(&a.online).then_with(|| a.device_id.cmp(&b.device_id)));
+        agents
+    }
+
+    /// Route messages for `device_id` in `role` to `handle`
+    ///
+    /// A device connecting again in the same role replaces its previous
+    /// connection, which is told to close. Returns whether there was one.
+    pub fn connect(&self, role: PeerRole, device_id: String, handle: PeerHandle) -> bool {
+        match self.connections.insert((role, device_id), handle) {
+            Some(previous) => {
+                previous.replaced.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forget a connection, unless the device has already reconnected on a newer one
+    ///
+    /// Returns whether `handle` was still the current connection.
+    pub fn disconnect(&self, role: PeerRole, device_id: &str, handle: &PeerHandle) -> bool {
+        let removed = self
+            .connections
+            .remove_if(&(role, device_id.to_string()), |_, current| current.same_connection(handle))
+            .is_some();
+        if removed && role == PeerRole::Agent {
+            if let Some(mut agent) = self.agents.get_mut(device_id) {
+                agent.online = false;
+                agent.peer.last_seen = Utc::now();
+            }
+        }
+        removed
+    }
+
+    /// Queue a message for a connected peer without waiting
+    ///
+    /// Returns `false` if the peer is not connected in `role` or its queue is full.
+    pub fn send_to(&self, role: PeerRole, device_id: &str, message: ProtocolMessage) -> bool {
+        match self.connections.get(&(role, device_id.to_string())) {
+            Some(peer) => peer.messages.try_send(message).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Message queue of a connected peer, for callers that wait for room
+    pub fn sender(&self, role: PeerRole, device_id: &str) -> Option<mpsc::Sender<ProtocolMessage>> {
+        self.connections.get(&(role, device_id.to_string())).map(|peer| peer.messages.clone())
+    }
+
+    /// Queue a frame for a connected client, dropping it if the client is behind
+    ///
+    /// Returns `false` if the frame was dropped.
+    pub fn relay_frame(&self, device_id: &str, frame: ProtocolMessage) -> bool {
+        match self.connections.get(&(PeerRole::Client, device_id.to_string())) {
+            Some(peer) => peer.frames.try_send(frame).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Create a pending session between the client holding `client_token` and an online agent
+    ///
+    /// The session gets the capabilities both ends support. Fails with
+    /// [`DomainError::PeerNotFound`] if no agent with that device id is online.
+    pub async fn create_session(
+        &self,
+        client_token: &AuthToken,
+        client_capabilities: &Capabilities,
+        agent_device: &str,
+    ) -> Result<Session> {
+        let (agent, capabilities) = self
+            .agents
+            .get(agent_device)
+            .filter(|agent| agent.online)
+            .map(|agent| {
+                let capabilities = Capabilities::for_session(&agent.peer.capabilities, client_capabilities);
+                (agent.peer.id.clone(), capabilities)
+            })
+            .ok_or_else(|| DomainError::PeerNotFound(PeerId::new(agent_device)))?;
+
+        self.sessions.create_session(client_token, agent, capabilities).await
+    }
+
+    /// Mark `session` active and start relaying between its ends
+    pub async fn activate_session(&self, session: &Session) -> Result<()> {
+        self.sessions.update_status(session.id, SessionStatus::Active).await?;
+        self.routes.insert(session.id, Route::of(session));
+        Ok(())
+    }
+
+    /// Route of `session_id`, if it is active
+    pub fn route(&self, session_id: SessionId) -> Option<Route> {
+        self.routes.get(&session_id).map(|route| route.clone())
+    }
+
+    /// Active sessions `agent` serves, with the client of each
+    pub fn clients_of(&self, agent: &PeerId) -> Vec<(SessionId, PeerId)> {
+        self.routes
+            .iter()
+            .filter(|route| route.agent == *agent)
+            .map(|route| (*route.key(), route.client.clone()))
+            .collect()
+    }
+
+    /// Issue the token the client of `session_id` presents to resume it
+    pub fn issue_resume_token(&self, session_id: SessionId) -> String {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        self.resume_tokens.insert(session_id, token.clone());
+        token
+    }
+
+    /// Pause `session_id` for the resume grace period
+    ///
+    /// Returns when the grace period is over.
+    pub async fn pause_session(&self, session_id: SessionId) -> Result<Instant> {
+        self.sessions.update_status(session_id, SessionStatus::Paused).await?;
+        self.routes.remove(&session_id);
+        let deadline = Instant::now() + self.resume_grace;
+        self.paused.insert(session_id, deadline);
+        Ok(deadline)
+    }
+
+    /// Whether `session_id` is still paused past its grace period
+    pub fn pause_expired(&self, session_id: SessionId) -> bool {
+        self.paused.get(&session_id).is_some_and(|deadline| *deadline <= Instant::now())
     }
-    
-    pub fn register_agent(&self, device_id: String, peer_id: PeerId) {
-        self.agents.insert(device_id, peer_id);
+
+    /// Give `session_id` back to `client`, which presented `resume_token`
+    ///
+    /// A session still active is resumed too, as the server may not have noticed
+    /// yet that the client's previous connection is gone. Fails with
+    /// [`DomainError::SessionNotFound`] if the session ended, belongs to another
+    /// client or the token does not match.
+    pub async fn resume_session(&self, session_id: SessionId, client: &PeerId, resume_token: &str) -> Result<Session> {
+        let session = self.sessions.get_session(session_id).await?;
+        let valid = session.client == *client
+            && self.resume_tokens.get(&session_id).is_some_and(|token| *token == resume_token);
+        if !valid {
+            return Err(DomainError::SessionNotFound(session_id).into());
+        }
+
+        self.paused.remove(&session_id);
+        if session.status != SessionStatus::Active {
+            self.sessions.update_status(session_id, SessionStatus::Active).await?;
+        }
+        self.routes.insert(session_id, Route::of(&session));
+        Ok(Session { status: SessionStatus::Active, ..session })
     }
-    
-    pub fn get_agent(&self, device_id: &str) -> Option<PeerId> {
-        self.agents.get(device_id).map(|entry| entry.value().clone())
+
+    /// Forget the route, resume token and pause of a session that ended
+    pub fn forget_session(&self, session_id: SessionId) {
+        self.routes.remove(&session_id);
+        self.resume_tokens.remove(&session_id);
+        self.paused.remove(&session_id);
     }
-    
-    pub fn add_session(&self, session: Session) {
-        self.sessions.insert(session.id, session);
+
+    /// End the sessions left open by a previous run, whose peers are long gone
+    ///
+    /// Returns how many sessions were ended.
+    pub async fn end_stale_sessions(&self) -> Result<usize> {
+        let stale = self.sessions.list_active_sessions().await?;
+        for session in &stale {
+            self.sessions.end_session(session.id).await?;
+        }
+        Ok(stale.len())
     }
-    
-    pub fn get_session(&self, session_id: SessionId) -> Option<Session> {
-        self.sessions.get(&session_id).map(|entry| entry.value().clone())
+}
+
+impl Route {
+    fn of(session: &Session) -> Self {
+        Self {
+            agent: session.agent.clone(),
+            client: session.client.clone(),
+            capabilities: session.capabilities.clone(),
+        }
     }
 }
 
@@ -42,3 +397,251 @@ impl Default for ServerState {
         Self::new()
     }
 }
+
+/// Authenticator for servers without a secret: every token names its device
+struct Unauthenticated;
+
+#[async_trait]
+impl Authenticator for Unauthenticated {
+    async fn authenticate(&self, token: &AuthToken) -> std::result::Result<PeerId, AuthError> {
+        Ok(PeerId::new(token.device_id.clone()))
+    }
+
+    async fn generate_token(&self, _device_id: &str) -> std::result::Result<AuthToken, AuthError> {
+        Err(AuthError::TokenGenerationFailed("authentication is disabled".to_string()))
+    }
+
+    async fn revoke_token(&self, _token: &AuthToken) -> std::result::Result<(), AuthError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_core::application::TokenAuthenticator;
+    use rd_core::domain::error::ApplicationError;
+    use rd_core::domain::models::{CodecType, InputKind, Platform, SessionId, SessionStatus};
+    use rd_storage::SqliteSessionRepository;
+
+    type Queues = (mpsc::Receiver<ProtocolMessage>, mpsc::Receiver<ProtocolMessage>);
+
+    /// Handle with single-slot queues, plus the receiving ends
+    fn handle() -> (PeerHandle, Queues) {
+        let (messages, messages_rx) = mpsc::channel(1);
+        let (frames, frames_rx) = mpsc::channel(1);
+        (PeerHandle::new(messages, frames), (messages_rx, frames_rx))
+    }
+
+    fn agent(device_id: &str) -> Peer {
+        Peer {
+            id: PeerId::new(device_id),
+            device_id: device_id.to_string(),
+            display_name: device_id.to_string(),
+            platform: Platform::Linux,
+            capabilities: Capabilities::default(),
+            last_seen: Utc::now(),
+        }
+    }
+
+    fn online(state: &ServerState, device_id: &str) -> bool {
+        state.agents.get(device_id).is_some_and(|agent| agent.online)
+    }
+
+    fn viewer_token() -> AuthToken {
+        AuthToken::new("", "viewer")
+    }
+
+    #[tokio::test]
+    async fn test_session_requires_registered_agent() {
+        let state = ServerState::new();
+        let viewer = Capabilities::default();
+        assert!(matches!(
+            state.create_session(&viewer_token(), &viewer, "missing").await,
+            Err(ApplicationError::Domain(DomainError::PeerNotFound(_)))
+        ));
+        assert!(state.sessions.list_active_sessions().await.unwrap().is_empty());
+
+        state.register_agent(agent("host"));
+        let session = state.create_session(&viewer_token(), &viewer, "host").await.unwrap();
+        assert_eq!(session.status, SessionStatus::Pending);
+        assert_eq!(session.client, PeerId::new("viewer"));
+        assert_eq!(state.sessions.sessions_of(&PeerId::new("host")).await.unwrap().len(), 1);
+        assert_eq!(state.sessions.sessions_of(&PeerId::new("viewer")).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stale_disconnect_keeps_newer_connection() {
+        let state = ServerState::new();
+        let (old, _old_queues) = handle();
+        let (new, (mut new_rx, _new_frames)) = handle();
+
+        state.register_agent(agent("host"));
+        assert!(!state.connect(PeerRole::Agent, "host".into(), old.clone()));
+        assert!(state.connect(PeerRole::Agent, "host".into(), new.clone()));
+        // The replaced connection is told to close
+        tokio::time::timeout(Duration::from_secs(1), old.replaced.notified()).await.unwrap();
+
+        // The first connection going away must not unregister the reconnected agent
+        assert!(!state.disconnect(PeerRole::Agent, "host", &old));
+        assert!(online(&state, "host"));
+        assert!(state.send_to(PeerRole::Agent, "host", ProtocolMessage::Disconnect));
+        assert!(matches!(new_rx.try_recv(), Ok(ProtocolMessage::Disconnect)));
+
+        assert!(state.disconnect(PeerRole::Agent, "host", &new));
+        assert!(!online(&state, "host"));
+        assert!(!state.send_to(PeerRole::Agent, "host", ProtocolMessage::Disconnect));
+    }
+
+    #[test]
+    fn test_agent_and_client_of_one_device_are_kept_apart() {
+        let state = ServerState::new();
+        let (as_agent, (mut agent_rx, _agent_frames)) = handle();
+        let (as_client, (mut client_rx, _client_frames)) = handle();
+
+        state.register_agent(agent("desk"));
+        assert!(!state.connect(PeerRole::Agent, "desk".into(), as_agent.clone()));
+        assert!(!state.connect(PeerRole::Client, "desk".into(), as_client.clone()));
+
+        assert!(state.send_to(PeerRole::Client, "desk", ProtocolMessage::ListAgents));
+        assert!(matches!(client_rx.try_recv(), Ok(ProtocolMessage::ListAgents)));
+        assert!(agent_rx.try_recv().is_err());
+
+        // The client leaving does not take the agent offline
+        assert!(state.disconnect(PeerRole::Client, "desk", &as_client));
+        assert!(online(&state, "desk"));
+        assert!(state.send_to(PeerRole::Agent, "desk", ProtocolMessage::Disconnect));
+        assert!(matches!(agent_rx.try_recv(), Ok(ProtocolMessage::Disconnect)));
+    }
+
+    #[test]
+    fn test_frames_are_dropped_when_peer_is_behind() {
+        let state = ServerState::new();
+        let (viewer, (mut messages, mut frames)) = handle();
+        state.connect(PeerRole::Client, "viewer".into(), viewer);
+
+        let frame = || ProtocolMessage::Heartbeat { timestamp: 0 };
+        assert!(state.relay_frame("viewer", frame()));
+        assert!(!state.relay_frame("viewer", frame()));
+
+        // A full frame queue does not hold up control messages
+        assert!(state.send_to(PeerRole::Client, "viewer", ProtocolMessage::Disconnect));
+        assert!(messages.try_recv().is_ok());
+
+        frames.try_recv().unwrap();
+        assert!(state.relay_frame("viewer", frame()));
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_agents_stay_listed_offline() {
+        let state = ServerState::new();
+        let (idle, _idle_queues) = handle();
+        let (busy, _busy_queues) = handle();
+        for (device_id, handle) in [("zeta", idle), ("alpha", busy.clone())] {
+            state.register_agent(agent(device_id));
+            state.connect(PeerRole::Agent, device_id.into(), handle);
+        }
+        let connected_at = state.agents.get("alpha").unwrap().peer.last_seen;
+
+        assert!(state.disconnect(PeerRole::Agent, "alpha", &busy));
+        assert!(!online(&state, "alpha"));
+        assert!(state.create_session(&viewer_token(), &Capabilities::default(), "alpha").await.is_err());
+
+        let agents = state.list_agents();
+        let listed: Vec<_> = agents.iter().map(|a| (a.device_id.as_str(), a.online)).collect();
+        assert_eq!(listed, [("zeta", true), ("alpha", false)]);
+        assert!(agents[1].last_seen >= connected_at);
+    }
+
+    #[tokio::test]
+    async fn test_routes_follow_the_session_lifecycle() {
+        let state = ServerState::new();
+        state.register_agent(agent("host"));
+        let session = state.create_session(&viewer_token(), &Capabilities::default(), "host").await.unwrap();
+        let (host, viewer) = (PeerId::new("host"), PeerId::new("viewer"));
+        assert!(state.route(session.id).is_none());
+
+        state.activate_session(&session).await.unwrap();
+        assert_eq!(state.route(session.id).unwrap().client, viewer);
+        assert_eq!(state.clients_of(&host), [(session.id, viewer.clone())]);
+        assert!(state.clients_of(&viewer).is_empty());
+
+        state.pause_session(session.id).await.unwrap();
+        assert!(state.route(session.id).is_none());
+        let token = state.issue_resume_token(session.id);
+        state.resume_session(session.id, &viewer, &token).await.unwrap();
+        assert_eq!(state.route(session.id).unwrap().agent, host);
+
+        state.sessions.end_session(session.id).await.unwrap();
+        state.forget_session(session.id);
+        assert!(state.clients_of(&host).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_session_gets_common_capabilities() {
+        let state = ServerState::new();
+        let mut host = agent("host");
+        host.capabilities.codecs = vec![CodecType::H264, CodecType::Jpeg];
+        host.capabilities.input.clear();
+        state.register_agent(host);
+
+        let viewer = Capabilities { input: vec![InputKind::Pointer], ..Capabilities::default() };
+        let session = state.create_session(&viewer_token(), &viewer, "host").await.unwrap();
+        assert_eq!(session.capabilities.codec(), Some(CodecType::Jpeg));
+        assert!(session.capabilities.is_view_only());
+    }
+
+    #[tokio::test]
+    async fn test_authentication_binds_token_to_device() {
+        let open = ServerState::new();
+        assert!(open.authenticate("host", &AuthToken::new("", "host")).await.is_ok());
+        assert!(open.authenticate("laptop", &AuthToken::new("", "host")).await.is_err());
+
+        let authenticator = TokenAuthenticator::new(b"secret".to_vec());
+        let token = authenticator.generate_token("host").await.unwrap();
+        let state = ServerState::new().with_authenticator(Arc::new(authenticator));
+
+        assert!(state.authenticate("host", &token).await.is_ok());
+        assert!(matches!(state.authenticate("laptop", &token).await, Err(AuthError::InvalidToken)));
+        assert!(matches!(
+            state.authenticate("host", &AuthToken::new("", "host")).await,
+            Err(AuthError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_need_a_valid_client_token() {
+        let authenticator = TokenAuthenticator::new(b"secret".to_vec());
+        let token = authenticator.generate_token("viewer").await.unwrap();
+        let state = ServerState::new().with_authenticator(Arc::new(authenticator));
+        state.register_agent(agent("host"));
+
+        let caps = Capabilities::default();
+        assert!(state.create_session(&token, &caps, "host").await.is_ok());
+        assert!(matches!(
+            state.create_session(&viewer_token(), &caps, "host").await,
+            Err(ApplicationError::Auth(AuthError::InvalidToken))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stale_sessions_are_ended_after_restart() {
+        let path = std::env::temp_dir().join(format!("rd-server-sessions-{}.db", SessionId::new()));
+        let store = || -> Arc<RwLock<dyn SessionRepository>> {
+            Arc::new(RwLock::new(SqliteSessionRepository::open(&path).unwrap()))
+        };
+
+        let state = ServerState::new().with_session_store(store());
+        state.register_agent(agent("host"));
+        let session = state.create_session(&viewer_token(), &Capabilities::default(), "host").await.unwrap();
+        drop(state);
+
+        // The next run finds the session, but neither of its peers
+        let restarted = ServerState::new().with_session_store(store());
+        assert_eq!(restarted.sessions.get_session(session.id).await.unwrap().id, session.id);
+        assert_eq!(restarted.end_stale_sessions().await.unwrap(), 1);
+        assert!(restarted.sessions.get_session(session.id).await.is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -9,7 +9,7 @@ use axum::{
         Path, State,
     },
     response::IntoResponse,
-    routing::{get, post},
+    routing::get,
     Json, Router,
 };
 use dashmap::DashMap;
@@ -49,7 +49,6 @@ pub enum SignalMessage {
 /// Connected peer info
 #[derive(Clone)]
 struct PeerInfo {
-    peer_id: String,
     tx: broadcast::Sender<SignalMessage>,
 }
 
@@ -164,7 +163,6 @@ async fn handle_signal(
             
             // Store peer info
             state.peers.insert(id.clone(), PeerInfo {
-                peer_id: id.clone(),
                 tx: tx.clone(),
             });
             
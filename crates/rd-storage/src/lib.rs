@@ -0,0 +1,8 @@
+pub mod memory;
+pub mod sqlite;
+
+pub use memory::InMemorySessionRepository;
+pub use sqlite::{SessionRecord, SqliteSessionRepository};
+
+// Re-export core traits
+pub use rd_core::domain::ports::SessionRepository;
@@ -0,0 +1,111 @@
+//! Session repository kept in memory
+//!
+//! Sessions are lost when the process exits, which is what tests and servers
+//! without a configured database want.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use rd_core::domain::{
+    error::RepositoryError,
+    models::{Session, SessionId, SessionStatus},
+    ports::SessionRepository,
+};
+
+/// [`SessionRepository`] backed by a `HashMap`
+#[derive(Debug, Default)]
+pub struct InMemorySessionRepository {
+    sessions: HashMap<SessionId, Session>,
+}
+
+impl InMemorySessionRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionRepository for InMemorySessionRepository {
+    async fn create(&mut self, session: Session) -> Result<(), RepositoryError> {
+        if self.sessions.contains_key(&session.id) {
+            return Err(RepositoryError::ConstraintViolation(format!(
+                "session {} already exists",
+                session.id
+            )));
+        }
+        self.sessions.insert(session.id, session);
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: SessionId) -> Result<Option<Session>, RepositoryError> {
+        Ok(self.sessions.get(&id).cloned())
+    }
+
+    async fn update(&mut self, session: Session) -> Result<(), RepositoryError> {
+        match self.sessions.get_mut(&session.id) {
+            Some(stored) => {
+                *stored = session;
+                Ok(())
+            }
+            None => Err(RepositoryError::NotFound),
+        }
+    }
+
+    async fn delete(&mut self, id: SessionId) -> Result<(), RepositoryError> {
+        self.sessions.remove(&id).map(|_| ()).ok_or(RepositoryError::NotFound)
+    }
+
+    async fn list_active(&self) -> Result<Vec<Session>, RepositoryError> {
+        Ok(self
+            .sessions
+            .values()
+            .filter(|session| session.status != SessionStatus::Closed)
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use rd_core::domain::models::{Capabilities, PeerId};
+
+    fn session() -> Session {
+        Session {
+            id: SessionId::new(),
+            client: PeerId::new("viewer"),
+            agent: PeerId::new("host"),
+            created_at: Utc::now(),
+            status: SessionStatus::Pending,
+            capabilities: Capabilities::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_lifecycle() {
+        let mut repo = InMemorySessionRepository::new();
+        let mut session = session();
+
+        repo.create(session.clone()).await.unwrap();
+        assert!(matches!(
+            repo.create(session.clone()).await,
+            Err(RepositoryError::ConstraintViolation(_))
+        ));
+
+        session.status = SessionStatus::Active;
+        repo.update(session.clone()).await.unwrap();
+        assert_eq!(repo.find_by_id(session.id).await.unwrap().unwrap().status, SessionStatus::Active);
+        assert_eq!(repo.list_active().await.unwrap().len(), 1);
+
+        session.status = SessionStatus::Closed;
+        repo.update(session.clone()).await.unwrap();
+        assert!(repo.list_active().await.unwrap().is_empty());
+
+        repo.delete(session.id).await.unwrap();
+        assert!(repo.find_by_id(session.id).await.unwrap().is_none());
+        assert!(matches!(repo.delete(session.id).await, Err(RepositoryError::NotFound)));
+        assert!(matches!(repo.update(session).await, Err(RepositoryError::NotFound)));
+    }
+}
@@ -0,0 +1,359 @@
+//! Session repository stored in SQLite
+//!
+//! Every session ever created stays in the `sessions` table. Deleting a session
+//! only stamps its `ended_at`, so the history of who connected to which agent,
+//! and how it ended, survives server restarts. Sessions still open are also
+//! kept in memory: the server looks them up for every relayed frame, which
+//! must not wait for the database.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Row};
+use tracing::debug;
+use uuid::Uuid;
+
+use rd_core::domain::{
+    error::RepositoryError,
+    models::{PeerId, Session, SessionId, SessionStatus},
+    ports::SessionRepository,
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        id           TEXT PRIMARY KEY,
+        client       TEXT NOT NULL,
+        agent        TEXT NOT NULL,
+        created_at   TEXT NOT NULL,
+        status       TEXT NOT NULL,
+        capabilities TEXT NOT NULL,
+        ended_at     TEXT
+    );
+    CREATE INDEX IF NOT EXISTS sessions_open ON sessions (ended_at) WHERE ended_at IS NULL;
+";
+
+const COLUMNS: &str = "id, client, agent, created_at, status, capabilities, ended_at";
+
+/// A session as recorded in the database
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    pub session: Session,
+    /// When the session was deleted from the repository; `None` while it is open
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+/// [`SessionRepository`] persisted in a SQLite database
+pub struct SqliteSessionRepository {
+    connection: Arc<Mutex<Connection>>,
+    /// Sessions not deleted yet, mirroring the rows without `ended_at`
+    open: HashMap<SessionId, Session>,
+}
+
+impl SqliteSessionRepository {
+    /// Open or create the database at `path`, creating missing parent directories
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RepositoryError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).map_err(|e| RepositoryError::DatabaseError(format!(
+                "Failed to create {}: {}",
+                parent.display(),
+                e
+            )))?;
+        }
+
+        Self::with_connection(Connection::open(path).map_err(db_error)?)
+    }
+
+    /// Database that lives only as long as the repository
+    pub fn open_in_memory() -> Result<Self, RepositoryError> {
+        Self::with_connection(Connection::open_in_memory().map_err(db_error)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self, RepositoryError> {
+        connection.execute_batch(SCHEMA).map_err(db_error)?;
+
+        let open = {
+            let mut statement = connection
+                .prepare(&format!("SELECT {} FROM sessions WHERE ended_at IS NULL", COLUMNS))
+                .map_err(db_error)?;
+            let records = statement
+                .query_map([], read_record)
+                .map_err(db_error)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(db_error)?;
+            records
+                .into_iter()
+                .map(|record| record.map(|record| (record.session.id, record.session)))
+                .collect::<Result<HashMap<_, _>, _>>()?
+        };
+        debug!("Loaded {} open sessions", open.len());
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            open,
+        })
+    }
+
+    /// Most recent sessions first, open and ended alike
+    pub async fn history(&self, limit: usize) -> Result<Vec<SessionRecord>, RepositoryError> {
+        self.run(move |connection| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM sessions ORDER BY created_at DESC LIMIT ?1",
+                COLUMNS
+            ))?;
+            let records = statement
+                .query_map([limit as i64], read_record)?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(records)
+        })
+        .await?
+        .into_iter()
+        .collect()
+    }
+
+    /// Run a query on the blocking thread pool, so the database never stalls the runtime
+    async fn run<T, F>(&self, query: F) -> Result<T, RepositoryError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let connection = connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            query(&connection)
+        })
+        .await
+        .map_err(|e| RepositoryError::DatabaseError(e.to_string()))?
+        .map_err(db_error)
+    }
+}
+
+#[async_trait]
+impl SessionRepository for SqliteSessionRepository {
+    async fn create(&mut self, session: Session) -> Result<(), RepositoryError> {
+        let capabilities = encode_capabilities(&session)?;
+        let row = session.clone();
+        let inserted = self.run(move |connection| {
+            connection.execute(
+                "INSERT OR IGNORE INTO sessions (id, client, agent, created_at, status, capabilities)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    row.id.0.to_string(),
+                    row.client.0,
+                    row.agent.0,
+                    row.created_at,
+                    status_name(row.status),
+                    capabilities,
+                ],
+            )
+        }).await?;
+        if inserted == 0 {
+            return Err(RepositoryError::ConstraintViolation(format!(
+                "session {} already exists",
+                session.id
+            )));
+        }
+
+        self.open.insert(session.id, session);
+        Ok(())
+    }
+
+    async fn find_by_id(&self, id: SessionId) -> Result<Option<Session>, RepositoryError> {
+        Ok(self.open.get(&id).cloned())
+    }
+
+    async fn update(&mut self, session: Session) -> Result<(), RepositoryError> {
+        if !self.open.contains_key(&session.id) {
+            return Err(RepositoryError::NotFound);
+        }
+
+        let capabilities = encode_capabilities(&session)?;
+        let (id, status) = (session.id.0.to_string(), status_name(session.status));
+        self.run(move |connection| {
+            connection.execute(
+                "UPDATE sessions SET status = ?2, capabilities = ?3 WHERE id = ?1",
+                params![id, status, capabilities],
+            )
+        }).await?;
+
+        self.open.insert(session.id, session);
+        Ok(())
+    }
+
+    async fn delete(&mut self, id: SessionId) -> Result<(), RepositoryError> {
+        if !self.open.contains_key(&id) {
+            return Err(RepositoryError::NotFound);
+        }
+
+        let row_id = id.0.to_string();
+        self.run(move |connection| {
+            connection.execute(
+                "UPDATE sessions SET ended_at = ?2 WHERE id = ?1",
+                params![row_id, Utc::now()],
+            )
+        }).await?;
+
+        self.open.remove(&id);
+        Ok(())
+    }
+
+    async fn list_active(&self) -> Result<Vec<Session>, RepositoryError> {
+        Ok(self
+            .open
+            .values()
+            .filter(|session| session.status != SessionStatus::Closed)
+            .cloned()
+            .collect())
+    }
+}
+
+fn db_error(error: rusqlite::Error) -> RepositoryError {
+    RepositoryError::DatabaseError(error.to_string())
+}
+
+fn encode_capabilities(session: &Session) -> Result<String, RepositoryError> {
+    serde_json::to_string(&session.capabilities)
+        .map_err(|e| RepositoryError::SerializationError(e.to_string()))
+}
+
+fn status_name(status: SessionStatus) -> &'static str {
+    match status {
+        SessionStatus::Pending => "pending",
+        SessionStatus::Active => "active",
+        SessionStatus::Paused => "paused",
+        SessionStatus::Closed => "closed",
+    }
+}
+
+fn parse_status(name: &str) -> Option<SessionStatus> {
+    match name {
+        "pending" => Some(SessionStatus::Pending),
+        "active" => Some(SessionStatus::Active),
+        "paused" => Some(SessionStatus::Paused),
+        "closed" => Some(SessionStatus::Closed),
+        _ => None,
+    }
+}
+
+/// Decode a row selected with [`COLUMNS`]
+///
+/// Columns SQLite cannot convert fail the query; values it stores fine but we
+/// cannot make sense of are reported separately, as serialization errors.
+fn read_record(row: &Row<'_>) -> rusqlite::Result<Result<SessionRecord, RepositoryError>> {
+    let id: String = row.get(0)?;
+    let status: String = row.get(4)?;
+    let capabilities: String = row.get(5)?;
+    let client: String = row.get(1)?;
+    let agent: String = row.get(2)?;
+    let (created_at, ended_at) = (row.get(3)?, row.get(6)?);
+
+    let decode = || {
+        let id = Uuid::parse_str(&id).map_err(|e| format!("bad session id {}: {}", id, e))?;
+        let status = parse_status(&status).ok_or_else(|| format!("unknown status {:?}", status))?;
+        let capabilities = serde_json::from_str(&capabilities)
+            .map_err(|e| format!("bad capabilities of session {}: {}", id, e))?;
+
+        Ok(SessionRecord {
+            session: Session {
+                id: SessionId(id),
+                client: PeerId::new(client),
+                agent: PeerId::new(agent),
+                created_at,
+                status,
+                capabilities,
+            },
+            ended_at,
+        })
+    };
+    Ok(decode().map_err(RepositoryError::SerializationError))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_core::domain::models::{Capabilities, CodecType, InputKind};
+
+    fn session(agent: &str) -> Session {
+        Session {
+            id: SessionId::new(),
+            client: PeerId::new("viewer"),
+            agent: PeerId::new(agent),
+            created_at: Utc::now(),
+            status: SessionStatus::Pending,
+            capabilities: Capabilities {
+                codecs: vec![CodecType::Jpeg],
+                input: vec![InputKind::Pointer],
+                ..Capabilities::default()
+            },
+        }
+    }
+
+    fn temp_db() -> std::path::PathBuf {
+        std::env::temp_dir()
+            .join(format!("rd-storage-{}", Uuid::new_v4()))
+            .join("sessions.db")
+    }
+
+    #[tokio::test]
+    async fn test_sessions_survive_reopening() {
+        let path = temp_db();
+        let mut open = session("host");
+        let ended = session("laptop");
+
+        {
+            let mut repo = SqliteSessionRepository::open(&path).unwrap();
+            repo.create(open.clone()).await.unwrap();
+            repo.create(ended.clone()).await.unwrap();
+            open.status = SessionStatus::Active;
+            repo.update(open.clone()).await.unwrap();
+            repo.delete(ended.id).await.unwrap();
+        }
+
+        let repo = SqliteSessionRepository::open(&path).unwrap();
+        let restored = repo.find_by_id(open.id).await.unwrap().unwrap();
+        assert_eq!(restored.status, SessionStatus::Active);
+        assert_eq!(restored.agent, open.agent);
+        assert_eq!(restored.capabilities, open.capabilities);
+        assert_eq!(restored.created_at, open.created_at);
+        assert!(repo.find_by_id(ended.id).await.unwrap().is_none());
+        assert_eq!(repo.list_active().await.unwrap().len(), 1);
+
+        // Ended sessions are still part of the history
+        let history = repo.history(10).await.unwrap();
+        assert_eq!(history.len(), 2);
+        let record = history.iter().find(|record| record.session.id == ended.id).unwrap();
+        assert!(record.ended_at.is_some());
+        assert_eq!(repo.history(1).await.unwrap()[0].session.id, ended.id);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ended_sessions_cannot_be_changed() {
+        let mut repo = SqliteSessionRepository::open_in_memory().unwrap();
+        let mut session = session("host");
+
+        repo.create(session.clone()).await.unwrap();
+        assert!(matches!(
+            repo.create(session.clone()).await,
+            Err(RepositoryError::ConstraintViolation(_))
+        ));
+
+        session.status = SessionStatus::Closed;
+        repo.update(session.clone()).await.unwrap();
+        assert!(repo.list_active().await.unwrap().is_empty());
+        repo.delete(session.id).await.unwrap();
+
+        assert!(matches!(repo.delete(session.id).await, Err(RepositoryError::NotFound)));
+        assert!(matches!(repo.update(session.clone()).await, Err(RepositoryError::NotFound)));
+        // Its id stays taken
+        assert!(matches!(
+            repo.create(session.clone()).await,
+            Err(RepositoryError::ConstraintViolation(_))
+        ));
+        assert_eq!(repo.history(10).await.unwrap()[0].session.status, SessionStatus::Closed);
+    }
+}
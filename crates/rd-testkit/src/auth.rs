@@ -0,0 +1,91 @@
+//! Authenticator with a fixed set of valid tokens
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use rd_core::domain::{
+    error::AuthError,
+    models::{AuthToken, PeerId},
+    ports::Authenticator,
+};
+
+/// [`Authenticator`] accepting the tokens it issued or was told about
+///
+/// Tokens are plain strings, valid for the device they were issued to until
+/// revoked or expired with [`FakeAuthenticator::expire`].
+#[derive(Clone, Default)]
+pub struct FakeAuthenticator {
+    /// token -> (device id, expired)
+    tokens: Arc<Mutex<HashMap<String, (String, bool)>>>,
+    issued: Arc<AtomicUsize>,
+}
+
+impl FakeAuthenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept `token` for `device_id`
+    pub fn allow(&self, token: impl Into<String>, device_id: impl Into<String>) -> AuthToken {
+        let (token, device_id) = (token.into(), device_id.into());
+        self.tokens.lock().unwrap().insert(token.clone(), (device_id.clone(), false));
+        AuthToken::new(token, device_id)
+    }
+
+    /// Make `token` fail with [`AuthError::TokenExpired`] from now on
+    pub fn expire(&self, token: &AuthToken) {
+        if let Some((_, expired)) = self.tokens.lock().unwrap().get_mut(&token.token) {
+            *expired = true;
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for FakeAuthenticator {
+    async fn authenticate(&self, token: &AuthToken) -> Result<PeerId, AuthError> {
+        match self.tokens.lock().unwrap().get(&token.token) {
+            Some((device_id, _)) if *device_id != token.device_id => Err(AuthError::InvalidToken),
+            Some((_, true)) => Err(AuthError::TokenExpired),
+            Some((device_id, false)) => Ok(PeerId::new(device_id.clone())),
+            None => Err(AuthError::InvalidToken),
+        }
+    }
+
+    async fn generate_token(&self, device_id: &str) -> Result<AuthToken, AuthError> {
+        let token = format!("fake-token-{}-{}", device_id, self.issued.fetch_add(1, Ordering::SeqCst));
+        Ok(self.allow(token, device_id))
+    }
+
+    async fn revoke_token(&self, token: &AuthToken) -> Result<(), AuthError> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .remove(&token.token)
+            .map(|_| ())
+            .ok_or(AuthError::InvalidToken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_token_lifecycle() {
+        let auth = FakeAuthenticator::new();
+        let token = auth.generate_token("host").await.unwrap();
+        assert_eq!(auth.authenticate(&token).await.unwrap(), PeerId::new("host"));
+
+        let borrowed = AuthToken::new(token.token.clone(), "laptop");
+        assert!(matches!(auth.authenticate(&borrowed).await, Err(AuthError::InvalidToken)));
+
+        auth.expire(&token);
+        assert!(matches!(auth.authenticate(&token).await, Err(AuthError::TokenExpired)));
+
+        auth.revoke_token(&token).await.unwrap();
+        assert!(matches!(auth.authenticate(&token).await, Err(AuthError::InvalidToken)));
+    }
+}
@@ -0,0 +1,133 @@
+//! Screen capture playing back a script
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use rd_core::domain::{
+    error::CaptureError,
+    models::{DisplayInfo, FrameFormat, ScreenFrame},
+    ports::ScreenCapture,
+};
+
+/// Raw RGBA frame of `width`x`height` whose pixels all hold `sequence`
+pub fn test_frame(sequence: u64, width: u32, height: u32) -> ScreenFrame {
+    ScreenFrame {
+        sequence,
+        timestamp: sequence,
+        data: vec![sequence as u8; (width * height * 4) as usize],
+        width,
+        height,
+        format: FrameFormat::Raw,
+    }
+}
+
+/// [`ScreenCapture`] returning scripted frames and errors in order
+///
+/// Once the script runs out the last frame keeps being returned, like a screen
+/// where nothing changes; with no frame at all, capturing fails.
+#[derive(Clone)]
+pub struct ScriptedCapture {
+    script: Arc<Mutex<VecDeque<Result<ScreenFrame, CaptureError>>>>,
+    last: Arc<Mutex<Option<ScreenFrame>>>,
+    displays: Vec<DisplayInfo>,
+    target: Arc<Mutex<u32>>,
+    captures: Arc<AtomicUsize>,
+}
+
+impl ScriptedCapture {
+    /// Capture returning `frames`, then the last one forever
+    pub fn new(frames: impl IntoIterator<Item = ScreenFrame>) -> Self {
+        Self::scripted(frames.into_iter().map(Ok))
+    }
+
+    /// Capture playing back frames and failures
+    pub fn scripted(script: impl IntoIterator<Item = Result<ScreenFrame, CaptureError>>) -> Self {
+        Self {
+            script: Arc::new(Mutex::new(script.into_iter().collect())),
+            last: Arc::new(Mutex::new(None)),
+            displays: vec![DisplayInfo {
+                id: 0,
+                name: "Fake display".to_string(),
+                width: 1920,
+                height: 1080,
+                x: 0,
+                y: 0,
+                is_primary: true,
+            }],
+            target: Arc::new(Mutex::new(0)),
+            captures: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Report `displays` instead of a single 1920x1080 one
+    pub fn with_displays(mut self, displays: Vec<DisplayInfo>) -> Self {
+        self.displays = displays;
+        self
+    }
+
+    /// How many times `capture` was called, failed captures included
+    pub fn captures(&self) -> usize {
+        self.captures.load(Ordering::SeqCst)
+    }
+
+    /// Display selected with `set_target_display`
+    pub fn target_display(&self) -> u32 {
+        *self.target.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl ScreenCapture for ScriptedCapture {
+    async fn capture(&mut self) -> Result<ScreenFrame, CaptureError> {
+        self.captures.fetch_add(1, Ordering::SeqCst);
+
+        let mut last = self.last.lock().unwrap();
+        match self.script.lock().unwrap().pop_front() {
+            Some(Ok(frame)) => {
+                *last = Some(frame.clone());
+                Ok(frame)
+            }
+            Some(Err(e)) => Err(e),
+            None => last
+                .clone()
+                .ok_or_else(|| CaptureError::CaptureFailed("no frame scripted".to_string())),
+        }
+    }
+
+    async fn get_displays(&self) -> Result<Vec<DisplayInfo>, CaptureError> {
+        Ok(self.displays.clone())
+    }
+
+    async fn set_target_display(&mut self, display_id: u32) -> Result<(), CaptureError> {
+        if !self.displays.iter().any(|display| display.id == display_id) {
+            return Err(CaptureError::DisplayNotFound(display_id));
+        }
+        *self.target.lock().unwrap() = display_id;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_script_then_static_screen() {
+        let mut capture = ScriptedCapture::scripted([
+            Err(CaptureError::PermissionDenied),
+            Ok(test_frame(1, 2, 2)),
+        ]);
+        let counter = capture.clone();
+
+        assert!(matches!(capture.capture().await, Err(CaptureError::PermissionDenied)));
+        assert_eq!(capture.capture().await.unwrap().sequence, 1);
+        assert_eq!(capture.capture().await.unwrap().sequence, 1);
+        assert_eq!(counter.captures(), 3);
+
+        assert!(ScriptedCapture::new([]).capture().await.is_err());
+        assert!(matches!(capture.set_target_display(3).await, Err(CaptureError::DisplayNotFound(3))));
+    }
+}
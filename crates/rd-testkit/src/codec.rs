@@ -0,0 +1,128 @@
+//! Encoder and decoder that only wrap the pixels
+//!
+//! [`FakeEncoder`] prefixes the frame's pixels with its width and height, which
+//! [`FakeDecoder`] reads back, so frames survive a round trip unchanged.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use rd_core::domain::{
+    error::CodecError,
+    models::{CodecType, EncoderConfig, FrameFormat, ScreenFrame},
+    ports::{Decoder, Encoder},
+};
+
+const HEADER_LEN: usize = 8;
+
+/// [`Encoder`] writing `width`, `height` (little-endian `u32`s) and the raw pixels
+#[derive(Clone, Default)]
+pub struct FakeEncoder {
+    config: EncoderConfig,
+    encoded: Arc<AtomicUsize>,
+    failures: Arc<Mutex<usize>>,
+}
+
+impl FakeEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail the next `count` frames with [`CodecError::EncodingFailed`]
+    pub fn fail_next(&self, count: usize) {
+        *self.failures.lock().unwrap() += count;
+    }
+
+    /// Frames encoded successfully so far
+    pub fn encoded(&self) -> usize {
+        self.encoded.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl Encoder for FakeEncoder {
+    async fn encode(&mut self, frame: &ScreenFrame) -> Result<Vec<u8>, CodecError> {
+        {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(CodecError::EncodingFailed("scripted failure".to_string()));
+            }
+        }
+
+        let mut data = Vec::with_capacity(HEADER_LEN + frame.data.len());
+        data.extend_from_slice(&frame.width.to_le_bytes());
+        data.extend_from_slice(&frame.height.to_le_bytes());
+        data.extend_from_slice(&frame.data);
+
+        self.encoded.fetch_add(1, Ordering::SeqCst);
+        Ok(data)
+    }
+
+    fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    fn set_config(&mut self, config: EncoderConfig) -> Result<(), CodecError> {
+        self.config = config;
+        Ok(())
+    }
+}
+
+/// [`Decoder`] for the output of [`FakeEncoder`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FakeDecoder;
+
+impl FakeDecoder {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl Decoder for FakeDecoder {
+    async fn decode(&mut self, data: &[u8]) -> Result<ScreenFrame, CodecError> {
+        if data.len() < HEADER_LEN {
+            return Err(CodecError::DecodingFailed(format!("{} bytes is too short", data.len())));
+        }
+        let width = u32::from_le_bytes(data[0..4].try_into().unwrap());
+        let height = u32::from_le_bytes(data[4..8].try_into().unwrap());
+
+        Ok(ScreenFrame {
+            sequence: 0,
+            timestamp: 0,
+            data: data[HEADER_LEN..].to_vec(),
+            width,
+            height,
+            format: FrameFormat::Raw,
+        })
+    }
+
+    fn codec_type(&self) -> CodecType {
+        CodecType::Jpeg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_frame;
+
+    #[tokio::test]
+    async fn test_round_trip_and_failures() {
+        let mut encoder = FakeEncoder::new();
+        let frame = test_frame(7, 3, 2);
+
+        encoder.fail_next(1);
+        assert!(encoder.encode(&frame).await.is_err());
+
+        let data = encoder.encode(&frame).await.unwrap();
+        let decoded = FakeDecoder::new().decode(&data).await.unwrap();
+        assert_eq!((decoded.width, decoded.height), (3, 2));
+        assert_eq!(decoded.data, frame.data);
+        assert_eq!(encoder.encoded(), 1);
+
+        assert!(FakeDecoder::new().decode(&[1, 2]).await.is_err());
+    }
+}
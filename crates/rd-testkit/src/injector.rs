@@ -0,0 +1,47 @@
+//! Input injector that records what it is asked to inject
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use rd_core::domain::{
+    error::InjectionError,
+    models::{InputEvent, InputKind},
+    ports::InputInjector,
+};
+
+/// [`InputInjector`] keeping every injected event instead of touching the OS
+#[derive(Clone, Default)]
+pub struct RecordingInjector {
+    events: Arc<Mutex<Vec<InputEvent>>>,
+    refused: Vec<InputKind>,
+}
+
+impl RecordingInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuse events of `kind` with [`InjectionError::UnsupportedEvent`], like a
+    /// backend without a keyboard
+    pub fn refusing(mut self, kind: InputKind) -> Self {
+        self.refused.push(kind);
+        self
+    }
+
+    /// Events injected so far, in order
+    pub fn events(&self) -> Vec<InputEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl InputInjector for RecordingInjector {
+    async fn inject(&mut self, event: InputEvent) -> Result<(), InjectionError> {
+        if self.refused.contains(&event.kind()) {
+            return Err(InjectionError::UnsupportedEvent);
+        }
+        self.events.lock().unwrap().push(event);
+        Ok(())
+    }
+}
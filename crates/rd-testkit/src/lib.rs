@@ -0,0 +1,24 @@
+//! Fakes for every port in `rd_core::domain::ports`, for tests of the
+//! application services and the binaries built on them
+//!
+//! Fakes that tests inspect after handing them to a service (recorded input,
+//! frames captured so far, tokens issued) are `Clone` and share their state
+//! between clones: keep one clone and move the other into the service.
+
+pub mod auth;
+pub mod capture;
+pub mod codec;
+pub mod injector;
+pub mod transport;
+
+pub use auth::FakeAuthenticator;
+pub use capture::{test_frame, ScriptedCapture};
+pub use codec::{FakeDecoder, FakeEncoder};
+pub use injector::RecordingInjector;
+pub use transport::ScriptedTransport;
+
+/// Connected transport pairs, optionally over a slow or lossy link
+pub use rd_transport::{LinkProfile, LoopbackTransport};
+
+/// Session repository for tests; keeps everything in memory
+pub use rd_storage::InMemorySessionRepository;
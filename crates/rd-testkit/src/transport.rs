@@ -0,0 +1,70 @@
+//! Scripted transport
+//!
+//! [`ScriptedTransport`] answers with canned replies and records what it was
+//! sent. For two ends that talk to each other, over an ideal or a slow and
+//! lossy link, use the re-exported [`LoopbackTransport`](crate::LoopbackTransport).
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use rd_core::domain::{
+    error::TransportError,
+    models::WireFormat,
+    ports::{ProtocolMessage, Transport},
+};
+
+/// Transport answering every `receive` with the next canned reply
+///
+/// Once the replies run out, `receive` fails with [`TransportError::Closed`].
+#[derive(Clone, Default)]
+pub struct ScriptedTransport {
+    replies: Arc<Mutex<VecDeque<ProtocolMessage>>>,
+    sent: Arc<Mutex<Vec<ProtocolMessage>>>,
+    wire_format: Arc<Mutex<Option<WireFormat>>>,
+}
+
+impl ScriptedTransport {
+    pub fn new(replies: impl IntoIterator<Item = ProtocolMessage>) -> Self {
+        Self {
+            replies: Arc::new(Mutex::new(replies.into_iter().collect())),
+            sent: Arc::default(),
+            wire_format: Arc::default(),
+        }
+    }
+
+    /// Messages sent so far, in order
+    pub fn sent(&self) -> Vec<ProtocolMessage> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Wire format the transport was last told to use
+    pub fn wire_format(&self) -> Option<WireFormat> {
+        *self.wire_format.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl Transport for ScriptedTransport {
+    async fn send(&mut self, message: ProtocolMessage) -> Result<(), TransportError> {
+        self.sent.lock().unwrap().push(message);
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<ProtocolMessage, TransportError> {
+        self.replies.lock().unwrap().pop_front().ok_or(TransportError::Closed)
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        Ok(())
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        *self.wire_format.lock().unwrap() = Some(format);
+    }
+
+    fn is_connected(&self) -> bool {
+        true
+    }
+}
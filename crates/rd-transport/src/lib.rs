@@ -1,9 +1,18 @@
+pub mod loopback;
 pub mod protocol;
+pub mod pump;
 pub mod quic;
+pub mod reconnect;
 pub mod webrtc;
 
+pub use loopback::{LinkProfile, LoopbackTransport};
 pub use protocol::*;
-pub use quic::{QuicClient, QuicServer, QuicTransport};
+pub use pump::{spawn_pump, TransportPump};
+pub use quic::{
+    CertificateAuthority, ClientIdentity, EnrollmentQueue, QuicClient, QuicServer, QuicTransport, QuicTuning,
+    ServerIdentity, ServerTrust, TrustSettings,
+};
+pub use reconnect::Backoff;
 pub use webrtc::{WebRTCTransport, SignalingClient};
 
 // Re-export core Transport trait
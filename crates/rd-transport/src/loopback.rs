@@ -0,0 +1,267 @@
+//! In-process transport pair
+//!
+//! Both ends live in the same process and exchange serialized messages over
+//! channels, so agent, server and client can talk to each other in tests
+//! without sockets, certificates or a network. Messages are still encoded in
+//! the agreed [wire format](crate::protocol::wire), and the link can be made
+//! slow or lossy with [`LinkProfile`].
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::debug;
+
+use rd_core::domain::{
+    models::WireFormat,
+    ports::{Transport, ProtocolMessage},
+    error::TransportError,
+};
+
+use crate::protocol::{codec, WireCodec, DEFAULT_MAX_MESSAGE_SIZE};
+
+/// How the link between two loopback ends behaves, in each direction
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LinkProfile {
+    /// Bytes per second the link carries; unlimited if `None`
+    pub bandwidth: Option<u64>,
+    /// Propagation delay added to every message
+    pub delay: Duration,
+    /// Share of messages dropped, from 0.0 to 1.0, as if sent as datagrams
+    pub drop_rate: f64,
+    /// Picks which messages are dropped, so a run can be repeated exactly
+    pub seed: u64,
+}
+
+impl LinkProfile {
+    pub fn with_bandwidth(mut self, bytes_per_second: u64) -> Self {
+        self.bandwidth = Some(bytes_per_second);
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn with_drops(mut self, drop_rate: f64, seed: u64) -> Self {
+        self.drop_rate = drop_rate;
+        self.seed = seed;
+        self
+    }
+
+    /// Time `bytes` occupy the link
+    fn transmission_time(&self, bytes: usize) -> Duration {
+        match self.bandwidth {
+            Some(bandwidth) if bandwidth > 0 => Duration::from_secs_f64(bytes as f64 / bandwidth as f64),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Serialized message and the time it reaches the other end
+type Packet = (Instant, Vec<u8>);
+
+/// One end of an in-process connection
+///
+/// Messages arrive in order. With limited bandwidth `send` waits until the
+/// message has left the link, like a write on a saturated connection; delay
+/// is added on top of that. Closing one end fails the other end's `send` and,
+/// once everything in flight was delivered, its `receive`.
+pub struct LoopbackTransport {
+    outgoing: Option<mpsc::UnboundedSender<Packet>>,
+    incoming: mpsc::UnboundedReceiver<Packet>,
+    /// Packet taken off the channel before it was due; survives a cancelled `receive`
+    pending: Option<Packet>,
+    profile: LinkProfile,
+    /// When the link in our sending direction is free again
+    link_free_at: Instant,
+    rng: u64,
+    wire: &'static dyn WireCodec,
+}
+
+impl LoopbackTransport {
+    /// Two connected ends over an ideal link
+    pub fn pair() -> (Self, Self) {
+        Self::pair_with(LinkProfile::default())
+    }
+
+    /// Two connected ends over a link behaving like `profile` in both directions
+    pub fn pair_with(profile: LinkProfile) -> (Self, Self) {
+        let (a_tx, a_rx) = mpsc::unbounded_channel();
+        let (b_tx, b_rx) = mpsc::unbounded_channel();
+        let end = |outgoing, incoming, seed| LoopbackTransport {
+            outgoing: Some(outgoing),
+            incoming,
+            pending: None,
+            profile,
+            link_free_at: Instant::now(),
+            rng: seed,
+            wire: codec(WireFormat::Bincode),
+        };
+        // The two directions drop different messages
+        (end(a_tx, b_rx, profile.seed), end(b_tx, a_rx, !profile.seed))
+    }
+
+    /// Whether the link drops the next message
+    fn drop_next(&mut self) -> bool {
+        if self.profile.drop_rate <= 0.0 {
+            return false;
+        }
+        // splitmix64
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < self.profile.drop_rate
+    }
+}
+
+#[async_trait]
+impl Transport for LoopbackTransport {
+    async fn send(&mut self, message: ProtocolMessage) -> Result<(), TransportError> {
+        if !self.is_connected() {
+            return Err(TransportError::Closed);
+        }
+
+        let data = self.wire.encode(&message)?;
+
+        // Wait for the link to carry the message
+        let sent_at = Instant::now().max(self.link_free_at) + self.profile.transmission_time(data.len());
+        self.link_free_at = sent_at;
+        tokio::time::sleep_until(sent_at).await;
+
+        if self.drop_next() {
+            debug!("Loopback dropped message ({} bytes)", data.len());
+            return Ok(());
+        }
+
+        let outgoing = self.outgoing.as_ref().ok_or(TransportError::Closed)?;
+        outgoing
+            .send((sent_at + self.profile.delay, data))
+            .map_err(|_| TransportError::Closed)
+    }
+
+    async fn receive(&mut self) -> Result<ProtocolMessage, TransportError> {
+        if self.pending.is_none() {
+            self.pending = Some(self.incoming.recv().await.ok_or(TransportError::Closed)?);
+        }
+        if let Some((due, _)) = &self.pending {
+            tokio::time::sleep_until(*due).await;
+        }
+
+        let (_, data) = self.pending.take().ok_or(TransportError::Closed)?;
+        self.wire.decode(&data, DEFAULT_MAX_MESSAGE_SIZE)
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        self.outgoing = None;
+        self.incoming.close();
+        Ok(())
+    }
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.wire = codec(format);
+    }
+
+    fn is_connected(&self) -> bool {
+        self.outgoing.as_ref().is_some_and(|outgoing| !outgoing.is_closed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_core::domain::models::FrameFormat;
+
+    fn frame(sequence: u64, size: usize) -> ProtocolMessage {
+        ProtocolMessage::ScreenFrame {
+            sequence,
+            timestamp: 0,
+            data: vec![0; size],
+            width: 1,
+            height: 1,
+            format: FrameFormat::Jpeg,
+        }
+    }
+
+    fn sequence(message: ProtocolMessage) -> u64 {
+        match message {
+            ProtocolMessage::ScreenFrame { sequence, .. } => sequence,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_messages_round_trip_through_the_codec() {
+        let (mut a, mut b) = LoopbackTransport::pair();
+
+        a.send(frame(1, 16)).await.unwrap();
+        b.send(ProtocolMessage::Heartbeat { timestamp: 9 }).await.unwrap();
+
+        assert_eq!(sequence(b.receive().await.unwrap()), 1);
+        assert!(matches!(a.receive().await.unwrap(), ProtocolMessage::Heartbeat { timestamp: 9 }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bandwidth_and_delay() {
+        // 10 kB/s: a ~1 kB frame takes about 100 ms to send, then 20 ms to arrive
+        let profile = LinkProfile::default()
+            .with_bandwidth(10_000)
+            .with_delay(Duration::from_millis(20));
+        let (mut a, mut b) = LoopbackTransport::pair_with(profile);
+        let start = Instant::now();
+
+        a.send(frame(0, 1000)).await.unwrap();
+        let sent = start.elapsed();
+        assert!(sent >= Duration::from_millis(100) && sent < Duration::from_millis(110), "{:?}", sent);
+
+        // Not due yet: a cancelled receive keeps the message
+        assert!(tokio::time::timeout(Duration::from_millis(10), b.receive()).await.is_err());
+        assert_eq!(sequence(b.receive().await.unwrap()), 0);
+        assert_eq!(start.elapsed(), sent + Duration::from_millis(20));
+
+        // The link is idle again, so the next frame takes as long
+        a.send(frame(1, 1000)).await.unwrap();
+        assert_eq!(sequence(b.receive().await.unwrap()), 1);
+        assert!(start.elapsed() >= 2 * sent);
+    }
+
+    #[tokio::test]
+    async fn test_drops_are_reproducible() {
+        let delivered = |seed| async move {
+            let (mut a, mut b) = LoopbackTransport::pair_with(LinkProfile::default().with_drops(0.3, seed));
+            for i in 0..100 {
+                a.send(frame(i, 1)).await.unwrap();
+            }
+            a.close().await.unwrap();
+
+            let mut received = Vec::new();
+            while let Ok(message) = b.receive().await {
+                received.push(sequence(message));
+            }
+            received
+        };
+
+        let first = delivered(1).await;
+        assert!((50..90).contains(&first.len()), "{} of 100 delivered", first.len());
+        assert!(first.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(first, delivered(1).await);
+        assert_ne!(first, delivered(2).await);
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let (mut a, mut b) = LoopbackTransport::pair();
+        a.send(frame(0, 1)).await.unwrap();
+        a.close().await.unwrap();
+
+        assert!(!a.is_connected() && !b.is_connected());
+        assert!(matches!(b.send(frame(1, 1)).await, Err(TransportError::Closed)));
+        // What was in flight is still delivered
+        assert_eq!(sequence(b.receive().await.unwrap()), 0);
+        assert!(matches!(b.receive().await, Err(TransportError::Closed)));
+    }
+}
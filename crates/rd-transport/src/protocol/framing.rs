@@ -0,0 +1,204 @@
+//! Framing of serialized messages on a byte stream
+//!
+//! A message goes out as one or more chunks, each behind a 4-byte big-endian
+//! header: the top bit is set when more chunks of the same message follow,
+//! the other 31 bits give the chunk's length. A message up to
+//! [`MAX_CHUNK_SIZE`] is a single chunk, which is the same as a plain length
+//! prefix.
+//!
+//! [`MessageCodec`] reassembles chunks as their bytes arrive. It refuses a
+//! message as soon as a header would take it past the limit, and only ever
+//! buffers bytes it actually received, so a peer cannot make it reserve
+//! memory by announcing a large length.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use rd_core::domain::error::TransportError;
+
+/// Largest message a codec accepts unless told otherwise
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Largest chunk a message is split into when sent
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of a chunk header
+pub const CHUNK_HEADER: usize = 4;
+
+/// Header bit telling that more chunks of the message follow
+const MORE_CHUNKS: u32 = 1 << 31;
+
+/// Chunks messages on their way out and reassembles them on their way in
+#[derive(Debug)]
+pub struct MessageCodec {
+    max_message_size: usize,
+    /// Chunk being read: bytes of it still to come, and whether it ends the message
+    chunk: Option<(usize, bool)>,
+    /// The message reassembled so far
+    message: BytesMut,
+}
+
+impl MessageCodec {
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            max_message_size,
+            chunk: None,
+            message: BytesMut::new(),
+        }
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Whether part of a message was read and the rest is still to come
+    pub fn is_partial(&self) -> bool {
+        self.chunk.is_some() || !self.message.is_empty()
+    }
+
+    fn too_large(&self, size: usize) -> TransportError {
+        TransportError::ProtocolError(format!(
+            "Message of at least {} bytes exceeds the limit of {} bytes",
+            size, self.max_message_size
+        ))
+    }
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MESSAGE_SIZE)
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Bytes;
+    type Error = TransportError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Bytes>, TransportError> {
+        loop {
+            let (remaining, last) = match self.chunk {
+                Some(chunk) => chunk,
+                None => {
+                    if src.len() < CHUNK_HEADER {
+                        return Ok(None);
+                    }
+                    let header = src.get_u32();
+                    let len = (header & !MORE_CHUNKS) as usize;
+                    if self.message.len() + len > self.max_message_size {
+                        return Err(self.too_large(self.message.len() + len));
+                    }
+                    (len, header & MORE_CHUNKS == 0)
+                }
+            };
+
+            let available = remaining.min(src.len());
+            self.message.extend_from_slice(&src.split_to(available));
+            if available < remaining {
+                self.chunk = Some((remaining - available, last));
+                return Ok(None);
+            }
+
+            self.chunk = None;
+            if last {
+                return Ok(Some(self.message.split().freeze()));
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Bytes>, TransportError> {
+        match self.decode(src)? {
+            Some(message) => Ok(Some(message)),
+            None if src.is_empty() && !self.is_partial() => Ok(None),
+            None => Err(TransportError::ProtocolError("Stream ended inside a message".to_string())),
+        }
+    }
+}
+
+impl Encoder<&[u8]> for MessageCodec {
+    type Error = TransportError;
+
+    fn encode(&mut self, data: &[u8], dst: &mut BytesMut) -> Result<(), TransportError> {
+        if data.len() > self.max_message_size {
+            return Err(self.too_large(data.len()));
+        }
+
+        dst.reserve(data.len() + CHUNK_HEADER * (data.len() / MAX_CHUNK_SIZE + 1));
+        let mut chunks = data.chunks(MAX_CHUNK_SIZE).peekable();
+        if chunks.peek().is_none() {
+            dst.put_u32(0);
+        }
+        while let Some(chunk) = chunks.next() {
+            let more = if chunks.peek().is_some() { MORE_CHUNKS } else { 0 };
+            dst.put_u32(chunk.len() as u32 | more);
+            dst.put_slice(chunk);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(codec: &mut MessageCodec, data: &[u8]) -> BytesMut {
+        let mut framed = BytesMut::new();
+        codec.encode(data, &mut framed).unwrap();
+        framed
+    }
+
+    #[test]
+    fn test_large_messages_are_chunked_and_reassembled() {
+        let mut codec = MessageCodec::default();
+        let message: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let mut framed = encode(&mut codec, &message);
+        framed.extend_from_slice(&encode(&mut codec, b"next"));
+        assert_eq!(framed.len(), message.len() + 4 + 4 * CHUNK_HEADER + 4);
+
+        // Fed a few bytes at a time, as reads off a stream would be
+        let mut received = Vec::new();
+        let mut src = BytesMut::new();
+        for piece in framed.chunks(1000) {
+            src.extend_from_slice(piece);
+            while let Some(message) = codec.decode(&mut src).unwrap() {
+                received.push(message);
+            }
+        }
+        assert_eq!(received, vec![Bytes::from(message), Bytes::from_static(b"next")]);
+        assert!(!codec.is_partial());
+    }
+
+    #[test]
+    fn test_small_messages_keep_a_plain_length_prefix() {
+        let mut codec = MessageCodec::default();
+        assert_eq!(&encode(&mut codec, b"abc")[..], [0, 0, 0, 3, b'a', b'b', b'c']);
+        assert_eq!(&encode(&mut codec, b"")[..], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_oversized_messages_are_refused_by_their_header() {
+        let mut codec = MessageCodec::new(100);
+        // Claims 4 GiB - 1 without sending any of it
+        let mut src = BytesMut::from(&[0xFF, 0xFF, 0xFF, 0xFF][..]);
+        assert!(matches!(codec.decode(&mut src), Err(TransportError::ProtocolError(_))));
+
+        // Chunks that only add up to too much
+        let mut codec = MessageCodec::new(100);
+        let mut src = BytesMut::new();
+        for _ in 0..2 {
+            src.put_u32(60 | MORE_CHUNKS);
+            src.put_slice(&[0; 60]);
+        }
+        assert!(matches!(codec.decode(&mut src), Err(TransportError::ProtocolError(_))));
+
+        assert!(MessageCodec::new(100).encode(&[0; 101][..], &mut BytesMut::new()).is_err());
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let mut codec = MessageCodec::default();
+        let mut src = BytesMut::from(&encode(&mut codec, b"abcdef")[..8]);
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        assert!(matches!(codec.decode_eof(&mut src), Err(TransportError::ProtocolError(_))));
+        assert_eq!(MessageCodec::default().decode_eof(&mut BytesMut::new()).unwrap(), None);
+    }
+}
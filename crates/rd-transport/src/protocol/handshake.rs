@@ -0,0 +1,185 @@
+//! Hello handshake from the connecting side
+
+use std::time::Duration;
+use tracing::debug;
+
+use rd_core::domain::{
+    error::{ApplicationError, AuthError, TransportError},
+    models::{AuthToken, Capabilities, EnrollmentStatus, Platform, PeerRole, WireFormat},
+    ports::{error_codes, ProtocolMessage, Transport},
+    version::VersionRange,
+};
+
+/// How long to wait for the server's answer to `Hello`
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Introduce this peer to the server, agree on a protocol version and announce
+/// what this peer supports
+///
+/// Returns the version the server picked, or
+/// [`TransportError::IncompatibleVersion`] if it speaks none of ours. The
+/// transport then uses the wire format the server picked.
+pub async fn hello<T>(
+    transport: &mut T,
+    device_id: &str,
+    role: PeerRole,
+    capabilities: Capabilities,
+) -> Result<u32, TransportError>
+where
+    T: Transport + ?Sized,
+{
+    transport.send(ProtocolMessage::Hello {
+        versions: VersionRange::SUPPORTED,
+        device_id: device_id.to_string(),
+        platform: Platform::current(),
+        role,
+    }).await?;
+
+    let version = match reply(transport).await? {
+        ProtocolMessage::HelloAck { version } if VersionRange::SUPPORTED.contains(version) => version,
+        ProtocolMessage::HelloAck { version } => return Err(TransportError::IncompatibleVersion(format!(
+            "server chose v{}, we speak {}",
+            version,
+            VersionRange::SUPPORTED
+        ))),
+        ProtocolMessage::Error { code: error_codes::INCOMPATIBLE_VERSION, message } => {
+            return Err(TransportError::IncompatibleVersion(message));
+        }
+        ProtocolMessage::Error { message, .. } => return Err(TransportError::ProtocolError(message)),
+        other => return Err(TransportError::ProtocolError(format!("Expected HelloAck, got {:?}", other))),
+    };
+    debug!("Negotiated protocol version {}", version);
+
+    transport.send(ProtocolMessage::Capabilities {
+        capabilities,
+        wire_formats: WireFormat::ALL.to_vec(),
+    }).await?;
+
+    let format = match reply(transport).await? {
+        ProtocolMessage::WireFormatSelected { format } => format,
+        ProtocolMessage::Error { message, .. } => return Err(TransportError::ProtocolError(message)),
+        other => return Err(TransportError::ProtocolError(format!("Expected WireFormatSelected, got {:?}", other))),
+    };
+    transport.set_wire_format(format);
+    debug!("Using wire format {:?}", format);
+
+    Ok(version)
+}
+
+/// The server's next message, within the handshake timeout
+async fn reply<T>(transport: &mut T) -> Result<ProtocolMessage, TransportError>
+where
+    T: Transport + ?Sized,
+{
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, transport.receive())
+        .await
+        .map_err(|_| TransportError::Timeout)?
+}
+
+/// Present `token` to the server, which registers agents and accepts session
+/// requests only from authenticated peers
+///
+/// A refused token fails with the server's [`AuthError`], e.g.
+/// [`AuthError::TokenExpired`].
+pub async fn authenticate<T>(transport: &mut T, token: AuthToken) -> Result<(), ApplicationError>
+where
+    T: Transport + ?Sized,
+{
+    transport.send(ProtocolMessage::Auth { token }).await?;
+
+    match reply(transport).await? {
+        ProtocolMessage::AuthResponse { success: true, .. } => Ok(()),
+        ProtocolMessage::AuthResponse { error, .. } => Err(AuthError::from_response(error).into()),
+        ProtocolMessage::Error { message, .. } => Err(TransportError::ProtocolError(message).into()),
+        other => Err(TransportError::ProtocolError(format!("Expected AuthResponse, got {:?}", other)).into()),
+    }
+}
+
+/// Ask the server to sign `csr`, a PEM certificate signing request, instead
+/// of authenticating
+///
+/// The server closes the connection after answering, so a `Pending` request
+/// is asked about again on a new connection.
+pub async fn enroll<T>(transport: &mut T, csr: String) -> Result<EnrollmentStatus, TransportError>
+where
+    T: Transport + ?Sized,
+{
+    transport.send(ProtocolMessage::EnrollRequest { csr }).await?;
+
+    match reply(transport).await? {
+        ProtocolMessage::EnrollResponse { status } => Ok(status),
+        ProtocolMessage::Error { message, .. } => Err(TransportError::ProtocolError(message)),
+        other => Err(TransportError::ProtocolError(format!("Expected EnrollResponse, got {:?}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_testkit::ScriptedTransport;
+
+    #[tokio::test]
+    async fn test_hello_advertises_supported_versions() {
+        let mut transport = ScriptedTransport::new([
+            ProtocolMessage::HelloAck { version: VersionRange::SUPPORTED.max },
+            ProtocolMessage::WireFormatSelected { format: WireFormat::Postcard },
+        ]);
+
+        let version = hello(&mut transport, "host", PeerRole::Agent, Capabilities::default()).await.unwrap();
+        assert_eq!(version, VersionRange::SUPPORTED.max);
+        assert!(matches!(
+            &transport.sent()[..],
+            [
+                ProtocolMessage::Hello { versions: VersionRange::SUPPORTED, role: PeerRole::Agent, .. },
+                ProtocolMessage::Capabilities { wire_formats, .. },
+            ] if *wire_formats == WireFormat::ALL
+        ));
+        assert_eq!(transport.wire_format(), Some(WireFormat::Postcard));
+    }
+
+    #[tokio::test]
+    async fn test_hello_surfaces_incompatibility() {
+        let mut transport = ScriptedTransport::new([ProtocolMessage::Error {
+            code: error_codes::INCOMPATIBLE_VERSION,
+            message: "server speaks v9".into(),
+        }]);
+        assert!(matches!(
+            hello(&mut transport, "viewer", PeerRole::Client, Capabilities::default()).await,
+            Err(TransportError::IncompatibleVersion(_))
+        ));
+
+        // A server must not pick a version we never offered
+        let mut transport = ScriptedTransport::new([ProtocolMessage::HelloAck {
+            version: VersionRange::SUPPORTED.max + 1,
+        }]);
+        assert!(matches!(
+            hello(&mut transport, "viewer", PeerRole::Client, Capabilities::default()).await,
+            Err(TransportError::IncompatibleVersion(_))
+        ));
+        // Capabilities only follow a successful negotiation
+        assert!(matches!(&transport.sent()[..], [ProtocolMessage::Hello { .. }]));
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_reports_typed_errors() {
+        let token = || AuthToken::new("token", "host");
+
+        let mut transport = ScriptedTransport::new([ProtocolMessage::AuthResponse {
+            success: true,
+            session_id: None,
+            error: None,
+        }]);
+        assert!(authenticate(&mut transport, token()).await.is_ok());
+        assert!(matches!(&transport.sent()[..], [ProtocolMessage::Auth { .. }]));
+
+        let mut transport = ScriptedTransport::new([ProtocolMessage::AuthResponse {
+            success: false,
+            session_id: None,
+            error: Some(AuthError::TokenExpired.to_string()),
+        }]);
+        assert!(matches!(
+            authenticate(&mut transport, token()).await,
+            Err(ApplicationError::Auth(AuthError::TokenExpired))
+        ));
+    }
+}
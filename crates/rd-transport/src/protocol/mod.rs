@@ -1,9 +1,12 @@
-use serde::{Serialize, Deserialize};
-use rd_core::domain::models::*;
-use rd_core::domain::ports::ProtocolMessage as CoreProtocolMessage;
+pub mod framing;
+pub mod handshake;
+pub mod wire;
 
 /// Re-export core protocol message
 pub use rd_core::domain::ports::ProtocolMessage;
+pub use framing::{MessageCodec, DEFAULT_MAX_MESSAGE_SIZE};
+pub use handshake::{authenticate, enroll, hello};
+pub use wire::{codec, WireCodec};
 
 /// Serialize a protocol message to bytes
 pub fn serialize_message(msg: &ProtocolMessage) -> Result<Vec<u8>, bincode::Error> {
@@ -18,6 +21,8 @@ pub fn deserialize_message(data: &[u8]) -> Result<ProtocolMessage, bincode::Erro
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rd_core::domain::models::{Platform, PeerRole};
+    use rd_core::domain::version::VersionRange;
     
     #[test]
     fn test_message_serialization() {
@@ -32,4 +37,32 @@ mod tests {
             _ => panic!("Wrong message type"),
         }
     }
+    
+    #[test]
+    fn test_handshake_encoding_is_stable() {
+        // Peers of every release must be able to decode these, so their bytes never change
+        let hello = ProtocolMessage::Hello {
+            versions: VersionRange::new(2, 3),
+            device_id: "a".into(),
+            platform: Platform::Linux,
+            role: PeerRole::Agent,
+        };
+        assert_eq!(serialize_message(&hello).unwrap(), [
+            0, 0, 0, 0, // variant
+            2, 0, 0, 0, 3, 0, 0, 0, // versions
+            1, 0, 0, 0, 0, 0, 0, 0, b'a', // device_id
+            1, 0, 0, 0, // platform
+            0, 0, 0, 0, // role
+        ]);
+        
+        let ack = ProtocolMessage::HelloAck { version: 2 };
+        assert_eq!(serialize_message(&ack).unwrap(), [1, 0, 0, 0, 2, 0, 0, 0]);
+        
+        let error = ProtocolMessage::Error { code: 426, message: "x".into() };
+        assert_eq!(serialize_message(&error).unwrap(), [
+            2, 0, 0, 0, // variant
+            0xaa, 0x01, 0, 0, // code
+            1, 0, 0, 0, 0, 0, 0, 0, b'x', // message
+        ]);
+    }
 }
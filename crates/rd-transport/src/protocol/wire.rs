@@ -0,0 +1,239 @@
+//! Encodings of protocol messages on the wire
+//!
+//! Peers agree on a [`WireFormat`] during the handshake; [`codec`] gives the
+//! [`WireCodec`] implementing it. Everything before the agreement is bincode.
+
+use std::io::Read;
+
+use rd_core::domain::{
+    error::TransportError,
+    models::WireFormat,
+    ports::{MessageClass, ProtocolMessage},
+};
+
+use super::{deserialize_message, serialize_message};
+
+/// Turns protocol messages into bytes and back
+pub trait WireCodec: Send + Sync {
+    fn encode(&self, message: &ProtocolMessage) -> Result<Vec<u8>, TransportError>;
+
+    /// Decode a message; one that decompresses to more than `max_size` bytes is refused
+    fn decode(&self, data: &[u8], max_size: usize) -> Result<ProtocolMessage, TransportError>;
+}
+
+/// Codec for `format`
+pub fn codec(format: WireFormat) -> &'static dyn WireCodec {
+    match format {
+        WireFormat::Bincode => &Bincode,
+        WireFormat::Postcard => &Postcard,
+        WireFormat::PostcardZstd => &POSTCARD_ZSTD,
+    }
+}
+
+/// bincode 1.x with its default options: fixed-size integers, little endian
+pub struct Bincode;
+
+impl WireCodec for Bincode {
+    fn encode(&self, message: &ProtocolMessage) -> Result<Vec<u8>, TransportError> {
+        serialize_message(message).map_err(|e| TransportError::SerializationError(e.to_string()))
+    }
+
+    fn decode(&self, data: &[u8], _max_size: usize) -> Result<ProtocolMessage, TransportError> {
+        deserialize_message(data).map_err(|e| TransportError::SerializationError(e.to_string()))
+    }
+}
+
+/// postcard: variable-length integers, so most messages come out smaller
+pub struct Postcard;
+
+impl WireCodec for Postcard {
+    fn encode(&self, message: &ProtocolMessage) -> Result<Vec<u8>, TransportError> {
+        postcard::to_stdvec(message).map_err(|e| TransportError::SerializationError(e.to_string()))
+    }
+
+    fn decode(&self, data: &[u8], _max_size: usize) -> Result<ProtocolMessage, TransportError> {
+        postcard::from_bytes(data).map_err(|e| TransportError::SerializationError(e.to_string()))
+    }
+}
+
+/// Another codec's output behind a flag byte, compressed with zstd unless it
+/// is a screen frame, whose data is compressed already, or too small to gain
+pub struct Zstd<C> {
+    inner: C,
+    level: i32,
+}
+
+static POSTCARD_ZSTD: Zstd<Postcard> = Zstd { inner: Postcard, level: 3 };
+
+/// Messages smaller than this are sent as they are
+const MIN_COMPRESSED_SIZE: usize = 128;
+
+const PLAIN: u8 = 0;
+const COMPRESSED: u8 = 1;
+
+impl<C: WireCodec> WireCodec for Zstd<C> {
+    fn encode(&self, message: &ProtocolMessage) -> Result<Vec<u8>, TransportError> {
+        let data = self.inner.encode(message)?;
+        if message.class() != MessageClass::Frame && data.len() >= MIN_COMPRESSED_SIZE {
+            let compressed = zstd::bulk::compress(&data, self.level)?;
+            if compressed.len() < data.len() {
+                return Ok([&[COMPRESSED][..], &compressed].concat());
+            }
+        }
+        Ok([&[PLAIN][..], &data].concat())
+    }
+
+    fn decode(&self, data: &[u8], max_size: usize) -> Result<ProtocolMessage, TransportError> {
+        match data.split_first() {
+            Some((&PLAIN, data)) => self.inner.decode(data, max_size),
+            Some((&COMPRESSED, data)) => {
+                // Bounded like framed messages, whatever the compressed data claims
+                let mut decompressed = Vec::new();
+                zstd::stream::read::Decoder::new(data)?
+                    .take(max_size as u64 + 1)
+                    .read_to_end(&mut decompressed)?;
+                if decompressed.len() > max_size {
+                    return Err(TransportError::ProtocolError("Decompressed message too large".to_string()));
+                }
+                self.inner.decode(&decompressed, max_size)
+            }
+            _ => Err(TransportError::SerializationError("Missing or unknown compression flag".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_core::domain::models::{
+        AuthToken, Capabilities, EnrollmentStatus, FrameFormat, InputEvent, KeyCode, PeerRole, Platform, SessionId,
+    };
+    use rd_core::domain::version::VersionRange;
+    use crate::protocol::DEFAULT_MAX_MESSAGE_SIZE;
+    use uuid::Uuid;
+
+    fn heartbeat() -> ProtocolMessage {
+        ProtocolMessage::Heartbeat { timestamp: 300 }
+    }
+
+    fn key_press() -> ProtocolMessage {
+        ProtocolMessage::InputEvent {
+            session_id: SessionId(Uuid::from_bytes([7; 16])),
+            timestamp: 1,
+            event: InputEvent::KeyPress { key: KeyCode::A, pressed: true },
+        }
+    }
+
+    fn frame() -> ProtocolMessage {
+        ProtocolMessage::ScreenFrame {
+            sequence: 2,
+            timestamp: 3,
+            data: vec![0; 200],
+            width: 1920,
+            height: 1080,
+            format: FrameFormat::Jpeg,
+        }
+    }
+
+    fn encoded(format: WireFormat, message: &ProtocolMessage) -> Vec<u8> {
+        let bytes = codec(format).encode(message).unwrap();
+        assert_eq!(
+            format!("{:?}", codec(format).decode(&bytes, DEFAULT_MAX_MESSAGE_SIZE).unwrap()),
+            format!("{:?}", message),
+        );
+        bytes
+    }
+
+    #[test]
+    fn test_bincode_encoding_is_stable() {
+        assert_eq!(encoded(WireFormat::Bincode, &heartbeat()), [
+            13, 0, 0, 0, // variant
+            0x2c, 0x01, 0, 0, 0, 0, 0, 0, // timestamp
+        ]);
+        assert_eq!(encoded(WireFormat::Bincode, &key_press()), [
+            12, 0, 0, 0, // variant
+            16, 0, 0, 0, 0, 0, 0, 0, // session id length
+            7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, // session id
+            1, 0, 0, 0, 0, 0, 0, 0, // timestamp
+            3, 0, 0, 0, // KeyPress
+            30, 0, 0, 0, // KeyCode::A
+            1, // pressed
+        ]);
+    }
+
+    #[test]
+    fn test_postcard_encoding_is_stable() {
+        assert_eq!(encoded(WireFormat::Postcard, &heartbeat()), [
+            13, // variant
+            0xac, 0x02, // timestamp
+        ]);
+        assert_eq!(encoded(WireFormat::Postcard, &key_press()), [
+            12, // variant
+            16, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, // session id
+            1, 3, 30, 1, // timestamp, KeyPress, KeyCode::A, pressed
+        ]);
+        assert_eq!(&encoded(WireFormat::Postcard, &frame())[..8], [
+            11, // variant
+            2, 3, // sequence, timestamp
+            0xc8, 0x01, 0, 0, 0, // data
+        ]);
+    }
+
+    #[test]
+    fn test_zstd_compresses_only_what_gains() {
+        // Small messages and frames go as they are, behind the flag
+        assert_eq!(encoded(WireFormat::PostcardZstd, &heartbeat()), [0, 13, 0xac, 0x02]);
+        assert_eq!(encoded(WireFormat::PostcardZstd, &frame())[..2], [0, 11]);
+
+        let agents = ProtocolMessage::SessionEnd {
+            session_id: SessionId::new(),
+            reason: "connection lost ".repeat(20),
+        };
+        let compressed = encoded(WireFormat::PostcardZstd, &agents);
+        assert_eq!(compressed[0], 1);
+        assert!(compressed.len() < Postcard.encode(&agents).unwrap().len() / 4);
+
+        assert!(codec(WireFormat::PostcardZstd).decode(&[], DEFAULT_MAX_MESSAGE_SIZE).is_err());
+        assert!(codec(WireFormat::PostcardZstd).decode(&[2, 13, 0], DEFAULT_MAX_MESSAGE_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_zstd_refuses_messages_decompressing_over_the_limit() {
+        let message = ProtocolMessage::SessionEnd {
+            session_id: SessionId::new(),
+            reason: "connection lost ".repeat(200),
+        };
+        let compressed = encoded(WireFormat::PostcardZstd, &message);
+        assert!(compressed.len() < 1024);
+
+        assert!(codec(WireFormat::PostcardZstd).decode(&compressed, 4096).is_ok());
+        assert!(matches!(
+            codec(WireFormat::PostcardZstd).decode(&compressed, 1024),
+            Err(TransportError::ProtocolError(_))
+        ));
+    }
+
+    #[test]
+    fn test_every_format_carries_the_handshake() {
+        let messages = [
+            ProtocolMessage::Hello {
+                versions: VersionRange::SUPPORTED,
+                device_id: "host".into(),
+                platform: Platform::Linux,
+                role: PeerRole::Agent,
+            },
+            ProtocolMessage::Capabilities {
+                capabilities: Capabilities::default(),
+                wire_formats: WireFormat::ALL.to_vec(),
+            },
+            ProtocolMessage::Auth { token: AuthToken::new("secret", "host") },
+            ProtocolMessage::EnrollResponse { status: EnrollmentStatus::Pending },
+            ProtocolMessage::WireFormatSelected { format: WireFormat::Postcard },
+        ];
+        for format in WireFormat::ALL {
+            for message in &messages {
+                encoded(format, message);
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Transport pump
+//!
+//! Runs a [`Transport`] on its own task so that several producers can send
+//! while another task waits for incoming messages, without sharing a lock.
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use rd_core::domain::{
+    ports::{Transport, ProtocolMessage},
+    error::TransportError,
+};
+
+/// Channel ends of a running pump
+pub struct TransportPump {
+    /// Queue towards the peer; senders wait when it is full
+    pub outgoing: mpsc::Sender<ProtocolMessage>,
+    /// Messages received from the peer
+    pub incoming: mpsc::Receiver<ProtocolMessage>,
+    /// Finishes when the transport closes, with the error that ended it
+    pub task: JoinHandle<Result<(), TransportError>>,
+}
+
+/// Move `transport` onto a task that drains `outgoing` and fills `incoming`
+///
+/// `capacity` bounds both queues. The pump sends `Disconnect` and closes the
+/// transport once every outgoing sender is dropped, and stops when the peer
+/// closes or the incoming receiver is dropped.
+pub fn spawn_pump<T>(transport: T, capacity: usize) -> TransportPump
+where
+    T: Transport + 'static,
+{
+    let (outgoing, outgoing_rx) = mpsc::channel(capacity);
+    let (incoming_tx, incoming) = mpsc::channel(capacity);
+    let task = tokio::spawn(run_pump(transport, outgoing_rx, incoming_tx));
+
+    TransportPump { outgoing, incoming, task }
+}
+
+async fn run_pump<T: Transport>(
+    mut transport: T,
+    mut outgoing: mpsc::Receiver<ProtocolMessage>,
+    incoming: mpsc::Sender<ProtocolMessage>,
+) -> Result<(), TransportError> {
+    loop {
+        tokio::select! {
+            message = outgoing.recv() => match message {
+                Some(message) => transport.send(message).await?,
+                None => {
+                    debug!("All senders dropped, closing transport");
+                    if let Err(e) = transport.send(ProtocolMessage::Disconnect).await {
+                        warn!("Failed to send disconnect: {}", e);
+                    }
+                    return transport.close().await;
+                }
+            },
+            // Receiving is cancel-safe, so an outgoing message never loses incoming data
+            result = transport.receive() => {
+                if incoming.send(result?).await.is_err() {
+                    debug!("Incoming receiver dropped, closing transport");
+                    return transport.close().await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    /// Transport backed by a pair of channels
+    struct ChannelTransport {
+        tx: mpsc::UnboundedSender<ProtocolMessage>,
+        rx: mpsc::UnboundedReceiver<ProtocolMessage>,
+        closed: bool,
+    }
+
+    #[async_trait]
+    impl Transport for ChannelTransport {
+        async fn send(&mut self, message: ProtocolMessage) -> Result<(), TransportError> {
+            self.tx.send(message).map_err(|_| TransportError::Closed)
+        }
+
+        async fn receive(&mut self) -> Result<ProtocolMessage, TransportError> {
+            self.rx.recv().await.ok_or(TransportError::Closed)
+        }
+
+        async fn close(&mut self) -> Result<(), TransportError> {
+            self.closed = true;
+            Ok(())
+        }
+
+        fn is_connected(&self) -> bool {
+            !self.closed
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pump_sends_while_waiting_to_receive() {
+        let (to_pump, rx) = mpsc::unbounded_channel();
+        let (tx, mut from_pump) = mpsc::unbounded_channel();
+        let mut pump = spawn_pump(ChannelTransport { tx, rx, closed: false }, 4);
+
+        // Nothing has been received yet, but sending must not wait for it
+        pump.outgoing.send(ProtocolMessage::Heartbeat { timestamp: 1 }).await.unwrap();
+        assert!(matches!(from_pump.recv().await, Some(ProtocolMessage::Heartbeat { timestamp: 1 })));
+
+        to_pump.send(ProtocolMessage::Heartbeat { timestamp: 2 }).unwrap();
+        assert!(matches!(pump.incoming.recv().await, Some(ProtocolMessage::Heartbeat { timestamp: 2 })));
+
+        // Dropping the last sender says goodbye and closes
+        drop(pump.outgoing);
+        assert!(matches!(from_pump.recv().await, Some(ProtocolMessage::Disconnect)));
+        assert!(pump.task.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pump_stops_when_peer_closes() {
+        let (to_pump, rx) = mpsc::unbounded_channel();
+        let (tx, _from_pump) = mpsc::unbounded_channel();
+        let mut pump = spawn_pump(ChannelTransport { tx, rx, closed: false }, 4);
+
+        drop(to_pump);
+        assert!(pump.incoming.recv().await.is_none());
+        assert!(matches!(pump.task.await.unwrap(), Err(TransportError::Closed)));
+    }
+}
@@ -0,0 +1,226 @@
+//! Client certificates for mutual TLS
+//!
+//! The server runs its own [`CertificateAuthority`] and signs the certificate
+//! signing requests of devices an administrator approved. A device then
+//! connects with its [`ClientIdentity`], and the server takes the device id
+//! from the certificate's common name instead of trusting what the device
+//! says about itself.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use chrono::{Datelike, Utc};
+use quinn::Connection;
+use rcgen::{
+    BasicConstraints, CertificateParams, CertificateSigningRequestParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
+use tracing::info;
+
+use super::tls::{crypto_provider, write_file};
+
+/// How long a device certificate is valid
+const DEVICE_CERT_VALIDITY_DAYS: i64 = 5 * 365;
+
+/// CA issuing device certificates, kept by the server
+pub struct CertificateAuthority {
+    /// Certificate as stored, which devices' certificates chain to
+    cert_der: CertificateDer<'static>,
+    /// Same name and key, to sign with
+    issuer: rcgen::Certificate,
+    key: KeyPair,
+}
+
+impl CertificateAuthority {
+    /// Read the CA certificate and key, or create both if neither file exists yet
+    pub fn load_or_generate(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let (cert_path, key_path) = (cert_path.as_ref(), key_path.as_ref());
+        if !cert_path.exists() && !key_path.exists() {
+            let key = KeyPair::generate()?;
+            let mut params = CertificateParams::new(Vec::new())?;
+            params.distinguished_name = DistinguishedName::new();
+            params.distinguished_name.push(DnType::CommonName, "Remote Desktop device CA");
+            params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+            params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+            let cert = params.self_signed(&key)?;
+
+            write_file(cert_path, cert.pem().as_bytes(), false)?;
+            write_file(key_path, key.serialize_pem().as_bytes(), true)?;
+            info!("Generated device CA {}", cert_path.display());
+        }
+
+        let cert_pem = std::fs::read_to_string(cert_path)
+            .with_context(|| format!("reading CA certificate {}", cert_path.display()))?;
+        let key_pem = std::fs::read_to_string(key_path)
+            .with_context(|| format!("reading CA key {}", key_path.display()))?;
+        let key = KeyPair::from_pem(&key_pem)?;
+        let issuer = CertificateParams::from_ca_cert_pem(&cert_pem)?.self_signed(&key)?;
+
+        Ok(Self {
+            cert_der: CertificateDer::from_pem_slice(cert_pem.as_bytes())?,
+            issuer,
+            key,
+        })
+    }
+
+    /// Issue a certificate for `device_id` to the key of a PEM signing request
+    ///
+    /// The certificate names `device_id` whatever the request asked for, so a
+    /// device cannot approve itself a different identity.
+    pub fn sign(&self, csr_pem: &str, device_id: &str) -> anyhow::Result<String> {
+        let mut csr = CertificateSigningRequestParams::from_pem(csr_pem).context("invalid signing request")?;
+
+        let now = Utc::now();
+        let expires = now + chrono::Duration::days(DEVICE_CERT_VALIDITY_DAYS);
+        let params = &mut csr.params;
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, device_id);
+        params.subject_alt_names = Vec::new();
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.not_before = rcgen::date_time_ymd(now.year(), now.month() as u8, now.day() as u8);
+        params.not_after = rcgen::date_time_ymd(expires.year(), expires.month() as u8, expires.day() as u8);
+
+        Ok(csr.signed_by(&self.issuer, &self.key)?.pem())
+    }
+
+    /// Verifier accepting certificates this CA issued, and connections without
+    /// one so devices can still enroll
+    pub fn client_verifier(&self) -> anyhow::Result<Arc<dyn ClientCertVerifier>> {
+        let mut roots = RootCertStore::empty();
+        roots.add(self.cert_der.clone())?;
+        Ok(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), crypto_provider())
+            .allow_unauthenticated()
+            .build()?)
+    }
+}
+
+/// Certificate and key a device presents to the server
+pub struct ClientIdentity {
+    pub cert_chain: Vec<CertificateDer<'static>>,
+    pub key: PrivateKeyDer<'static>,
+}
+
+impl ClientIdentity {
+    /// Read a PEM certificate and private key
+    pub fn load(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let (cert_path, key_path) = (cert_path.as_ref(), key_path.as_ref());
+        let cert_chain = CertificateDer::pem_file_iter(cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("reading certificate {}", cert_path.display()))?;
+        anyhow::ensure!(!cert_chain.is_empty(), "no certificate in {}", cert_path.display());
+        let key = PrivateKeyDer::from_pem_file(key_path)
+            .with_context(|| format!("reading private key {}", key_path.display()))?;
+
+        Ok(Self { cert_chain, key })
+    }
+
+    /// PEM signing request for `device_id`, from the key in `key_path`
+    ///
+    /// The key is generated and stored there first if the file does not exist.
+    pub fn signing_request(key_path: impl AsRef<Path>, device_id: &str) -> anyhow::Result<String> {
+        let key_path = key_path.as_ref();
+        if !key_path.exists() {
+            write_file(key_path, KeyPair::generate()?.serialize_pem().as_bytes(), true)?;
+            info!("Generated device key {}", key_path.display());
+        }
+        let key_pem = std::fs::read_to_string(key_path)
+            .with_context(|| format!("reading private key {}", key_path.display()))?;
+        let key = KeyPair::from_pem(&key_pem)?;
+
+        let mut params = CertificateParams::new(Vec::new())?;
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, device_id);
+        Ok(params.serialize_request(&key)?.pem()?)
+    }
+}
+
+/// Device id a certificate was issued to: its subject's common name
+pub fn device_id_of(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+    let common_name = cert.subject().iter_common_name().next()?.as_str().ok()?;
+    Some(common_name.to_string())
+}
+
+/// Device id of the certificate the peer of `connection` presented, if any
+///
+/// Only certificates the server's client verifier accepted make it this far.
+pub fn peer_device_id(connection: &Connection) -> Option<String> {
+    let certs = connection
+        .peer_identity()?
+        .downcast::<Vec<CertificateDer<'static>>>()
+        .ok()?;
+    device_id_of(certs.first()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{QuicClient, QuicServer, ServerIdentity, ServerTrust};
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rd-ca-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_issued_certificate_names_the_approved_device() {
+        let dir = temp_dir("sign");
+        let ca = CertificateAuthority::load_or_generate(dir.join("ca.crt"), dir.join("ca.key")).unwrap();
+        let csr = ClientIdentity::signing_request(dir.join("device.key"), "someone-else").unwrap();
+
+        let cert = ca.sign(&csr, "host").unwrap();
+        let cert = CertificateDer::from_pem_slice(cert.as_bytes()).unwrap();
+        assert_eq!(device_id_of(&cert).as_deref(), Some("host"));
+
+        // The same CA after a restart
+        let reloaded = CertificateAuthority::load_or_generate(dir.join("ca.crt"), dir.join("ca.key")).unwrap();
+        assert_eq!(reloaded.cert_der, ca.cert_der);
+        assert!(ca.sign("not a request", "host").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_sees_the_certificate_identity() {
+        let dir = temp_dir("mtls");
+        let ca = CertificateAuthority::load_or_generate(dir.join("ca.crt"), dir.join("ca.key")).unwrap();
+        let identity = ServerIdentity::self_signed(vec!["localhost".into()]).unwrap();
+        let server = QuicServer::with_client_ca(
+            "127.0.0.1:0".parse().unwrap(),
+            identity,
+            &ca,
+        ).unwrap();
+        let addr = server.local_addr().unwrap();
+        let accepted = tokio::spawn(async move {
+            let mut ids = Vec::new();
+            for _ in 0..2 {
+                let connection = server.accept().await.unwrap();
+                ids.push(peer_device_id(&connection));
+            }
+            ids
+        });
+
+        let key = dir.join("device.key");
+        let cert = dir.join("device.crt");
+        let csr = ClientIdentity::signing_request(&key, "host").unwrap();
+        std::fs::write(&cert, ca.sign(&csr, "host").unwrap()).unwrap();
+
+        let client = QuicClient::with_trust(ServerTrust::Insecure)
+            .unwrap()
+            .with_client_identity(ClientIdentity::load(&cert, &key).unwrap());
+        let _with_cert = client.connect(addr).await.unwrap();
+        let anonymous = QuicClient::with_trust(ServerTrust::Insecure).unwrap();
+        let _without_cert = anonymous.connect(addr).await.unwrap();
+
+        assert_eq!(accepted.await.unwrap(), vec![Some("host".to_string()), None]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
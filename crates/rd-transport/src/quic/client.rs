@@ -1,102 +1,133 @@
-use quinn::{Endpoint, ClientConfig, Connection};
-use std::sync::Arc;
+use quinn::{Endpoint, ClientConfig, Connection, TransportConfig};
+use std::sync::{Arc, Mutex};
 use std::net::SocketAddr;
-use tracing::{info, error};
+use tracing::info;
+
+use super::ca::ClientIdentity;
+use super::tls::{crypto_provider, ServerTrust, TrustSettings, ALPN};
+use super::tuning::QuicTuning;
 
 /// QUIC client for connecting to remote endpoints
 pub struct QuicClient {
     endpoint: Endpoint,
+    trust: ServerTrust,
+    server_name: String,
+    /// Certificate presented to servers that ask for one
+    identity: Option<ClientIdentity>,
+    /// Transport parameters of new connections, from a checked [`QuicTuning`]
+    transport: Arc<TransportConfig>,
 }
 
 impl QuicClient {
-    /// Create a new QUIC client
+    /// Create a new QUIC client trusting servers on first use, see [`ServerTrust::default`]
     pub fn new() -> anyhow::Result<Self> {
-        let mut endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
-        
-        // Configure client with default settings
-        let client_config = configure_client();
-        endpoint.set_default_client_config(client_config);
-        
-        Ok(Self { endpoint })
+        Self::with_trust(ServerTrust::default())
     }
-    
-    /// Connect to a remote server
-    pub async fn connect(&self, server_addr: SocketAddr) -> anyhow::Result<Connection> {
-        info!("Connecting to {}", server_addr);
-        
-        let connection = self.endpoint
-            .connect(server_addr, "localhost")?
-            .await?;
-        
-        info!("Connected to {}", server_addr);
-        
-        Ok(connection)
+
+    /// Create a new QUIC client checking server certificates with `trust`
+    pub fn with_trust(trust: ServerTrust) -> anyhow::Result<Self> {
+        let endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
+
+        Ok(Self {
+            endpoint,
+            trust,
+            server_name: "localhost".to_string(),
+            identity: None,
+            transport: Arc::new(QuicTuning::default().transport_config()?),
+        })
     }
-}
 
-impl Default for QuicClient {
-    fn default() -> Self {
-        Self::new().expect("Failed to create QUIC client")
+    /// Create a new QUIC client from config file settings
+    pub fn from_settings(settings: &TrustSettings) -> anyhow::Result<Self> {
+        let client = Self::with_trust(settings.trust()?)?;
+        Ok(match &settings.server_name {
+            Some(name) => client.with_server_name(name.clone()),
+            None => client,
+        })
     }
-}
 
-/// Configure the QUIC client with TLS settings
-fn configure_client() -> ClientConfig {
-    let mut crypto = rustls::ClientConfig::builder()
-        .dangerous()
-        .with_custom_certificate_verifier(SkipServerVerification::new())
-        .with_no_client_auth();
-    
-    // Must match server's ALPN protocol
-    crypto.alpn_protocols = vec![b"rdp/1".to_vec()];
-    
-    ClientConfig::new(Arc::new(
-        quinn::crypto::rustls::QuicClientConfig::try_from(crypto).unwrap()
-    ))
-}
+    /// Name the server's certificate must be issued for when checked against a CA
+    pub fn with_server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = server_name.into();
+        self
+    }
 
-/// Skip server certificate verification (for development)
-/// TODO: Replace with proper certificate validation in production
-#[derive(Debug)]
-struct SkipServerVerification(Arc<rustls::crypto::CryptoProvider>);
+    /// Present `identity` to servers requiring mutual TLS
+    pub fn with_client_identity(mut self, identity: ClientIdentity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
 
-impl SkipServerVerification {
-    fn new() -> Arc<Self> {
-        Arc::new(Self(Arc::new(rustls::crypto::ring::default_provider())))
+    /// Apply `tuning` to connections made from now on
+    ///
+    /// Fails right away if the settings are invalid, rather than on every `connect`.
+    pub fn with_tuning(mut self, tuning: &QuicTuning) -> anyhow::Result<Self> {
+        self.transport = Arc::new(tuning.transport_config()?);
+        Ok(self)
     }
-}
 
-impl rustls::client::danger::ServerCertVerifier for SkipServerVerification {
-    fn verify_server_cert(
-        &self,
-        _end_entity: &rustls::pki_types::CertificateDer<'_>,
-        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
-        _server_name: &rustls::pki_types::ServerName<'_>,
-        _ocsp: &[u8],
-        _now: rustls::pki_types::UnixTime,
-    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
-        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    /// Connect to a remote server
+    ///
+    /// A certificate refused by the trust settings fails with the
+    /// [`TrustError`](super::TrustError) explaining why.
+    pub async fn connect(&self, server_addr: SocketAddr) -> anyhow::Result<Connection> {
+        info!("Connecting to {}", server_addr);
+
+        let failure = Arc::new(Mutex::new(None));
+        let client_config = self.configure_client(&server_addr.to_string(), failure.clone())?;
+
+        let connection = match self.endpoint
+            .connect_with(client_config, server_addr, &self.server_name)?
+            .await
+        {
+            Ok(connection) => connection,
+            Err(e) => return Err(match failure.lock().unwrap().take() {
+                Some(refused) => refused.into(),
+                None => e.into(),
+            }),
+        };
+
+        info!("Connected to {}", server_addr);
+
+        Ok(connection)
     }
 
-    fn verify_tls12_signature(
-        &self,
-        _message: &[u8],
-        _cert: &rustls::pki_types::CertificateDer<'_>,
-        _dss: &rustls::DigitallySignedStruct,
-    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    /// Wait until the connections closed on this client have told their server,
+    /// so that exiting right after does not leave the server waiting for a timeout
+    pub async fn wait_idle(&self) {
+        self.endpoint.wait_idle().await;
     }
 
-    fn verify_tls13_signature(
+    /// Configure the QUIC client with TLS settings for a connection to `host`
+    fn configure_client(
         &self,
-        _message: &[u8],
-        _cert: &rustls::pki_types::CertificateDer<'_>,
-        _dss: &rustls::DigitallySignedStruct,
-    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+        host: &str,
+        failure: Arc<Mutex<Option<super::TrustError>>>,
+    ) -> anyhow::Result<ClientConfig> {
+        let builder = rustls::ClientConfig::builder_with_provider(crypto_provider())
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(self.trust.verifier(host, failure)?);
+        let mut crypto = match &self.identity {
+            Some(identity) => builder.with_client_auth_cert(identity.cert_chain.clone(), identity.key.clone_key())?,
+            None => builder.with_no_client_auth(),
+        };
+
+        // Must match server's ALPN protocol
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+        let mut client_config = ClientConfig::new(Arc::new(
+            quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?
+        ));
+
+        client_config.transport_config(self.transport.clone());
+
+        Ok(client_config)
     }
+}
 
-    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
-        self.0.signature_verification_algorithms.supported_schemes()
+impl Default for QuicClient {
+    fn default() -> Self {
+        Self::new().expect("Failed to create QUIC client")
     }
 }
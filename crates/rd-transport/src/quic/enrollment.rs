@@ -0,0 +1,185 @@
+//! Device enrollment requests waiting for an administrator
+//!
+//! Requests live as PEM files in a directory shared by the server and
+//! `rd-cli enroll`: `pending/<device_id>.csr` until approved, then
+//! `approved/<device_id>.csr`. The server signs the next request of the device
+//! for the same key as the approved one. Requests are compared by their key,
+//! since signing the same request twice does not give the same bytes.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rcgen::{CertificateSigningRequestParams, PublicKeyData};
+use tracing::info;
+
+use rd_core::domain::models::EnrollmentStatus;
+
+use super::ca::CertificateAuthority;
+use super::tls::Fingerprint;
+
+/// A request waiting for approval
+#[derive(Debug, Clone)]
+pub struct PendingEnrollment {
+    pub device_id: String,
+    pub requested_at: DateTime<Utc>,
+    /// Of the device's key, also logged by the device so the administrator can
+    /// tell it is the one asking
+    pub fingerprint: Fingerprint,
+}
+
+/// Enrollment requests kept in a directory
+#[derive(Debug, Clone)]
+pub struct EnrollmentQueue {
+    dir: PathBuf,
+}
+
+impl EnrollmentQueue {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Handle a device's request: issue its certificate if this very request
+    /// was approved, otherwise queue it for approval
+    pub fn submit(&self, ca: &CertificateAuthority, device_id: &str, csr: &str) -> anyhow::Result<EnrollmentStatus> {
+        if !is_valid_device_id(device_id) {
+            return Ok(EnrollmentStatus::Refused { reason: format!("Invalid device id: {:?}", device_id) });
+        }
+        let Ok(fingerprint) = request_fingerprint(csr) else {
+            return Ok(EnrollmentStatus::Refused { reason: "Invalid certificate signing request".to_string() });
+        };
+        let stored_key = |state| {
+            std::fs::read_to_string(self.path(state, device_id))
+                .ok()
+                .and_then(|stored| request_fingerprint(&stored).ok())
+        };
+
+        if stored_key("approved") == Some(fingerprint) {
+            info!("Issuing certificate to {}", device_id);
+            return Ok(EnrollmentStatus::Issued { certificate: ca.sign(csr, device_id)? });
+        }
+
+        // A new key needs a new approval
+        if stored_key("pending") != Some(fingerprint) {
+            write(&self.path("pending", device_id), csr)?;
+            info!("{} asks to enroll with key {}; approve with `rd-cli enroll approve {}`",
+                device_id, fingerprint, device_id);
+        }
+        Ok(EnrollmentStatus::Pending)
+    }
+
+    /// Requests waiting for approval, oldest first
+    pub fn pending(&self) -> anyhow::Result<Vec<PendingEnrollment>> {
+        let dir = self.dir.join("pending");
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", dir.display())),
+        };
+
+        let mut pending = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let Some(device_id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if path.extension().and_then(|extension| extension.to_str()) != Some("csr") {
+                continue;
+            }
+            pending.push(PendingEnrollment {
+                device_id: device_id.to_string(),
+                requested_at: std::fs::metadata(&path)?.modified()?.into(),
+                fingerprint: request_fingerprint(&std::fs::read_to_string(&path)?)?,
+            });
+        }
+        pending.sort_by_key(|request| request.requested_at);
+        Ok(pending)
+    }
+
+    /// Approve the pending request of `device_id`
+    pub fn approve(&self, device_id: &str) -> anyhow::Result<()> {
+        let pending = self.existing_request(device_id)?;
+        let approved = self.path("approved", device_id);
+        if let Some(parent) = approved.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(&pending, &approved).with_context(|| format!("approving {}", device_id))
+    }
+
+    /// Drop the pending request of `device_id`
+    pub fn reject(&self, device_id: &str) -> anyhow::Result<()> {
+        let pending = self.existing_request(device_id)?;
+        std::fs::remove_file(pending).with_context(|| format!("rejecting {}", device_id))
+    }
+
+    fn existing_request(&self, device_id: &str) -> anyhow::Result<PathBuf> {
+        anyhow::ensure!(is_valid_device_id(device_id), "invalid device id {:?}", device_id);
+        let path = self.path("pending", device_id);
+        anyhow::ensure!(path.exists(), "no pending enrollment for {}", device_id);
+        Ok(path)
+    }
+
+    fn path(&self, state: &str, device_id: &str) -> PathBuf {
+        self.dir.join(state).join(format!("{}.csr", device_id))
+    }
+}
+
+/// Fingerprint of the key a PEM signing request is for, as shown to device
+/// and administrator
+pub fn request_fingerprint(csr: &str) -> anyhow::Result<Fingerprint> {
+    let request = CertificateSigningRequestParams::from_pem(csr)?;
+    Ok(Fingerprint::digest(request.public_key.der_bytes()))
+}
+
+/// Device ids become file names, so only plain ones are accepted
+fn is_valid_device_id(device_id: &str) -> bool {
+    !device_id.is_empty()
+        && device_id.len() <= 64
+        && !device_id.starts_with('.')
+        && device_id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn write(path: &Path, contents: &str) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents).with_context(|| format!("writing {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quic::ca::ClientIdentity;
+
+    #[test]
+    fn test_certificate_is_issued_after_approval() {
+        let dir = std::env::temp_dir().join(format!("rd-enroll-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let ca = CertificateAuthority::load_or_generate(dir.join("ca.crt"), dir.join("ca.key")).unwrap();
+        let queue = EnrollmentQueue::new(dir.join("requests"));
+        let csr = ClientIdentity::signing_request(dir.join("host.key"), "host").unwrap();
+
+        assert_eq!(queue.submit(&ca, "host", &csr).unwrap(), EnrollmentStatus::Pending);
+        // Each request for the same key is signed anew, so its bytes differ
+        let again = ClientIdentity::signing_request(dir.join("host.key"), "host").unwrap();
+        assert_ne!(again, csr);
+        assert_eq!(queue.submit(&ca, "host", &again).unwrap(), EnrollmentStatus::Pending);
+        let pending = queue.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].device_id, "host");
+        assert_eq!(pending[0].fingerprint, request_fingerprint(&csr).unwrap());
+
+        queue.approve("host").unwrap();
+        assert!(queue.pending().unwrap().is_empty());
+        assert!(matches!(queue.submit(&ca, "host", &again).unwrap(), EnrollmentStatus::Issued { .. }));
+
+        // Another key for the same device waits for its own approval
+        let other = ClientIdentity::signing_request(dir.join("other.key"), "host").unwrap();
+        assert_eq!(queue.submit(&ca, "host", &other).unwrap(), EnrollmentStatus::Pending);
+        queue.reject("host").unwrap();
+        assert!(queue.approve("host").is_err());
+
+        assert!(matches!(queue.submit(&ca, "../ca", &csr).unwrap(), EnrollmentStatus::Refused { .. }));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,15 @@
+mod ca;
 mod client;
+mod enrollment;
 mod server;
+mod tls;
 mod transport;
+mod tuning;
 
+pub use ca::{device_id_of, peer_device_id, CertificateAuthority, ClientIdentity};
 pub use client::QuicClient;
+pub use enrollment::{request_fingerprint, EnrollmentQueue, PendingEnrollment};
 pub use server::QuicServer;
+pub use tls::{check_known_host, Fingerprint, ServerIdentity, ServerTrust, TrustError, TrustSettings, ALPN};
 pub use transport::QuicTransport;
-
-// TODO: Add TLS certificate generation utilities
-// TODO: Add QUIC configuration builders
+pub use tuning::{CongestionController, QuicTuning};
@@ -3,22 +3,65 @@ use std::sync::Arc;
 use std::net::SocketAddr;
 use tracing::{info, error};
 
+use rustls::server::danger::ClientCertVerifier;
+
+use super::ca::CertificateAuthority;
+use super::tls::{crypto_provider, ServerIdentity, ALPN};
+use super::tuning::QuicTuning;
+
 /// QUIC server for accepting incoming connections
 pub struct QuicServer {
     endpoint: Endpoint,
+    server_config: ServerConfig,
 }
 
 impl QuicServer {
     /// Create a new QUIC server bound to the given address
+    ///
+    /// The server presents a throwaway self-signed certificate, so clients
+    /// that remember or pin it will refuse it after a restart; use
+    /// [`QuicServer::with_identity`] to keep one.
     pub fn new(bind_addr: SocketAddr) -> anyhow::Result<Self> {
-        let server_config = configure_server()?;
-        
-        let endpoint = Endpoint::server(server_config, bind_addr)?;
-        info!("QUIC server listening on {}", bind_addr);
-        
-        Ok(Self { endpoint })
+        Self::with_identity(bind_addr, ServerIdentity::self_signed(vec!["localhost".into()])?)
+    }
+
+    /// Create a new QUIC server presenting `identity`
+    pub fn with_identity(bind_addr: SocketAddr, identity: ServerIdentity) -> anyhow::Result<Self> {
+        Self::bind(bind_addr, configure_server(identity, None)?)
+    }
+
+    /// Create a new QUIC server presenting `identity` that asks peers for a
+    /// certificate issued by `ca`
+    ///
+    /// Peers without a certificate are still accepted, so devices can enroll;
+    /// see [`peer_device_id`](super::peer_device_id) for who presented one.
+    pub fn with_client_ca(
+        bind_addr: SocketAddr,
+        identity: ServerIdentity,
+        ca: &CertificateAuthority,
+    ) -> anyhow::Result<Self> {
+        Self::bind(bind_addr, configure_server(identity, Some(ca.client_verifier()?))?)
     }
-    
+
+    fn bind(bind_addr: SocketAddr, server_config: ServerConfig) -> anyhow::Result<Self> {
+        let endpoint = Endpoint::server(server_config.clone(), bind_addr)?;
+        info!("QUIC server listening on {}", endpoint.local_addr()?);
+
+        Ok(Self { endpoint, server_config })
+    }
+
+    /// Apply `tuning` to connections accepted from now on
+    pub fn with_tuning(mut self, tuning: &QuicTuning) -> anyhow::Result<Self> {
+        self.server_config.transport_config(Arc::new(tuning.transport_config()?));
+        self.endpoint.set_server_config(Some(self.server_config.clone()));
+        Ok(self)
+    }
+
+    /// Address the server is bound to
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
     /// Accept incoming connections
     pub async fn accept(&self) -> Option<Connection> {
         match self.endpoint.accept().await {
@@ -40,31 +83,26 @@ impl QuicServer {
 }
 
 /// Configure the QUIC server with TLS settings
-fn configure_server() -> anyhow::Result<ServerConfig> {
-    // Generate self-signed certificate (for development)
-    // TODO: Use proper certificates in production
-    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
-    let cert_der = rustls::pki_types::CertificateDer::from(cert.cert);
-    let priv_key = rustls::pki_types::PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
-    
-    let mut server_crypto = rustls::ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(vec![cert_der], priv_key.into())?;
-    
-    server_crypto.alpn_protocols = vec![b"rdp/1".to_vec()];
-    
+fn configure_server(
+    identity: ServerIdentity,
+    client_verifier: Option<Arc<dyn ClientCertVerifier>>,
+) -> anyhow::Result<ServerConfig> {
+    let builder = rustls::ServerConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()?;
+    let builder = match client_verifier {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_crypto = builder
+        .with_single_cert(identity.cert_chain, identity.key)?;
+
+    server_crypto.alpn_protocols = vec![ALPN.to_vec()];
+
     let mut server_config = ServerConfig::with_crypto(Arc::new(
         quinn::crypto::rustls::QuicServerConfig::try_from(server_crypto)?
     ));
-    
-    // Configure transport
-    let mut transport_config = quinn::TransportConfig::default();
-    transport_config.max_concurrent_uni_streams(0_u8.into());
-    server_config.transport_config(Arc::new(transport_config));
-    
+
+    server_config.transport_config(Arc::new(QuicTuning::default().transport_config()?));
+
     Ok(server_config)
 }
-
-// Add rcgen dependency
-#[cfg(not(target_family = "wasm"))]
-use rcgen;
@@ -0,0 +1,568 @@
+//! Server certificates and how clients decide to trust them
+//!
+//! The server presents a [`ServerIdentity`], normally kept on disk so its
+//! fingerprint stays the same across restarts. Clients check it according to
+//! a [`ServerTrust`]: a CA, a pinned SHA-256 fingerprint, or trust on first
+//! use with a known hosts file like SSH.
+
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+/// ALPN protocol spoken by server and clients
+pub const ALPN: &[u8] = b"rdp/1";
+
+/// Crypto provider installed by the binary, or ring where none was installed
+pub(crate) fn crypto_provider() -> Arc<CryptoProvider> {
+    CryptoProvider::get_default()
+        .cloned()
+        .unwrap_or_else(|| Arc::new(rustls::crypto::ring::default_provider()))
+}
+
+/// SHA-256 of a DER encoded certificate
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn of(cert: &CertificateDer<'_>) -> Self {
+        Self::digest(cert.as_ref())
+    }
+
+    pub(crate) fn digest(der: &[u8]) -> Self {
+        Self(Sha256::digest(der).into())
+    }
+
+    /// Parse 64 hex digits, with or without colons and a `SHA256:` prefix
+    pub fn parse(text: &str) -> Result<Self, TrustError> {
+        let invalid = || TrustError::InvalidFingerprint(text.to_string());
+        let trimmed = text.trim();
+        let digits: String = trimmed
+            .strip_prefix("SHA256:")
+            .or_else(|| trimmed.strip_prefix("sha256:"))
+            .unwrap_or(trimmed)
+            .chars()
+            .filter(|c| *c != ':')
+            .collect();
+        if digits.len() != 64 || !digits.is_ascii() {
+            return Err(invalid());
+        }
+
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fingerprint({})", self)
+    }
+}
+
+/// Why a server's certificate was not trusted
+#[derive(Debug, Error)]
+pub enum TrustError {
+    #[error("invalid fingerprint {0:?}: expected 64 hex digits")]
+    InvalidFingerprint(String),
+
+    #[error(
+        "SERVER CERTIFICATE DOES NOT MATCH THE PINNED FINGERPRINT!\n\
+         Expected {expected}\n\
+         but the server presented {actual}.\n\
+         Someone may be intercepting the connection. Not connecting."
+    )]
+    PinMismatch { expected: Fingerprint, actual: Fingerprint },
+
+    #[error(
+        "SERVER IDENTITY FOR {host} HAS CHANGED!\n\
+         {known_hosts} says {host} presents {expected}\n\
+         but it presented {actual}.\n\
+         Someone may be intercepting the connection, or the server's certificate was replaced.\n\
+         If the change is expected, remove the line for {host} from {known_hosts} and connect again."
+    )]
+    HostKeyChanged {
+        host: String,
+        expected: Fingerprint,
+        actual: Fingerprint,
+        known_hosts: String,
+    },
+
+    #[error("known hosts file {path}: {reason}")]
+    KnownHosts { path: String, reason: String },
+}
+
+/// Certificate chain and private key the server presents
+pub struct ServerIdentity {
+    pub cert_chain: Vec<CertificateDer<'static>>,
+    pub key: PrivateKeyDer<'static>,
+}
+
+impl ServerIdentity {
+    /// Fresh self-signed certificate for `names`, kept in memory only
+    pub fn self_signed(names: Vec<String>) -> anyhow::Result<Self> {
+        let certified = rcgen::generate_simple_self_signed(names)?;
+        Ok(Self {
+            cert_chain: vec![certified.cert.der().clone()],
+            key: PrivateKeyDer::Pkcs8(certified.key_pair.serialize_der().into()),
+        })
+    }
+
+    /// Read a PEM certificate chain and private key
+    pub fn load(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let (cert_path, key_path) = (cert_path.as_ref(), key_path.as_ref());
+        let cert_chain = CertificateDer::pem_file_iter(cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("reading certificate {}", cert_path.display()))?;
+        anyhow::ensure!(!cert_chain.is_empty(), "no certificate in {}", cert_path.display());
+        let key = PrivateKeyDer::from_pem_file(key_path)
+            .with_context(|| format!("reading private key {}", key_path.display()))?;
+
+        Ok(Self { cert_chain, key })
+    }
+
+    /// Read the certificate and key, or create a self-signed pair for
+    /// `localhost` and store it there if neither file exists yet
+    pub fn load_or_generate(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let (cert_path, key_path) = (cert_path.as_ref(), key_path.as_ref());
+        if cert_path.exists() || key_path.exists() {
+            return Self::load(cert_path, key_path);
+        }
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        write_file(cert_path, certified.cert.pem().as_bytes(), false)?;
+        write_file(key_path, certified.key_pair.serialize_pem().as_bytes(), true)?;
+        info!("Generated self-signed certificate {}", cert_path.display());
+
+        Self::load(cert_path, key_path)
+    }
+
+    /// Fingerprint of the leaf certificate, which clients pin or remember
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.cert_chain[0])
+    }
+}
+
+/// Write `contents` to a new file, readable by the owner only if `private`
+pub(crate) fn write_file(path: &Path, contents: &[u8], private: bool) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+/// How a client decides whether to trust the server's certificate
+#[derive(Debug, Clone)]
+pub enum ServerTrust {
+    /// Certificates issued for the server name by one of these roots
+    Ca(Arc<RootCertStore>),
+    /// Only the certificate with this fingerprint
+    Pinned(Fingerprint),
+    /// The certificate a server presented the first time, remembered in this file
+    KnownHosts(PathBuf),
+    /// Any certificate; the connection is encrypted but not authenticated
+    Insecure,
+}
+
+impl ServerTrust {
+    /// Trust the CA certificates in a PEM file
+    pub fn ca_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(path).with_context(|| format!("reading CA {}", path.display()))? {
+            roots.add(cert?)?;
+        }
+        anyhow::ensure!(!roots.is_empty(), "no certificate in {}", path.display());
+        Ok(Self::Ca(Arc::new(roots)))
+    }
+
+    /// Per-user known hosts file, `~/.rd/known_hosts`
+    pub fn default_known_hosts() -> PathBuf {
+        std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(PathBuf::from)
+            .unwrap_or_default()
+            .join(".rd")
+            .join("known_hosts")
+    }
+
+    /// Verifier for a connection to `host`, reporting why it refused a
+    /// certificate through `failure`
+    pub(crate) fn verifier(
+        &self,
+        host: &str,
+        failure: Arc<Mutex<Option<TrustError>>>,
+    ) -> anyhow::Result<Arc<dyn ServerCertVerifier>> {
+        let provider = crypto_provider();
+        let check = match self {
+            ServerTrust::Ca(roots) => {
+                return Ok(WebPkiServerVerifier::builder_with_provider(roots.clone(), provider).build()?);
+            }
+            ServerTrust::Pinned(fingerprint) => Check::Pinned(*fingerprint),
+            ServerTrust::KnownHosts(path) => Check::KnownHosts { path: path.clone(), host: host.to_string() },
+            ServerTrust::Insecure => Check::Any,
+        };
+        Ok(Arc::new(FingerprintVerifier { check, provider, failure }))
+    }
+}
+
+impl Default for ServerTrust {
+    fn default() -> Self {
+        ServerTrust::KnownHosts(Self::default_known_hosts())
+    }
+}
+
+/// Trust settings as they appear in config files
+///
+/// At most one of `ca`, `fingerprint` and `insecure` may be set; without any
+/// the known hosts file is used.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrustSettings {
+    /// PEM file with the CA certificates the server's certificate must chain to
+    pub ca: Option<String>,
+    /// SHA-256 fingerprint the server's certificate must have
+    pub fingerprint: Option<String>,
+    /// Known hosts file for trust on first use, `~/.rd/known_hosts` by default
+    pub known_hosts: Option<String>,
+    /// Name the server's certificate is issued for, `localhost` by default
+    pub server_name: Option<String>,
+    /// Accept any certificate; for development only
+    pub insecure: bool,
+}
+
+impl TrustSettings {
+    pub fn trust(&self) -> anyhow::Result<ServerTrust> {
+        match (&self.ca, &self.fingerprint, self.insecure) {
+            (Some(ca), None, false) => ServerTrust::ca_file(ca),
+            (None, Some(fingerprint), false) => Ok(ServerTrust::Pinned(Fingerprint::parse(fingerprint)?)),
+            (None, None, true) => {
+                warn!("Server certificates are not verified: the connection can be intercepted");
+                Ok(ServerTrust::Insecure)
+            }
+            (None, None, false) => Ok(ServerTrust::KnownHosts(
+                self.known_hosts.as_ref().map(PathBuf::from).unwrap_or_else(ServerTrust::default_known_hosts),
+            )),
+            _ => anyhow::bail!("only one of ca, fingerprint and insecure can be set"),
+        }
+    }
+}
+
+enum Check {
+    Pinned(Fingerprint),
+    KnownHosts { path: PathBuf, host: String },
+    Any,
+}
+
+/// Trusts a certificate by its fingerprint rather than by who issued it
+///
+/// Handshake signatures are still verified, so the server must hold the key
+/// of the certificate it presents.
+struct FingerprintVerifier {
+    check: Check,
+    provider: Arc<CryptoProvider>,
+    failure: Arc<Mutex<Option<TrustError>>>,
+}
+
+impl fmt::Debug for FingerprintVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FingerprintVerifier")
+    }
+}
+
+impl FingerprintVerifier {
+    fn check(&self, actual: Fingerprint) -> Result<(), TrustError> {
+        match &self.check {
+            Check::Pinned(expected) if *expected == actual => Ok(()),
+            Check::Pinned(expected) => Err(TrustError::PinMismatch { expected: *expected, actual }),
+            Check::KnownHosts { path, host } => check_known_host(path, host, actual),
+            Check::Any => Ok(()),
+        }
+    }
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self.check(Fingerprint::of(end_entity)) {
+            Ok(()) => Ok(ServerCertVerified::assertion()),
+            Err(e) => {
+                // The details stay here; the server only learns the certificate was refused
+                error!("{}", e);
+                *self.failure.lock().unwrap() = Some(e);
+                Err(rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure))
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Accept `actual` for `host` if the known hosts file has it, or remember it
+/// if the file has no entry for `host` yet
+///
+/// The file holds one `host fingerprint` pair per line; `#` starts a comment.
+pub fn check_known_host(path: &Path, host: &str, actual: Fingerprint) -> Result<(), TrustError> {
+    let file_error = |e: std::io::Error| TrustError::KnownHosts {
+        path: path.display().to_string(),
+        reason: e.to_string(),
+    };
+
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(file_error(e)),
+    };
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        if let (Some(known), Some(fingerprint)) = (fields.next(), fields.next()) {
+            if known == host {
+                let expected = Fingerprint::parse(fingerprint)?;
+                return if expected == actual {
+                    Ok(())
+                } else {
+                    Err(TrustError::HostKeyChanged {
+                        host: host.to_string(),
+                        expected,
+                        actual,
+                        known_hosts: path.display().to_string(),
+                    })
+                };
+            }
+        }
+    }
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).map_err(file_error)?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{} {}", host, actual))
+        .map_err(file_error)?;
+    warn!("Trusting {} on first use: {} added to {}", host, actual, path.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rd-tls-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_fingerprint_parsing() {
+        let fingerprint = Fingerprint([0xAB; 32]);
+        let text = fingerprint.to_string();
+        assert_eq!(text.len(), 95);
+        assert_eq!(Fingerprint::parse(&text).unwrap(), fingerprint);
+        assert_eq!(Fingerprint::parse(&format!("sha256:{}", "ab".repeat(32))).unwrap(), fingerprint);
+
+        assert!(Fingerprint::parse("AB:CD").is_err());
+        assert!(Fingerprint::parse(&"zz".repeat(32)).is_err());
+        assert!(Fingerprint::parse(&"é".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_known_hosts_trust_on_first_use() {
+        let path = temp_path("known").join("known_hosts");
+        let (first, second) = (Fingerprint([1; 32]), Fingerprint([2; 32]));
+
+        check_known_host(&path, "10.0.0.1:4433", first).unwrap();
+        check_known_host(&path, "10.0.0.1:4433", first).unwrap();
+        // Other hosts are remembered on their own
+        check_known_host(&path, "10.0.0.2:4433", second).unwrap();
+
+        match check_known_host(&path, "10.0.0.1:4433", second) {
+            Err(TrustError::HostKeyChanged { expected, actual, .. }) => {
+                assert_eq!((expected, actual), (first, second));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_identity_is_kept_across_restarts() {
+        let dir = temp_path("identity");
+        let (cert, key) = (dir.join("server.crt"), dir.join("server.key"));
+
+        let first = ServerIdentity::load_or_generate(&cert, &key).unwrap();
+        let second = ServerIdentity::load_or_generate(&cert, &key).unwrap();
+        assert_eq!(first.fingerprint(), second.fingerprint());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&key).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Server on a free local port accepting connections until dropped
+    fn serve(identity: ServerIdentity) -> std::net::SocketAddr {
+        let server = crate::QuicServer::with_identity("127.0.0.1:0".parse().unwrap(), identity).unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Some(connection) = server.accept().await {
+                connections.push(connection);
+            }
+        });
+        addr
+    }
+
+    async fn connect(trust: ServerTrust, addr: std::net::SocketAddr) -> anyhow::Result<()> {
+        crate::QuicClient::with_trust(trust)?.connect(addr).await.map(|_| ())
+    }
+
+    fn localhost() -> ServerIdentity {
+        ServerIdentity::self_signed(vec!["localhost".into()]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pinned_certificate() {
+        let identity = localhost();
+        let fingerprint = identity.fingerprint();
+        let addr = serve(identity);
+
+        connect(ServerTrust::Pinned(fingerprint), addr).await.unwrap();
+
+        let error = connect(ServerTrust::Pinned(Fingerprint([0; 32])), addr).await.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(TrustError::PinMismatch { actual, .. }) if *actual == fingerprint));
+    }
+
+    #[tokio::test]
+    async fn test_known_hosts_refuse_a_changed_certificate() {
+        let path = temp_path("quic").join("known_hosts");
+        let trust = ServerTrust::KnownHosts(path.clone());
+        let first = localhost();
+        let remembered = first.fingerprint();
+        let addr = serve(first);
+
+        connect(trust.clone(), addr).await.unwrap();
+        connect(trust.clone(), addr).await.unwrap();
+
+        // Another certificate where the known one is expected
+        let impostor = serve(localhost());
+        std::fs::OpenOptions::new().append(true).open(&path).and_then(|mut file| {
+            writeln!(file, "{} {}", impostor, remembered)
+        }).unwrap();
+
+        let error = connect(trust, impostor).await.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(TrustError::HostKeyChanged { .. })));
+        assert!(error.to_string().contains("HAS CHANGED"));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ca_issued_certificate() {
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["localhost".into()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+        let addr = serve(ServerIdentity {
+            cert_chain: vec![cert.der().clone()],
+            key: PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let trust = ServerTrust::Ca(Arc::new(roots));
+        connect(trust.clone(), addr).await.unwrap();
+
+        // A self-signed certificate is not issued by the CA
+        assert!(connect(trust, serve(localhost())).await.is_err());
+    }
+
+    #[test]
+    fn test_settings_allow_one_mode() {
+        let settings = TrustSettings { fingerprint: Some("ab".repeat(32)), ..Default::default() };
+        assert!(matches!(settings.trust().unwrap(), ServerTrust::Pinned(_)));
+
+        let settings = TrustSettings { known_hosts: Some("hosts".into()), ..Default::default() };
+        assert!(matches!(settings.trust().unwrap(), ServerTrust::KnownHosts(path) if path == Path::new("hosts")));
+
+        let settings = TrustSettings { fingerprint: Some("ab".repeat(32)), insecure: true, ..Default::default() };
+        assert!(settings.trust().is_err());
+    }
+}
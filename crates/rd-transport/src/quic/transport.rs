@@ -1,126 +1,794 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
 use async_trait::async_trait;
+use futures_util::StreamExt;
 use quinn::{Connection, SendStream, RecvStream};
-use bytes::{Bytes, BytesMut, BufMut};
-use tracing::{debug, warn, error};
+use bytes::{Bytes, BytesMut};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio_util::codec::{Decoder, Encoder, FramedRead};
+use tracing::{debug, warn};
 
 use rd_core::domain::{
-    ports::{Transport, ProtocolMessage},
+    models::{InputEvent, WireFormat},
+    ports::{MessageClass, Transport, ProtocolMessage},
     error::TransportError,
 };
 
-use crate::protocol::{serialize_message, deserialize_message};
+use crate::protocol::{codec, MessageCodec, WireCodec, DEFAULT_MAX_MESSAGE_SIZE};
+
+/// First byte of a unidirectional stream, telling what it carries
+const INPUT_STREAM: u8 = 1;
+const FRAME_STREAM: u8 = 2;
+
+/// Frames read from their streams but not yet received
+const FRAMES_CAPACITY: usize = 16;
+
+/// Input and datagrams read but not yet received; separate from frames, so a
+/// receiver behind on frames still gets them first
+const INPUT_CAPACITY: usize = 64;
+
+/// Bytes in front of every datagram's and input stream message: its sequence number
+const DATAGRAM_HEADER: usize = 8;
+
+/// Unidirectional streams a peer may have open at once: one for input, the
+/// rest for frames in flight
+pub(crate) const MAX_UNI_STREAMS: u32 = 32;
+
+/// Send priorities: frames yield to everything else when the link is busy
+const CONTROL_PRIORITY: i32 = 1;
+const INPUT_PRIORITY: i32 = 1;
+const FRAME_PRIORITY: i32 = 0;
+
+/// How long closing waits for the peer to acknowledge the last messages
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Which end of the connection creates the message stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamSide {
+    /// Opens the stream (the connecting client or agent)
+    Open,
+    /// Waits for the peer to open it (the server)
+    Accept,
+}
 
 /// QUIC transport implementation
+///
+/// Each kind of traffic gets its own streams, so a large frame never holds
+/// up a heartbeat or a key press:
+///
+/// - control messages go over one bidirectional stream, opened by the
+///   connecting side;
+/// - input events over one unidirectional stream per direction, keeping
+///   their order;
+/// - every screen frame over a unidirectional stream of its own, so a lost
+///   packet only delays that frame. Frames can therefore arrive out of order,
+///   and after control messages sent later;
+/// - [coalescable](MessageClass::Coalescable) messages, pointer motion and
+///   heartbeats, as datagrams that are never resent. If the peer takes no
+///   datagrams, or the message does not fit one, they go with input or control
+///   messages instead.
+///
+/// Pointer motion carries a sequence number; the receiver drops datagrams
+/// older than the newest motion it received, and keeps only the newest one it
+/// has not received yet.
+///
+/// A pointer position sent as a datagram is repeated on the input stream
+/// before the next button or scroll event, so a click lands where the pointer
+/// was even if the datagram got lost or overtaken. If a newer position was
+/// received meanwhile, it is received again right after the click.
+///
+/// On receipt, control messages come first, then input and datagrams, then
+/// frames, whenever several are waiting.
+///
+/// Streams carry messages as chunks (see [`framing`](crate::protocol::framing)),
+/// and a peer sending a message over [the limit](Self::with_max_message_size)
+/// fails `receive` with [`TransportError::ProtocolError`].
 pub struct QuicTransport {
     connection: Connection,
+    side: StreamSide,
     send_stream: Option<SendStream>,
     recv_stream: Option<RecvStream>,
+    /// Bytes read but not yet decoded; kept across calls so `receive` is cancel-safe
+    recv_buf: BytesMut,
+    codec: MessageCodec,
+    /// Encoding of messages, bincode until the handshake agrees on another
+    wire: &'static dyn WireCodec,
+    /// Limit for messages on every stream, shared with the stream readers
+    max_message_size: Arc<AtomicUsize>,
+    /// Our input stream, opened with the first input event
+    input_stream: Option<SendStream>,
+    /// Last pointer motion sent as a datagram since the last input on the stream
+    unconfirmed_move: Option<Bytes>,
+    /// Sequence number of the last pointer motion sent
+    move_sequence: u64,
+    /// Order of the pointer motion received, across datagrams and the input stream
+    moves: MoveOrder,
+    /// Messages read from the peer's input stream and datagrams, still encoded
+    input: mpsc::Receiver<Result<Bytes, TransportError>>,
+    /// Messages read from the peer's frame streams, still encoded
+    frames: mpsc::Receiver<Result<Bytes, TransportError>>,
+    /// Newest pointer motion datagram from the peer, still encoded and numbered
+    pointer: watch::Receiver<Option<Bytes>>,
+    /// Read the peer's unidirectional streams and datagrams; hold the connection open
+    readers: [JoinHandle<()>; 2],
 }
 
 impl QuicTransport {
-    /// Create a new QUIC transport from an established connection
+    /// Create a new QUIC transport on the connecting side of a connection
     pub async fn new(connection: Connection) -> Result<Self, TransportError> {
-        Ok(Self {
+        Ok(Self::with_side(connection, StreamSide::Open))
+    }
+
+    /// Create a QUIC transport on the accepting (server) side of a connection
+    ///
+    /// The message stream is accepted once the peer sends its first message.
+    pub async fn accept(connection: Connection) -> Result<Self, TransportError> {
+        Ok(Self::with_side(connection, StreamSide::Accept))
+    }
+
+    fn with_side(connection: Connection, side: StreamSide) -> Self {
+        let (input_tx, input) = mpsc::channel(INPUT_CAPACITY);
+        let (frames_tx, frames) = mpsc::channel(FRAMES_CAPACITY);
+        let (pointer_tx, pointer) = watch::channel(None);
+        let max_message_size = Arc::new(AtomicUsize::new(DEFAULT_MAX_MESSAGE_SIZE));
+        let readers = [
+            tokio::spawn(accept_uni_streams(
+                connection.clone(),
+                max_message_size.clone(),
+                input_tx.clone(),
+                frames_tx,
+            )),
+            tokio::spawn(read_datagrams(connection.clone(), input_tx, pointer_tx)),
+        ];
+
+        Self {
             connection,
+            side,
             send_stream: None,
             recv_stream: None,
-        })
+            recv_buf: BytesMut::new(),
+            codec: MessageCodec::default(),
+            wire: codec(WireFormat::Bincode),
+            max_message_size,
+            input_stream: None,
+            unconfirmed_move: None,
+            move_sequence: 0,
+            moves: MoveOrder::default(),
+            input,
+            frames,
+            pointer,
+            readers,
+        }
     }
-    
+
+    /// Refuse messages larger than `bytes`, sent or received, instead of
+    /// [`DEFAULT_MAX_MESSAGE_SIZE`]
+    pub fn with_max_message_size(mut self, bytes: usize) -> Self {
+        self.codec = MessageCodec::new(bytes);
+        self.max_message_size.store(bytes, Ordering::Relaxed);
+        self
+    }
+
     /// Initialize bidirectional stream
     async fn ensure_stream(&mut self) -> Result<(), TransportError> {
         if self.send_stream.is_none() || self.recv_stream.is_none() {
-            let (send, recv) = self.connection
-                .open_bi()
-                .await
-                .map_err(|e| TransportError::ConnectionFailed(e.to_string()))?;
-            
-            self.send_stream = Some(send);
-            self.recv_stream = Some(recv);
-            
-            debug!("Opened bidirectional QUIC stream");
+            let streams = control_stream(&self.connection, self.side).await?;
+            self.set_control_stream(streams);
+        }
+        Ok(())
+    }
+
+    fn set_control_stream(&mut self, (send, recv): (SendStream, RecvStream)) {
+        self.send_stream = Some(send);
+        self.recv_stream = Some(recv);
+    }
+
+    /// Write a framed message to the control stream
+    async fn write_control(&mut self, frame: &[u8]) -> Result<(), TransportError> {
+        self.ensure_stream().await?;
+        self.send_stream
+            .as_mut()
+            .unwrap()
+            .write_all(frame)
+            .await
+            .map_err(|_| TransportError::Closed)
+    }
+
+    /// Write a framed message to the input stream, after the pointer position
+    /// a `positional` event has to follow
+    async fn write_input(&mut self, frame: &[u8], positional: bool) -> Result<(), TransportError> {
+        let unconfirmed_move = if positional { self.unconfirmed_move.take() } else { None };
+        let stream = self.input_stream().await?;
+        if let Some(pointer) = unconfirmed_move {
+            stream.write_all(&pointer).await.map_err(|_| TransportError::Closed)?;
+        }
+        stream.write_all(frame).await.map_err(|_| TransportError::Closed)
+    }
+
+    /// Send a numbered message as a datagram, if the peer takes one that size
+    fn send_datagram(&self, datagram: &Bytes) -> Result<bool, TransportError> {
+        if self.connection.max_datagram_size().map_or(true, |max| datagram.len() > max) {
+            return Ok(false);
         }
+        match self.connection.send_datagram(datagram.clone()) {
+            Ok(()) => Ok(true),
+            Err(quinn::SendDatagramError::ConnectionLost(e)) => Err(connection_error(e)),
+            // Not supported by the peer or too large after all
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Our input stream, opened on first use
+    async fn input_stream(&mut self) -> Result<&mut SendStream, TransportError> {
+        if self.input_stream.is_none() {
+            let stream = open_uni(&self.connection, INPUT_STREAM, INPUT_PRIORITY).await?;
+            debug!("Opened input stream");
+            self.input_stream = Some(stream);
+        }
+        Ok(self.input_stream.as_mut().unwrap())
+    }
+
+    /// Send a frame on a stream of its own, without waiting for it to be written
+    ///
+    /// Waits only while the peer allows no more streams, which holds back a
+    /// sender producing frames faster than the peer takes them.
+    async fn send_frame(&mut self, data: Bytes) -> Result<(), TransportError> {
+        let mut stream = open_uni(&self.connection, FRAME_STREAM, FRAME_PRIORITY).await?;
+        tokio::spawn(async move {
+            let written = match stream.write_all(&data).await {
+                Ok(()) => stream.finish().map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = written {
+                debug!("Frame not delivered: {}", e);
+            }
+        });
         Ok(())
     }
+
+    /// Frame a message for the input stream, behind its sequence number
+    fn input_frame(&mut self, sequence: u64, data: &[u8]) -> Result<Bytes, TransportError> {
+        let mut frame = BytesMut::new();
+        self.codec.encode(&datagram(sequence, data)[..], &mut frame)?;
+        Ok(frame.freeze())
+    }
+
+    /// Decode the next complete message from the receive buffer, if there is one
+    fn take_buffered(&mut self) -> Result<Option<ProtocolMessage>, TransportError> {
+        let Some(data) = self.codec.decode(&mut self.recv_buf)? else {
+            return Ok(None);
+        };
+
+        debug!("Received message ({} bytes)", data.len());
+
+        self.wire.decode(&data, self.codec.max_message_size()).map(Some)
+    }
 }
 
 #[async_trait]
 impl Transport for QuicTransport {
     async fn send(&mut self, message: ProtocolMessage) -> Result<(), TransportError> {
-        self.ensure_stream().await?;
-        
-        // Serialize message
-        let data = serialize_message(&message)
-            .map_err(|e| TransportError::SerializationError(e.to_string()))?;
-        
-        // Send length prefix (4 bytes)
-        let len = data.len() as u32;
-        let mut frame = BytesMut::with_capacity(4 + data.len());
-        frame.put_u32(len);
-        frame.put_slice(&data);
-        
-        // Send frame
-        let send_stream = self.send_stream.as_mut().unwrap();
-        send_stream
-            .write_all(&frame)
-            .await
-            .map_err(|_| TransportError::Closed)?;
-        
+        let data = self.wire.encode(&message)?;
+        let mut frame = BytesMut::new();
+        self.codec.encode(&data[..], &mut frame)?;
+        let frame = frame.freeze();
+        let is_move = matches!(message, ProtocolMessage::InputEvent { event: InputEvent::MouseMove { .. }, .. });
+        let positional = matches!(
+            message,
+            ProtocolMessage::InputEvent { event: InputEvent::MouseButton { .. } | InputEvent::MouseScroll { .. }, .. }
+        );
+
+        match message.class() {
+            MessageClass::Frame => self.send_frame(frame.clone()).await?,
+            MessageClass::Input => {
+                let frame = self.input_frame(0, &data)?;
+                self.write_input(&frame, positional).await?
+            }
+            MessageClass::Coalescable if is_move => {
+                self.move_sequence += 1;
+                let sequence = self.move_sequence;
+                let frame = self.input_frame(sequence, &data)?;
+                if self.send_datagram(&datagram(sequence, &data))? {
+                    self.unconfirmed_move = Some(frame);
+                } else {
+                    // In order with the rest of the input, so no repeat is needed
+                    self.unconfirmed_move = None;
+                    self.write_input(&frame, false).await?
+                }
+            }
+            MessageClass::Coalescable if self.send_datagram(&datagram(0, &data))? => {}
+            MessageClass::Coalescable | MessageClass::Control => self.write_control(&frame).await?,
+        }
+
         debug!("Sent message ({} bytes)", data.len());
-        
+
         Ok(())
     }
-    
+
     async fn receive(&mut self) -> Result<ProtocolMessage, TransportError> {
-        self.ensure_stream().await?;
-        
-        let recv_stream = self.recv_stream.as_mut().unwrap();
-        
-        // Read length prefix (4 bytes)
-        let mut len_buf = [0u8; 4];
-        recv_stream
-            .read_exact(&mut len_buf)
-            .await
-            .map_err(|e| match e {
-                quinn::ReadExactError::FinishedEarly(_) => TransportError::Closed,
-                quinn::ReadExactError::ReadError(e) => TransportError::IoError(e.into()),
-            })?;
-        
-        let len = u32::from_be_bytes(len_buf) as usize;
-        
-        // Read message data
-        let mut data = vec![0u8; len];
-        recv_stream
-            .read_exact(&mut data)
-            .await
-            .map_err(|e| match e {
-                quinn::ReadExactError::FinishedEarly(_) => TransportError::Closed,
-                quinn::ReadExactError::ReadError(e) => TransportError::IoError(e.into()),
-            })?;
-        
-        // Deserialize message
-        let message = deserialize_message(&data)
-            .map_err(|e| TransportError::SerializationError(e.to_string()))?;
-        
-        debug!("Received message ({} bytes)", len);
-        
-        Ok(message)
+        let max_size = self.codec.max_message_size();
+        loop {
+            if let Some(message) = self.take_buffered()? {
+                return Ok(message);
+            }
+            if let Some(data) = self.moves.pending.take() {
+                return self.wire.decode(&data, max_size);
+            }
+
+            // Datagrams and unidirectional streams may come before the control stream
+            if self.recv_stream.is_none() {
+                tokio::select! {
+                    biased;
+                    streams = control_stream(&self.connection, self.side) => self.set_control_stream(streams?),
+                    Some(data) = self.input.recv() => return self.moves.take_input(self.wire, data?, max_size),
+                    Ok(()) = self.pointer.changed() => if let Some(data) = self.pointer.borrow_and_update().clone() {
+                        if let Some(message) = self.moves.take_pointer(self.wire, data, max_size)? {
+                            return Ok(message);
+                        }
+                    },
+                    Some(data) = self.frames.recv() => return self.wire.decode(&data?, max_size),
+                }
+                continue;
+            }
+
+            // All branches are cancel-safe, and partial messages stay in `recv_buf`
+            let recv_stream = self.recv_stream.as_mut().unwrap();
+            tokio::select! {
+                biased;
+                chunk = recv_stream.read_chunk(usize::MAX, true) => match chunk {
+                    Ok(Some(chunk)) => self.recv_buf.extend_from_slice(&chunk.bytes),
+                    Ok(None) => return Err(TransportError::Closed),
+                    Err(quinn::ReadError::ConnectionLost(e)) => return Err(connection_error(e)),
+                    Err(e) => return Err(TransportError::IoError(e.into())),
+                },
+                Some(data) = self.input.recv() => return self.moves.take_input(self.wire, data?, max_size),
+                Ok(()) = self.pointer.changed() => if let Some(data) = self.pointer.borrow_and_update().clone() {
+                    if let Some(message) = self.moves.take_pointer(self.wire, data, max_size)? {
+                        return Ok(message);
+                    }
+                },
+                Some(data) = self.frames.recv() => return self.wire.decode(&data?, max_size),
+            }
+        }
     }
-    
+
     async fn close(&mut self) -> Result<(), TransportError> {
+        if let Some(mut input) = self.input_stream.take() {
+            input.finish().ok();
+        }
         if let Some(mut send) = self.send_stream.take() {
             send.finish()
                 .map_err(|e| TransportError::IoError(e.into()))?;
+            // Closing the connection drops whatever is still unacknowledged,
+            // such as a parting Disconnect
+            if tokio::time::timeout(CLOSE_TIMEOUT, send.stopped()).await.is_err() {
+                debug!("Peer did not acknowledge the last messages in time");
+            }
         }
-        
+
         self.connection.close(0u32.into(), b"closing");
-        
+
         debug!("Closed QUIC transport");
-        
+
         Ok(())
     }
-    
+
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.wire = codec(format);
+    }
+
     fn is_connected(&self) -> bool {
-        !self.connection.close_reason().is_some()
+        self.connection.close_reason().is_none()
+    }
+}
+
+impl Drop for QuicTransport {
+    fn drop(&mut self) {
+        // The readers' handles on the connection would otherwise keep it open
+        for reader in &self.readers {
+            reader.abort();
+        }
+    }
+}
+
+/// Open or accept the control stream, depending on our side of the connection
+async fn control_stream(connection: &Connection, side: StreamSide) -> Result<(SendStream, RecvStream), TransportError> {
+    let (send, recv) = match side {
+        StreamSide::Open => connection.open_bi().await,
+        StreamSide::Accept => connection.accept_bi().await,
+    }
+    .map_err(connection_error)?;
+    send.set_priority(CONTROL_PRIORITY).ok();
+
+    debug!("{} bidirectional QUIC stream", match side {
+        StreamSide::Open => "Opened",
+        StreamSide::Accept => "Accepted",
+    });
+    Ok((send, recv))
+}
+
+/// Open a unidirectional stream and announce what it carries
+async fn open_uni(connection: &Connection, kind: u8, priority: i32) -> Result<SendStream, TransportError> {
+    let mut stream = connection.open_uni().await.map_err(connection_error)?;
+    stream.set_priority(priority).ok();
+    stream.write_all(&[kind]).await.map_err(|_| TransportError::Closed)?;
+    Ok(stream)
+}
+
+/// Read the peer's unidirectional streams, each on its own task, into the
+/// channel for what they carry
+async fn accept_uni_streams(
+    connection: Connection,
+    max_message_size: Arc<AtomicUsize>,
+    input: mpsc::Sender<Result<Bytes, TransportError>>,
+    frames: mpsc::Sender<Result<Bytes, TransportError>>,
+) {
+    while let Ok(stream) = connection.accept_uni().await {
+        let framing = MessageCodec::new(max_message_size.load(Ordering::Relaxed));
+        tokio::spawn(read_uni_stream(stream, framing, input.clone(), frames.clone()));
+    }
+}
+
+/// Read the peer's datagrams: pointer motion into `pointer`, replacing any
+/// older motion not received yet, the rest into `input`
+///
+/// Datagrams the receiver has no room for, and pointer motion older than the
+/// newest seen, are dropped; the next one replaces them anyway.
+async fn read_datagrams(
+    connection: Connection,
+    input: mpsc::Sender<Result<Bytes, TransportError>>,
+    pointer: watch::Sender<Option<Bytes>>,
+) {
+    let mut newest_move = 0;
+    while let Ok(datagram) = connection.read_datagram().await {
+        // Kept numbered, for `receive` to order against the input stream
+        let Some((sequence, _)) = parse_datagram(datagram.clone()) else {
+            debug!("Dropped datagram without a sequence number");
+            continue;
+        };
+        if sequence == 0 {
+            match input.try_send(Ok(datagram)) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => debug!("Dropped datagram: receiver is behind"),
+                Err(mpsc::error::TrySendError::Closed(_)) => return,
+            }
+        } else if sequence <= newest_move {
+            debug!("Dropped pointer motion {}: overtaken by {}", sequence, newest_move);
+        } else {
+            newest_move = sequence;
+            pointer.send_replace(Some(datagram));
+        }
+    }
+}
+
+/// Put a sequence number in front of a datagram's or input stream message;
+/// 0 for anything but pointer motion
+fn datagram(sequence: u64, data: &[u8]) -> Bytes {
+    let mut datagram = BytesMut::with_capacity(DATAGRAM_HEADER + data.len());
+    datagram.extend_from_slice(&sequence.to_be_bytes());
+    datagram.extend_from_slice(data);
+    datagram.freeze()
+}
+
+/// Split a datagram or input stream message into its sequence number and message
+fn parse_datagram(mut datagram: Bytes) -> Option<(u64, Bytes)> {
+    if datagram.len() < DATAGRAM_HEADER {
+        return None;
+    }
+    let header = datagram.split_to(DATAGRAM_HEADER);
+    Some((u64::from_be_bytes(header[..].try_into().ok()?), datagram))
+}
+
+async fn read_uni_stream(
+    mut stream: RecvStream,
+    framing: MessageCodec,
+    input: mpsc::Sender<Result<Bytes, TransportError>>,
+    frames: mpsc::Sender<Result<Bytes, TransportError>>,
+) {
+    let mut kind = [0u8; 1];
+    if stream.read_exact(&mut kind).await.is_err() {
+        return;
+    }
+    let incoming = match kind[0] {
+        INPUT_STREAM => input,
+        FRAME_STREAM => frames,
+        other => {
+            warn!("Ignoring unidirectional stream of unknown kind {}", other);
+            stream.stop(0u32.into()).ok();
+            return;
+        }
+    };
+
+    // A frame stream carries one message, the input stream all of them in order
+    let mut messages = FramedRead::new(stream, framing);
+    while let Some(data) = messages.next().await {
+        // The stream was reset or the connection lost; `receive` learns of
+        // the latter from the control stream
+        if let Err(TransportError::IoError(e)) = &data {
+            debug!("Unidirectional stream ended: {}", e);
+            return;
+        }
+        let failed = data.is_err();
+        if incoming.send(data).await.is_err() || failed || kind[0] == FRAME_STREAM {
+            if failed {
+                messages.into_inner().stop(0u32.into()).ok();
+            }
+            return;
+        }
+    }
+}
+
+/// Pointer motion received so far, to order datagrams against the input stream
+#[derive(Default)]
+struct MoveOrder {
+    /// Sequence number and message of the newest pointer motion received
+    newest: Option<(u64, Bytes)>,
+    /// Whether the click after an older repeated position has to be followed
+    /// by the newest one
+    behind: bool,
+    /// Newest pointer motion, to receive again now that the click was received
+    pending: Option<Bytes>,
+}
+
+impl MoveOrder {
+    /// Decode a message from the input stream, or a datagram other than pointer motion
+    ///
+    /// Positions repeated before a click are always received, as the click has to
+    /// land there; if the pointer was already at a newer one, that one follows
+    /// the click.
+    fn take_input(&mut self, wire: &dyn WireCodec, payload: Bytes, max_size: usize) -> Result<ProtocolMessage, TransportError> {
+        let (sequence, data) = parse_datagram(payload)
+            .ok_or_else(|| TransportError::ProtocolError("Input without a sequence number".to_string()))?;
+        let message = wire.decode(&data, max_size)?;
+        if sequence == 0 {
+            if self.behind && matches!(message, ProtocolMessage::InputEvent { .. }) {
+                self.behind = false;
+                self.pending = self.newest.as_ref().map(|(_, data)| data.clone());
+            }
+            return Ok(message);
+        }
+        match &self.newest {
+            Some((newest, _)) if sequence < *newest => self.behind = true,
+            _ => self.newest = Some((sequence, data)),
+        }
+        Ok(message)
+    }
+
+    /// Decode a pointer motion datagram, unless a newer position was received
+    fn take_pointer(&mut self, wire: &dyn WireCodec, payload: Bytes, max_size: usize) -> Result<Option<ProtocolMessage>, TransportError> {
+        let (sequence, data) = parse_datagram(payload)
+            .ok_or_else(|| TransportError::ProtocolError("Input without a sequence number".to_string()))?;
+        if self.newest.as_ref().is_some_and(|(newest, _)| sequence <= *newest) {
+            debug!("Dropped pointer motion {}: a newer one was received", sequence);
+            return Ok(None);
+        }
+        let message = wire.decode(&data, max_size)?;
+        self.newest = Some((sequence, data));
+        Ok(Some(message))
+    }
+}
+
+/// A peer closing the connection is a normal close, anything else a failure
+fn connection_error(e: quinn::ConnectionError) -> TransportError {
+    match e {
+        quinn::ConnectionError::ApplicationClosed(_) | quinn::ConnectionError::LocallyClosed => {
+            TransportError::Closed
+        }
+        quinn::ConnectionError::TimedOut => TransportError::Timeout,
+        e => TransportError::ConnectionFailed(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_core::domain::models::{FrameFormat, InputEvent, KeyCode, MouseButton, SessionId};
+    use crate::{QuicClient, QuicServer, ServerTrust};
+
+    async fn connected_pair() -> (QuicTransport, QuicTransport, QuicServer) {
+        let server = QuicServer::new("127.0.0.1:0".parse().unwrap()).unwrap();
+        let client = QuicClient::with_trust(ServerTrust::Insecure).unwrap();
+        let (connection, accepted) = tokio::join!(
+            client.connect(server.local_addr().unwrap()),
+            server.accept(),
+        );
+        let client_end = QuicTransport::new(connection.unwrap()).await.unwrap();
+        let server_end = QuicTransport::accept(accepted.unwrap()).await.unwrap();
+        (client_end, server_end, server)
+    }
+
+    fn frame(sequence: u64, size: usize) -> ProtocolMessage {
+        ProtocolMessage::ScreenFrame {
+            sequence,
+            timestamp: 0,
+            data: vec![0x5A; size],
+            width: 1920,
+            height: 1080,
+            format: FrameFormat::Jpeg,
+        }
+    }
+
+    fn input(event: InputEvent) -> ProtocolMessage {
+        ProtocolMessage::InputEvent { session_id: SessionId::new(), timestamp: 0, event }
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_overtakes_large_frame() {
+        let (mut client, mut server, _endpoint) = connected_pair().await;
+
+        client.send(frame(1, 4 * 1024 * 1024)).await.unwrap();
+        client.send(ProtocolMessage::Heartbeat { timestamp: 7 }).await.unwrap();
+
+        assert!(matches!(server.receive().await.unwrap(), ProtocolMessage::Heartbeat { timestamp: 7 }));
+        match server.receive().await.unwrap() {
+            ProtocolMessage::ScreenFrame { sequence, data, .. } => assert_eq!((sequence, data.len()), (1, 4 * 1024 * 1024)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_input_keeps_its_order_between_frames() {
+        let (mut client, mut server, _endpoint) = connected_pair().await;
+
+        // The server end sends too, once the client opened the control stream
+        client.send(ProtocolMessage::ListAgents).await.unwrap();
+        server.receive().await.unwrap();
+        for x in 0..20 {
+            server.send(input(InputEvent::MouseScroll { delta_x: x, delta_y: 0 })).await.unwrap();
+            server.send(frame(x as u64, 1024)).await.unwrap();
+        }
+
+        let (mut scrolls, mut frames) = (Vec::new(), 0);
+        while scrolls.len() < 20 || frames < 20 {
+            match client.receive().await.unwrap() {
+                ProtocolMessage::InputEvent { event: InputEvent::MouseScroll { delta_x, .. }, .. } => scrolls.push(delta_x),
+                ProtocolMessage::ScreenFrame { .. } => frames += 1,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(scrolls, (0..20).collect::<Vec<_>>());
+
+        client.close().await.unwrap();
+        assert!(server.receive().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_input_overtakes_frames_the_receiver_is_behind_on() {
+        let (mut client, mut server, _endpoint) = connected_pair().await;
+
+        // More frames than the receiver buffers, then a key press
+        for sequence in 0..2 * FRAMES_CAPACITY as u64 {
+            client.send(frame(sequence, 1024)).await.unwrap();
+        }
+        client.send(input(InputEvent::KeyPress { key: KeyCode::A, pressed: true })).await.unwrap();
+        while server.input.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(matches!(server.receive().await.unwrap(), ProtocolMessage::InputEvent { .. }));
+        for _ in 0..2 * FRAMES_CAPACITY {
+            assert!(matches!(server.receive().await.unwrap(), ProtocolMessage::ScreenFrame { .. }));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_messages_over_the_limit_are_refused() {
+        let (client, server, _endpoint) = connected_pair().await;
+        let mut server = server.with_max_message_size(64 * 1024);
+        let mut client = client.with_max_message_size(1024 * 1024);
+
+        // Reassembled from chunks, and refused once they add up to too much
+        client.send(frame(1, 60 * 1024)).await.unwrap();
+        assert!(matches!(server.receive().await.unwrap(), ProtocolMessage::ScreenFrame { sequence: 1, .. }));
+        client.send(frame(2, 200 * 1024)).await.unwrap();
+        assert!(matches!(server.receive().await, Err(TransportError::ProtocolError(_))));
+
+        client.send(ProtocolMessage::ListAgents).await.unwrap();
+        assert!(matches!(server.receive().await.unwrap(), ProtocolMessage::ListAgents));
+        assert!(matches!(
+            client.send(frame(3, 2 * 1024 * 1024)).await,
+            Err(TransportError::ProtocolError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pointer_motion_goes_as_datagrams() {
+        let (mut client, mut server, _endpoint) = connected_pair().await;
+        assert!(client.connection.max_datagram_size().is_some());
+
+        client.send(input(InputEvent::MouseMove { x: 10, y: 20 })).await.unwrap();
+        client.send(input(InputEvent::MouseButton { button: MouseButton::Left, pressed: true })).await.unwrap();
+        assert!(client.input_stream.is_some() && client.send_stream.is_none());
+
+        // The position may also arrive as a datagram, but it is repeated before the press
+        let mut events = Vec::new();
+        loop {
+            match server.receive().await.unwrap() {
+                ProtocolMessage::InputEvent { event, .. } => events.push(event),
+                other => panic!("unexpected {:?}", other),
+            }
+            if matches!(events.last(), Some(InputEvent::MouseButton { .. })) {
+                break;
+            }
+        }
+        let before_press = &events[events.len() - 2];
+        assert!(matches!(before_press, InputEvent::MouseMove { x: 10, y: 20 }), "{:?}", events);
+    }
+
+    #[tokio::test]
+    async fn test_stale_pointer_motion_is_dropped() {
+        let (client, mut server, _endpoint) = connected_pair().await;
+        let wire = codec(WireFormat::Bincode);
+        let encoded = |message: ProtocolMessage| wire.encode(&message).unwrap();
+
+        // The newer motion overtook the older one in flight
+        let newer = encoded(input(InputEvent::MouseMove { x: 5, y: 0 }));
+        let older = encoded(input(InputEvent::MouseMove { x: 3, y: 0 }));
+        client.connection.send_datagram(datagram(5, &newer)).unwrap();
+        client.connection.send_datagram(datagram(3, &older)).unwrap();
+        client.connection.send_datagram(datagram(0, &encoded(ProtocolMessage::Heartbeat { timestamp: 1 }))).unwrap();
+
+        let (mut moves, mut heartbeats) = (Vec::new(), 0);
+        while heartbeats == 0 || moves.is_empty() {
+            match server.receive().await.unwrap() {
+                ProtocolMessage::InputEvent { event: InputEvent::MouseMove { x, .. }, .. } => moves.push(x),
+                ProtocolMessage::Heartbeat { .. } => heartbeats += 1,
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(moves, [5]);
+        assert!(tokio::time::timeout(Duration::from_millis(100), server.receive()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_click_after_an_older_repeated_position_lands_there() {
+        let (mut client, mut server, _endpoint) = connected_pair().await;
+        let wire = codec(WireFormat::Bincode);
+        let encoded = |message: ProtocolMessage| wire.encode(&message).unwrap();
+
+        // The newer motion arrives as a datagram before the older one is
+        // repeated on the input stream, ahead of a click
+        let newer = encoded(input(InputEvent::MouseMove { x: 5, y: 0 }));
+        client.connection.send_datagram(datagram(5, &newer)).unwrap();
+        assert!(matches!(
+            server.receive().await.unwrap(),
+            ProtocolMessage::InputEvent { event: InputEvent::MouseMove { x: 5, .. }, .. }
+        ));
+        let older = client.input_frame(3, &encoded(input(InputEvent::MouseMove { x: 3, y: 0 }))).unwrap();
+        client.write_input(&older, false).await.unwrap();
+        let press = encoded(input(InputEvent::MouseButton { button: MouseButton::Left, pressed: true }));
+        let press = client.input_frame(0, &press).unwrap();
+        client.write_input(&press, false).await.unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            match server.receive().await.unwrap() {
+                ProtocolMessage::InputEvent { event, .. } => events.push(event),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(matches!(
+            events[..],
+            [InputEvent::MouseMove { x: 3, .. }, InputEvent::MouseButton { .. }, InputEvent::MouseMove { x: 5, .. }]
+        ), "{:?}", events);
+        assert!(tokio::time::timeout(Duration::from_millis(100), server.receive()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_receiver_behind_gets_only_the_newest_pointer_motion() {
+        let (mut client, mut server, _endpoint) = connected_pair().await;
+
+        for x in 0..100 {
+            client.send(input(InputEvent::MouseMove { x, y: 0 })).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(matches!(
+            server.receive().await.unwrap(),
+            ProtocolMessage::InputEvent { event: InputEvent::MouseMove { x: 99, .. }, .. }
+        ));
     }
 }
@@ -0,0 +1,166 @@
+//! QUIC transport parameters from config files
+//!
+//! Both ends apply their own [`QuicTuning`]; where QUIC negotiates a value,
+//! such as the idle timeout, the smaller one of the two wins.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use quinn::congestion::{BbrConfig, CubicConfig};
+use quinn::{IdleTimeout, TransportConfig, VarInt};
+use serde::{Deserialize, Serialize};
+
+use super::transport::MAX_UNI_STREAMS;
+
+/// Algorithm deciding how fast to send
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CongestionController {
+    /// Backs off on packet loss; the safe choice on shared links
+    #[default]
+    Cubic,
+    /// Paces by measured bandwidth and RTT, keeping queues short on lossy or
+    /// long links
+    Bbr,
+}
+
+/// Transport parameters as they appear in config files, the `quic` table
+///
+/// Durations are in milliseconds, sizes in bytes; a zero idle timeout,
+/// keep-alive interval or datagram receive buffer turns that feature off.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuicTuning {
+    /// Close the connection after this long without hearing from the peer
+    pub idle_timeout_ms: u64,
+    /// Send a keep-alive after this long without sending anything; keep it
+    /// well under the idle timeout
+    pub keep_alive_interval_ms: u64,
+    /// RTT assumed before the first measurement
+    pub initial_rtt_ms: u64,
+    /// Bytes the peer may send on one stream ahead of what was read
+    pub stream_receive_window: u64,
+    /// Bytes the peer may send on all streams together ahead of what was read
+    pub receive_window: u64,
+    pub congestion_controller: CongestionController,
+    /// Datagrams received but not yet read; 0 refuses datagrams, so the peer
+    /// sends pointer motion and heartbeats on streams
+    pub datagram_receive_buffer: usize,
+    /// Datagrams queued to send before the oldest ones are dropped
+    pub datagram_send_buffer: usize,
+}
+
+impl Default for QuicTuning {
+    fn default() -> Self {
+        Self {
+            idle_timeout_ms: 30_000,
+            keep_alive_interval_ms: 10_000,
+            initial_rtt_ms: 333,
+            stream_receive_window: 1_250_000,
+            receive_window: 16 * 1024 * 1024,
+            congestion_controller: CongestionController::Cubic,
+            datagram_receive_buffer: 1_250_000,
+            datagram_send_buffer: 1024 * 1024,
+        }
+    }
+}
+
+impl QuicTuning {
+    /// quinn transport configuration applying these settings
+    pub fn transport_config(&self) -> anyhow::Result<TransportConfig> {
+        let millis = |ms| (ms > 0).then(|| Duration::from_millis(ms));
+        let (idle_timeout, keep_alive) = (millis(self.idle_timeout_ms), millis(self.keep_alive_interval_ms));
+        if let (Some(idle_timeout), Some(keep_alive)) = (idle_timeout, keep_alive) {
+            anyhow::ensure!(
+                keep_alive < idle_timeout,
+                "keep_alive_interval_ms ({}) must be shorter than idle_timeout_ms ({})",
+                self.keep_alive_interval_ms,
+                self.idle_timeout_ms
+            );
+        }
+        anyhow::ensure!(self.initial_rtt_ms > 0, "initial_rtt_ms must be positive");
+
+        let mut config = TransportConfig::default();
+        config
+            .max_concurrent_uni_streams(MAX_UNI_STREAMS.into())
+            .max_idle_timeout(idle_timeout.map(IdleTimeout::try_from).transpose()?)
+            .keep_alive_interval(keep_alive)
+            .initial_rtt(Duration::from_millis(self.initial_rtt_ms))
+            .stream_receive_window(VarInt::from_u64(self.stream_receive_window)?)
+            .receive_window(VarInt::from_u64(self.receive_window)?)
+            .datagram_receive_buffer_size((self.datagram_receive_buffer > 0).then_some(self.datagram_receive_buffer))
+            .datagram_send_buffer_size(self.datagram_send_buffer);
+        match self.congestion_controller {
+            CongestionController::Cubic => config.congestion_controller_factory(Arc::new(CubicConfig::default())),
+            CongestionController::Bbr => config.congestion_controller_factory(Arc::new(BbrConfig::default())),
+        };
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{QuicClient, QuicServer, QuicTransport, ServerTrust};
+    use rd_core::domain::models::{InputEvent, MouseButton, SessionId};
+    use rd_core::domain::ports::{ProtocolMessage, Transport};
+
+    #[tokio::test]
+    async fn test_settings_are_checked() {
+        assert!(QuicTuning::default().transport_config().is_ok());
+
+        let tuning: QuicTuning = serde_json::from_str(r#"{ "congestion_controller": "bbr", "idle_timeout_ms": 0 }"#).unwrap();
+        assert_eq!(tuning.congestion_controller, CongestionController::Bbr);
+        assert_eq!(tuning.keep_alive_interval_ms, QuicTuning::default().keep_alive_interval_ms);
+        assert!(tuning.transport_config().is_ok());
+
+        let keep_alive_too_long = QuicTuning { keep_alive_interval_ms: 30_000, ..QuicTuning::default() };
+        assert!(keep_alive_too_long.transport_config().is_err());
+        assert!(QuicClient::with_trust(ServerTrust::Insecure).unwrap().with_tuning(&keep_alive_too_long).is_err());
+        let window_too_large = QuicTuning { receive_window: u64::MAX, ..QuicTuning::default() };
+        assert!(window_too_large.transport_config().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pointer_motion_falls_back_without_datagrams() {
+        let without_datagrams = QuicTuning { datagram_receive_buffer: 0, ..QuicTuning::default() };
+        let server = QuicServer::new("127.0.0.1:0".parse().unwrap())
+            .unwrap()
+            .with_tuning(&without_datagrams)
+            .unwrap();
+        let client = QuicClient::with_trust(ServerTrust::Insecure)
+            .unwrap()
+            .with_tuning(&QuicTuning { congestion_controller: CongestionController::Bbr, ..QuicTuning::default() })
+            .unwrap();
+        let (connection, accepted) = tokio::join!(client.connect(server.local_addr().unwrap()), server.accept());
+        let connection = connection.unwrap();
+        assert_eq!(connection.max_datagram_size(), None);
+
+        let mut client_end = QuicTransport::new(connection).await.unwrap();
+        let mut server_end = QuicTransport::accept(accepted.unwrap()).await.unwrap();
+        for x in 0..3 {
+            let event = InputEvent::MouseMove { x, y: 0 };
+            client_end.send(ProtocolMessage::InputEvent { session_id: SessionId::new(), timestamp: 0, event }).await.unwrap();
+        }
+        let event = InputEvent::MouseButton { button: MouseButton::Left, pressed: true };
+        client_end.send(ProtocolMessage::InputEvent { session_id: SessionId::new(), timestamp: 0, event }).await.unwrap();
+
+        // Every move arrives, in order, on the input stream
+        let mut received = Vec::new();
+        for _ in 0..4 {
+            match server_end.receive().await.unwrap() {
+                ProtocolMessage::InputEvent { event, .. } => received.push(event),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(matches!(
+            &received[..],
+            [
+                InputEvent::MouseMove { x: 0, .. },
+                InputEvent::MouseMove { x: 1, .. },
+                InputEvent::MouseMove { x: 2, .. },
+                InputEvent::MouseButton { .. },
+            ]
+        ));
+    }
+}
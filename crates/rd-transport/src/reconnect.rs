@@ -0,0 +1,90 @@
+//! Reconnecting to the server after the connection was lost
+//!
+//! Attempts wait longer after each failure, from one second doubling up to a
+//! minute. Each wait is shortened by a random part of up to half, so that
+//! devices cut off together, e.g. by a server restart, do not all come back at
+//! the same instant.
+
+use std::time::Duration;
+
+use rand::Rng;
+
+use rd_core::domain::error::{ApplicationError, TransportError};
+
+use crate::quic::TrustError;
+
+/// Delays between reconnection attempts
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    /// Start at `initial`, doubling up to `max`
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max, next: initial }
+    }
+
+    /// How long to wait before the next attempt
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        rand::thread_rng().gen_range(delay / 2..=delay)
+    }
+
+    /// Start over from the initial delay, once connected again
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+/// Whether trying again cannot fix `error`: the server refused our token or
+/// protocol version, or its certificate was not trusted
+pub fn is_permanent(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<TrustError>()
+            || matches!(cause.downcast_ref(), Some(TransportError::IncompatibleVersion(_)))
+            || matches!(
+                cause.downcast_ref(),
+                Some(ApplicationError::Auth(_) | ApplicationError::Transport(TransportError::IncompatibleVersion(_)))
+            )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rd_core::domain::error::AuthError;
+
+    #[test]
+    fn test_delays_double_up_to_the_maximum() {
+        let mut backoff = Backoff::default();
+        for expected in [1, 2, 4, 8, 16, 32, 60, 60] {
+            let delay = backoff.next_delay();
+            let expected = Duration::from_secs(expected);
+            assert!(delay >= expected / 2 && delay <= expected, "{:?} for {:?}", delay, expected);
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_refusals_are_permanent() {
+        assert!(is_permanent(&ApplicationError::Auth(AuthError::TokenExpired).into()));
+        assert!(is_permanent(&TransportError::IncompatibleVersion("v9".to_string()).into()));
+        assert!(is_permanent(&anyhow::Error::new(TrustError::InvalidFingerprint("AB".to_string())).context("connecting")));
+
+        assert!(!is_permanent(&TransportError::Timeout.into()));
+        assert!(!is_permanent(&ApplicationError::Transport(TransportError::Closed).into()));
+        assert!(!is_permanent(&anyhow::anyhow!("connection refused")));
+    }
+}
@@ -7,10 +7,11 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use rd_core::domain::{
     error::TransportError,
+    models::WireFormat,
     ports::{ProtocolMessage, Transport},
 };
 use tokio::sync::{mpsc, Mutex};
-use tracing::{debug, error, info};
+use tracing::info;
 use webrtc::{
     api::APIBuilder,
     data_channel::{data_channel_message::DataChannelMessage, RTCDataChannel},
@@ -24,14 +25,14 @@ use webrtc::{
 };
 
 use super::signaling::SignalingClient;
+use crate::protocol::{codec, WireCodec, DEFAULT_MAX_MESSAGE_SIZE};
 
 /// WebRTC-based P2P transport
 pub struct WebRTCTransport {
     peer_connection: Arc<RTCPeerConnection>,
     data_channel: Arc<RTCDataChannel>,
     rx: mpsc::Receiver<Vec<u8>>,
-    signaling: Arc<Mutex<SignalingClient>>,
-    remote_peer_id: String,
+    wire: &'static dyn WireCodec,
 }
 
 impl WebRTCTransport {
@@ -88,7 +89,7 @@ impl WebRTCTransport {
             Box::pin(async move {
                 if let Some(c) = candidate {
                     if let Ok(json) = c.to_json() {
-                        let mut sig = signaling.lock().await;
+                        let sig = signaling.lock().await;
                         let _ = sig.send_ice_candidate(
                             &peer_id,
                             &json.candidate,
@@ -105,7 +106,7 @@ impl WebRTCTransport {
         peer_connection.set_local_description(offer.clone()).await?;
         
         {
-            let mut sig = signaling.lock().await;
+            let sig = signaling.lock().await;
             sig.send_offer(remote_peer_id, &offer.sdp).await?;
         }
         
@@ -127,7 +128,7 @@ impl WebRTCTransport {
                         let ice = webrtc::ice_transport::ice_candidate::RTCIceCandidateInit {
                             candidate,
                             sdp_mid,
-                            sdp_mline_index: sdp_mline_index.map(|i| i as u16),
+                            sdp_mline_index,
                             ..Default::default()
                         };
                         drop(sig);
@@ -142,8 +143,7 @@ impl WebRTCTransport {
             peer_connection,
             data_channel,
             rx,
-            signaling,
-            remote_peer_id: remote_peer_id.to_string(),
+            wire: codec(WireFormat::Bincode),
         })
     }
     
@@ -203,33 +203,25 @@ impl WebRTCTransport {
         
         info!("Waiting for incoming offer...");
         
-        let mut remote_peer_id = String::new();
-        
         // Wait for offer from caller
-        loop {
+        let remote_peer_id = loop {
             let mut sig = signaling.lock().await;
-            if let Some(msg) = sig.recv().await {
-                match msg {
-                    super::signaling::SignalMessage::Offer { peer_id, sdp } => {
-                        info!("Received offer from {}", peer_id);
-                        remote_peer_id = peer_id.clone();
-                        
-                        let offer = RTCSessionDescription::offer(sdp)?;
-                        drop(sig);
-                        peer_connection.set_remote_description(offer).await?;
-                        
-                        // Create and send answer
-                        let answer = peer_connection.create_answer(None).await?;
-                        peer_connection.set_local_description(answer.clone()).await?;
-                        
-                        let mut sig = signaling.lock().await;
-                        sig.send_answer(&peer_id, &answer.sdp).await?;
-                        break;
-                    }
-                    _ => {}
-                }
+            if let Some(super::signaling::SignalMessage::Offer { peer_id, sdp }) = sig.recv().await {
+                info!("Received offer from {}", peer_id);
+                
+                let offer = RTCSessionDescription::offer(sdp)?;
+                drop(sig);
+                peer_connection.set_remote_description(offer).await?;
+                
+                // Create and send answer
+                let answer = peer_connection.create_answer(None).await?;
+                peer_connection.set_local_description(answer.clone()).await?;
+                
+                let sig = signaling.lock().await;
+                sig.send_answer(&peer_id, &answer.sdp).await?;
+                break peer_id;
             }
-        }
+        };
         
         // Handle ICE candidates
         let signaling_ice = signaling.clone();
@@ -240,7 +232,7 @@ impl WebRTCTransport {
             Box::pin(async move {
                 if let Some(c) = candidate {
                     if let Ok(json) = c.to_json() {
-                        let mut sig = signaling.lock().await;
+                        let sig = signaling.lock().await;
                         let _ = sig.send_ice_candidate(
                             &peer_id,
                             &json.candidate,
@@ -266,8 +258,7 @@ impl WebRTCTransport {
             peer_connection,
             data_channel,
             rx,
-            signaling,
-            remote_peer_id,
+            wire: codec(WireFormat::Bincode),
         })
     }
 }
@@ -275,8 +266,7 @@ impl WebRTCTransport {
 #[async_trait]
 impl Transport for WebRTCTransport {
     async fn send(&mut self, message: ProtocolMessage) -> Result<(), TransportError> {
-        let data = bincode::serialize(&message)
-            .map_err(|e| TransportError::SerializationError(format!("Serialize error: {}", e)))?;
+        let data = self.wire.encode(&message)?;
         
         self.data_channel.send(&bytes::Bytes::from(data)).await
             .map_err(|e| TransportError::ProtocolError(format!("Send error: {}", e)))?;
@@ -288,10 +278,7 @@ impl Transport for WebRTCTransport {
         let data = self.rx.recv().await
             .ok_or(TransportError::Closed)?;
         
-        let message: ProtocolMessage = bincode::deserialize(&data)
-            .map_err(|e| TransportError::SerializationError(format!("Deserialize error: {}", e)))?;
-        
-        Ok(message)
+        self.wire.decode(&data, DEFAULT_MAX_MESSAGE_SIZE)
     }
     
     async fn close(&mut self) -> Result<(), TransportError> {
@@ -300,6 +287,10 @@ impl Transport for WebRTCTransport {
         Ok(())
     }
     
+    fn set_wire_format(&mut self, format: WireFormat) {
+        self.wire = codec(format);
+    }
+
     fn is_connected(&self) -> bool {
         self.peer_connection.connection_state() == RTCPeerConnectionState::Connected
     }
@@ -0,0 +1,67 @@
+//! Feeds arbitrary bytes, split at arbitrary points, to `MessageCodec`
+//!
+//! Run with `cargo +nightly fuzz run framing` from the repository root. The
+//! round trip repeats the input past one chunk, so libFuzzer's default
+//! `-max_len` of 4096 still sends messages of several chunks.
+
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use tokio_util::codec::{Decoder, Encoder};
+
+use rd_core::domain::error::TransportError;
+use rd_transport::protocol::framing::{MessageCodec, MAX_CHUNK_SIZE};
+
+const MAX_MESSAGE_SIZE: usize = 3 * MAX_CHUNK_SIZE;
+
+fuzz_target!(|input: &[u8]| {
+    let Some((&step, data)) = input.split_first() else {
+        return;
+    };
+    let step = step as usize + 1;
+
+    // Whatever arrives, the decoder returns messages within the limit or an error
+    let mut codec = MessageCodec::new(MAX_MESSAGE_SIZE);
+    let mut src = BytesMut::new();
+    'read: for piece in data.chunks(step) {
+        src.extend_from_slice(piece);
+        loop {
+            match codec.decode(&mut src) {
+                Ok(Some(message)) => assert!(message.len() <= MAX_MESSAGE_SIZE),
+                Ok(None) => break,
+                Err(_) => break 'read,
+            }
+        }
+    }
+
+    // Messages over the limit are refused rather than sent
+    let mut codec = MessageCodec::new(MAX_MESSAGE_SIZE);
+    let mut framed = BytesMut::new();
+    if data.len() > MAX_MESSAGE_SIZE {
+        let refused = codec.encode(data, &mut framed);
+        assert!(matches!(refused, Err(TransportError::ProtocolError(_))));
+        return;
+    }
+
+    // Whatever is sent comes back the same, however it is split up
+    let data = if data.is_empty() {
+        Vec::new()
+    } else {
+        let copies = (MAX_CHUNK_SIZE / data.len() + 2).min(MAX_MESSAGE_SIZE / data.len());
+        data.repeat(copies)
+    };
+    let data = &data[..];
+    codec.encode(data, &mut framed).unwrap();
+    let mut src = BytesMut::new();
+    let mut decoded = None;
+    for piece in framed.chunks(step) {
+        src.extend_from_slice(piece);
+        if let Some(message) = codec.decode(&mut src).unwrap() {
+            assert!(decoded.is_none());
+            decoded = Some(message);
+        }
+    }
+    assert_eq!(decoded.as_deref(), Some(data));
+    assert!(src.is_empty() && !codec.is_partial());
+});